@@ -0,0 +1,554 @@
+use super::cli::DEFAULT_BAUD_RATE;
+use super::data::LogFormat;
+use super::log_monitor::MonitorMessage;
+use super::session_log;
+
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap};
+
+pub const COMMAND: &str = "analyze";
+const DEFAULT_BUCKET_SECONDS: i64 = 60;
+const TOP_GAPS: usize = 5;
+const TOP_REPEATED: usize = 10;
+/// Boot banners printed within this time after the first banner of a reboot, e.g.
+/// of the boot loader and the kernel, belong to the same reboot.
+const REBOOT_WINDOW: chrono::TimeDelta = chrono::TimeDelta::seconds(5);
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Lines printed by common boot loaders and kernels after a reset.
+const BOOT_PATTERNS: [&str; 7] = [
+    "rst:0x",
+    "U-Boot SPL ",
+    "U-Boot 20",
+    "Booting Linux",
+    "Linux version ",
+    "POWERON_RESET",
+    "*** Booting Zephyr",
+];
+const ERROR_PATTERNS: [&str; 5] = ["error", "fail", "fatal", "panic", "exception"];
+const WARNING_PATTERNS: [&str; 1] = ["warn"];
+
+#[derive(Debug, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+    Other,
+}
+
+fn classify(message: &str) -> Severity {
+    let message = message.to_lowercase();
+    if ERROR_PATTERNS.iter().any(|p| message.contains(p)) {
+        Severity::Error
+    } else if WARNING_PATTERNS.iter().any(|p| message.contains(p)) {
+        Severity::Warning
+    } else {
+        Severity::Other
+    }
+}
+
+fn is_boot_banner(message: &str) -> bool {
+    BOOT_PATTERNS.iter().any(|p| message.contains(p))
+}
+
+/// Replaces numbers, so messages differing only by counters or addresses are
+/// counted together.
+fn normalize(message: &str) -> String {
+    let mut normalized = String::new();
+    let mut chars = message.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            normalized.push(c);
+            continue;
+        }
+
+        // hex numbers, e.g. addresses, are replaced as a whole
+        let mut hex = chars.clone();
+        if c == '0'
+            && hex.next_if(|c| *c == 'x' || *c == 'X').is_some()
+            && hex.peek().is_some_and(|c| c.is_ascii_hexdigit())
+        {
+            chars = hex;
+            while chars.next_if(|c| c.is_ascii_hexdigit()).is_some() {}
+        } else {
+            while chars.next_if(|c| c.is_ascii_digit()).is_some() {}
+        }
+        normalized.push('#');
+    }
+
+    normalized
+}
+
+#[derive(Debug, Default)]
+struct SourceStats {
+    lines: usize,
+    errors: usize,
+    warnings: usize,
+    events: usize,
+    first: Option<chrono::DateTime<chrono::Local>>,
+    last: Option<chrono::DateTime<chrono::Local>>,
+    /// Time and banner of every detected reboot
+    reboots: Vec<(chrono::DateTime<chrono::Local>, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Gap {
+    source_name: String,
+    start: chrono::DateTime<chrono::Local>,
+    end: chrono::DateTime<chrono::Local>,
+}
+
+/// Statistics of the lines of one or more session logs.
+#[derive(Debug, Default)]
+struct Analysis {
+    files: usize,
+    skipped: usize,
+    sources: IndexMap<String, SourceStats>,
+    bucket_seconds: i64,
+    /// Lines of all sources per bucket, keyed by the start of the bucket
+    rate: BTreeMap<i64, usize>,
+    gaps: Vec<Gap>,
+    /// Occurrences of every normalized message
+    counts: HashMap<String, usize>,
+    repeated: Vec<(String, usize)>,
+}
+
+impl Analysis {
+    fn new(bucket_seconds: i64) -> Analysis {
+        Analysis {
+            bucket_seconds,
+            ..Default::default()
+        }
+    }
+
+    fn add_log(&mut self, log: session_log::SessionLog) {
+        self.files += 1;
+        self.skipped += log.skipped;
+        // gaps are only looked for within a file, not between sessions
+        let mut times: IndexMap<String, Vec<chrono::DateTime<chrono::Local>>> = IndexMap::new();
+        let mut buckets: Option<(i64, i64)> = None;
+
+        for msg in log.messages {
+            match msg {
+                MonitorMessage::Log(log) => {
+                    times
+                        .entry(log.source_name.clone())
+                        .or_default()
+                        .push(log.timestamp);
+                    let stats = self.sources.entry(log.source_name).or_default();
+                    stats.lines += 1;
+                    match classify(&log.message) {
+                        Severity::Error => stats.errors += 1,
+                        Severity::Warning => stats.warnings += 1,
+                        Severity::Other => {}
+                    }
+                    if is_boot_banner(&log.message) {
+                        stats.reboots.push((log.timestamp, log.message.clone()));
+                    }
+
+                    let second = log.timestamp.timestamp();
+                    let bucket = second - second.rem_euclid(self.bucket_seconds);
+                    *self.rate.entry(bucket).or_default() += 1;
+                    buckets = Some(match buckets {
+                        Some((first, last)) => (first.min(bucket), last.max(bucket)),
+                        None => (bucket, bucket),
+                    });
+                    *self.counts.entry(normalize(&log.message)).or_default() += 1;
+                }
+                MonitorMessage::Event(event) => {
+                    self.sources.entry(event.source_name).or_default().events += 1;
+                }
+                _ => {}
+            }
+        }
+
+        // buckets without any lines are part of the rate as well, but only within
+        // the file, logs of different days would add thousands of them otherwise
+        if let Some((first, last)) = buckets {
+            for bucket in (first..last).step_by(self.bucket_seconds as usize) {
+                self.rate.entry(bucket).or_default();
+            }
+        }
+
+        for (name, mut times) in times {
+            times.sort();
+            let stats = self.sources.get_mut(&name).unwrap();
+            let (first, last) = (times[0], times[times.len() - 1]);
+            stats.first = Some(stats.first.map_or(first, |t| t.min(first)));
+            stats.last = Some(stats.last.map_or(last, |t| t.max(last)));
+
+            for pair in times.windows(2) {
+                self.gaps.push(Gap {
+                    source_name: name.clone(),
+                    start: pair[0],
+                    end: pair[1],
+                });
+            }
+        }
+    }
+
+    /// Computes what needs all the lines, once every log was added.
+    fn finish(&mut self) {
+        for stats in self.sources.values_mut() {
+            stats.reboots.sort();
+            let mut last_reboot = None;
+            stats.reboots.retain(|(time, _)| {
+                let is_new = last_reboot.is_none_or(|last| *time - last > REBOOT_WINDOW);
+                if is_new {
+                    last_reboot = Some(*time);
+                }
+                is_new
+            });
+        }
+
+        self.gaps
+            .sort_by_key(|g| std::cmp::Reverse(g.end - g.start));
+        self.gaps.truncate(TOP_GAPS);
+
+        self.repeated = std::mem::take(&mut self.counts)
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect();
+        self.repeated
+            .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        self.repeated.truncate(TOP_REPEATED);
+    }
+}
+
+fn format_time(time: &chrono::DateTime<chrono::Local>) -> String {
+    time.format(TIME_FORMAT).to_string()
+}
+
+fn format_bucket(bucket: i64) -> String {
+    match chrono::DateTime::from_timestamp(bucket, 0) {
+        Some(time) => format_time(&time.with_timezone(&chrono::Local)),
+        None => bucket.to_string(),
+    }
+}
+
+fn format_seconds(delta: chrono::TimeDelta) -> String {
+    format!("{:.1} s", delta.num_milliseconds() as f64 / 1000.0)
+}
+
+fn to_text(analysis: &Analysis) -> String {
+    let total: usize = analysis.sources.values().map(|s| s.lines).sum();
+    let mut text = format!("Analyzed {} lines from {} files", total, analysis.files);
+    if analysis.skipped > 0 {
+        text += &format!(", {} unreadable lines skipped", analysis.skipped);
+    }
+    text += "\n";
+
+    text += "\nSources\n";
+    for (name, stats) in &analysis.sources {
+        text += &format!(
+            "  {}: {} lines, {} errors, {} warnings, {} events, {} reboots\n",
+            name,
+            stats.lines,
+            stats.errors,
+            stats.warnings,
+            stats.events,
+            stats.reboots.len()
+        );
+        if let (Some(first), Some(last)) = (&stats.first, &stats.last) {
+            text += &format!("    from {} to {}\n", format_time(first), format_time(last));
+        }
+    }
+
+    text += &format!("\nRate (lines per {} s)\n", analysis.bucket_seconds);
+    for (bucket, lines) in &analysis.rate {
+        text += &format!("  {}  {}\n", format_bucket(*bucket), lines);
+    }
+
+    text += "\nLongest silent gaps\n";
+    for gap in &analysis.gaps {
+        text += &format!(
+            "  {}: {} from {} to {}\n",
+            gap.source_name,
+            format_seconds(gap.end - gap.start),
+            format_time(&gap.start),
+            format_time(&gap.end)
+        );
+    }
+
+    text += "\nReboots\n";
+    for (name, stats) in &analysis.sources {
+        for (time, banner) in &stats.reboots {
+            text += &format!("  {} {}: {}\n", format_time(time), name, banner);
+        }
+    }
+
+    text += "\nTop repeated messages\n";
+    for (message, count) in &analysis.repeated {
+        text += &format!("  {:>6}x {}\n", count, message);
+    }
+
+    text
+}
+
+fn to_json(analysis: &Analysis) -> serde_json::Value {
+    let sources: serde_json::Map<String, serde_json::Value> = analysis
+        .sources
+        .iter()
+        .map(|(name, stats)| {
+            let stats = serde_json::json!({
+                "lines": stats.lines,
+                "errors": stats.errors,
+                "warnings": stats.warnings,
+                "events": stats.events,
+                "first": stats.first.map(|t| t.to_rfc3339()),
+                "last": stats.last.map(|t| t.to_rfc3339()),
+                "reboots": stats.reboots.iter().map(|(time, banner)| serde_json::json!({
+                    "timestamp": time.to_rfc3339(),
+                    "message": banner,
+                })).collect::<Vec<_>>(),
+            });
+            (name.clone(), stats)
+        })
+        .collect();
+
+    serde_json::json!({
+        "files": analysis.files,
+        "skipped": analysis.skipped,
+        "sources": sources,
+        "bucket_seconds": analysis.bucket_seconds,
+        "rate": analysis.rate.iter().map(|(bucket, lines)| serde_json::json!({
+            "start": chrono::DateTime::from_timestamp(*bucket, 0)
+                .map(|t| t.with_timezone(&chrono::Local).to_rfc3339()),
+            "lines": lines,
+        })).collect::<Vec<_>>(),
+        "gaps": analysis.gaps.iter().map(|gap| serde_json::json!({
+            "source": gap.source_name,
+            "start": gap.start.to_rfc3339(),
+            "end": gap.end.to_rfc3339(),
+            "seconds": (gap.end - gap.start).num_milliseconds() as f64 / 1000.0,
+        })).collect::<Vec<_>>(),
+        "repeated": analysis.repeated.iter().map(|(message, count)| serde_json::json!({
+            "message": message,
+            "count": count,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+struct AnalyzeArguments {
+    paths: Vec<String>,
+    json: bool,
+    bucket_seconds: i64,
+}
+
+/// parses the arguments following the analyze command:
+/// LOG_PATH... [--json] [--bucket SECONDS]
+fn parse_args(args: &[String]) -> Result<AnalyzeArguments, String> {
+    let mut arguments = AnalyzeArguments {
+        paths: Vec::new(),
+        json: false,
+        bucket_seconds: DEFAULT_BUCKET_SECONDS,
+    };
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--json" => arguments.json = true,
+            "--bucket" => {
+                let value = args_iter.next().ok_or("Missing value for --bucket")?;
+                arguments.bucket_seconds = match value.parse::<i64>() {
+                    Ok(seconds) if seconds > 0 => seconds,
+                    _ => return Err(format!("Invalid bucket length: {}", value)),
+                };
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => arguments.paths.push(arg.clone()),
+        }
+    }
+
+    if arguments.paths.is_empty() {
+        return Err("Missing the paths of the analyzed logs".to_string());
+    }
+    Ok(arguments)
+}
+
+/// Analyzes the session logs given as arguments and prints the report.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = parse_args(args)?;
+    let mut analysis = Analysis::new(arguments.bucket_seconds);
+
+    for path in &arguments.paths {
+        analysis.add_log(session_log::read(path, LogFormat::Auto, DEFAULT_BAUD_RATE)?);
+    }
+    analysis.finish();
+
+    if arguments.json {
+        println!("{:#}", to_json(&analysis));
+    } else {
+        print!("{}", to_text(&analysis));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::{Event, Log};
+
+    fn create_log(lines: &[(i64, &str, &str)]) -> session_log::SessionLog {
+        let start = chrono::DateTime::parse_from_rfc3339("2024-03-01T12:00:00+00:00")
+            .unwrap()
+            .with_timezone(&chrono::Local);
+        let mut messages: Vec<MonitorMessage> = lines
+            .iter()
+            .map(|(second, source_name, message)| {
+                MonitorMessage::Log(Log {
+                    source_name: source_name.to_string(),
+                    message: message.to_string(),
+                    timestamp: start + chrono::TimeDelta::seconds(*second),
+                })
+            })
+            .collect();
+        messages.push(MonitorMessage::Event(Event {
+            source_name: "a".to_string(),
+            description: "Port disconnected".to_string(),
+            timestamp: start,
+        }));
+        messages.push(MonitorMessage::UnsolictedMessage("boot".to_string()));
+
+        session_log::SessionLog {
+            messages,
+            skipped: 1,
+        }
+    }
+
+    #[test]
+    fn test_classify_and_normalize() {
+        assert_eq!(classify("E (123) wifi: connection FAILED"), Severity::Error);
+        assert_eq!(classify("W: low memory warning"), Severity::Warning);
+        assert_eq!(classify("I: ready"), Severity::Other);
+        assert_eq!(normalize("retry 12 of 0x1f"), "retry # of #");
+        assert_eq!(
+            normalize("Guru Meditation at 0x3ffb0000"),
+            normalize("Guru Meditation at 0x400d1a2c")
+        );
+        assert_eq!(normalize("0x, 0xg"), "#x, #xg");
+    }
+
+    #[test]
+    fn test_analysis() {
+        let mut analysis = Analysis::new(60);
+        analysis.add_log(create_log(&[
+            (0, "a", "rst:0x1 (POWERON_RESET),boot:0x13"),
+            (1, "a", "Booting Linux on physical CPU 0x0"),
+            (2, "b", "error: sensor 1 timeout"),
+            (50, "b", "error: sensor 2 timeout"),
+            (130, "a", "rst:0xc (SW_CPU_RESET),boot:0x13"),
+            (135, "a", "warning: brownout"),
+        ]));
+        analysis.finish();
+
+        assert_eq!(analysis.files, 1);
+        assert_eq!(analysis.skipped, 1);
+        let a = &analysis.sources["a"];
+        assert_eq!((a.lines, a.errors, a.warnings, a.events), (4, 0, 1, 1));
+        // the banners a second apart belong to the first reboot
+        assert_eq!(a.reboots.len(), 2);
+        let b = &analysis.sources["b"];
+        assert_eq!((b.lines, b.errors, b.events), (2, 2, 0));
+
+        assert_eq!(
+            analysis.rate.values().copied().collect::<Vec<_>>(),
+            [4, 0, 2]
+        );
+        assert_eq!(analysis.gaps[0].source_name, "a");
+        assert_eq!(
+            analysis.gaps[0].end - analysis.gaps[0].start,
+            chrono::TimeDelta::seconds(129)
+        );
+        assert_eq!(analysis.gaps[1].source_name, "b");
+        assert_eq!(
+            analysis.repeated,
+            [("error: sensor # timeout".to_string(), 2)]
+        );
+
+        let json = to_json(&analysis);
+        assert_eq!(json["sources"]["a"]["reboots"].as_array().unwrap().len(), 2);
+        assert_eq!(json["gaps"][0]["seconds"], 129.0);
+        assert_eq!(json["repeated"][0]["count"], 2);
+        let text = to_text(&analysis);
+        assert!(text.starts_with("Analyzed 6 lines from 1 files, 1 unreadable lines skipped\n"));
+        assert!(text.contains("  b: 2 lines, 2 errors, 0 warnings, 0 events, 0 reboots\n"));
+    }
+
+    #[test]
+    fn test_gaps_per_file() {
+        let mut analysis = Analysis::new(60);
+        analysis.add_log(create_log(&[(0, "a", "one"), (10, "a", "two")]));
+        analysis.add_log(create_log(&[(1000, "a", "three"), (1005, "a", "four")]));
+        analysis.finish();
+
+        // the time between the sessions is not a silent gap
+        assert_eq!(
+            analysis.gaps[0].end - analysis.gaps[0].start,
+            chrono::TimeDelta::seconds(10)
+        );
+        let a = &analysis.sources["a"];
+        assert_eq!(
+            a.last.unwrap() - a.first.unwrap(),
+            chrono::TimeDelta::seconds(1005)
+        );
+    }
+
+    #[test]
+    fn test_rate_within_files() {
+        let mut analysis = Analysis::new(60);
+        analysis.add_log(create_log(&[(0, "a", "one"), (130, "a", "two")]));
+        analysis.add_log(create_log(&[(86_400, "a", "next day")]));
+        analysis.finish();
+
+        // no empty buckets for the day between the files
+        assert_eq!(
+            analysis.rate.values().copied().collect::<Vec<_>>(),
+            [1, 0, 1, 1]
+        );
+    }
+
+    #[test]
+    fn test_boot_loop() {
+        let mut analysis = Analysis::new(60);
+        let lines: Vec<(i64, &str, &str)> = (0..10)
+            .flat_map(|i| {
+                [
+                    (i * 30, "a", "rst:0xc (RTC_WDT_RESET),boot:0x13"),
+                    (i * 30 + 1, "a", "*** Booting Zephyr OS build v3.5.0 ***"),
+                ]
+            })
+            .collect();
+        analysis.add_log(create_log(&lines));
+        analysis.finish();
+
+        let a = &analysis.sources["a"];
+        assert_eq!(a.reboots.len(), 10);
+        assert!(a
+            .reboots
+            .iter()
+            .all(|(_, banner)| banner.starts_with("rst:0xc")));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = ["a.txt", "--json", "b.txt", "--bucket", "10"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            parse_args(&args),
+            Ok(AnalyzeArguments {
+                paths: vec!["a.txt".to_string(), "b.txt".to_string()],
+                json: true,
+                bucket_seconds: 10,
+            })
+        );
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["--json".to_string()]).is_err());
+        assert!(parse_args(&["a.txt".to_string(), "--bucket".to_string()]).is_err());
+        assert!(parse_args(&["a.txt".to_string(), "--top".to_string()]).is_err());
+    }
+}
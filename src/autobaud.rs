@@ -0,0 +1,99 @@
+/// Baud rates tried when detecting the rate of an unknown port.
+pub const CANDIDATE_BAUD_RATES: &[u32] = &[
+    9_600, 19_200, 38_400, 57_600, 74_880, 115_200, 230_400, 460_800, 921_600,
+];
+
+/// Time spent listening at every candidate rate.
+pub const SAMPLE_TIME: u64 = 1000;
+/// Number of bytes after which listening at a rate finishes early.
+pub const SAMPLE_SIZE: usize = 256;
+/// Samples shorter than that are too small to judge the rate.
+const MIN_SAMPLE_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub struct Detection {
+    pub baud_rate: u32,
+    /// Between 0 and 1.
+    pub confidence: f64,
+}
+
+fn is_printable(byte: u8) -> bool {
+    (0x20..0x7f).contains(&byte) || byte == b'\r' || byte == b'\n' || byte == b'\t'
+}
+
+/// NUL and 0xFF bytes are what a receiver running at a wrong rate usually produces
+/// on framing errors, the port does not report the errors themselves.
+fn is_framing_error(byte: u8) -> bool {
+    byte == 0x00 || byte == 0xff
+}
+
+/// Scores data received at some rate, from 0 (garbage) to 1 (plain text).
+pub fn score_sample(sample: &[u8]) -> f64 {
+    if sample.len() < MIN_SAMPLE_SIZE {
+        return 0.0;
+    }
+
+    let printable = sample.iter().filter(|b| is_printable(**b)).count() as f64;
+    let framing_errors = sample.iter().filter(|b| is_framing_error(**b)).count() as f64;
+    let score = (printable - framing_errors) / sample.len() as f64;
+
+    score.max(0.0)
+}
+
+/// Picks the rate with the best score. The confidence is lowered when
+/// the runner-up scored almost as well.
+pub fn pick_best(scores: &[(u32, f64)]) -> Option<Detection> {
+    let mut sorted: Vec<&(u32, f64)> = scores.iter().filter(|(_, s)| *s > 0.0).collect();
+    sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let (baud_rate, best_score) = **sorted.first()?;
+    let runner_up_score = sorted.get(1).map(|(_, s)| *s).unwrap_or(0.0);
+
+    Some(Detection {
+        baud_rate,
+        confidence: best_score * (1.0 - runner_up_score / best_score / 2.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_plain_text() {
+        let sample = b"ets Jun  8 2016 00:22:57\r\nrst:0x1 (POWERON_RESET)\r\n";
+        assert_eq!(score_sample(sample), 1.0);
+    }
+
+    #[test]
+    fn test_score_garbage() {
+        let sample = [0x00, 0xff, 0x80, 0xfe, 0x00, 0x00, 0xff, 0x12].repeat(4);
+        assert_eq!(score_sample(&sample), 0.0);
+    }
+
+    #[test]
+    fn test_score_too_short() {
+        assert_eq!(score_sample(b"ok\r\n"), 0.0);
+    }
+
+    #[test]
+    fn test_pick_best() {
+        let scores = [(9_600, 0.1), (115_200, 1.0), (230_400, 0.2)];
+        let detection = pick_best(&scores).unwrap();
+        assert_eq!(detection.baud_rate, 115_200);
+        assert!((detection.confidence - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pick_best_ambiguous() {
+        let scores = [(57_600, 0.8), (115_200, 0.8)];
+        let detection = pick_best(&scores).unwrap();
+        assert!((detection.confidence - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pick_best_no_data() {
+        assert!(pick_best(&[(9_600, 0.0), (115_200, 0.0)]).is_none());
+        assert!(pick_best(&[]).is_none());
+    }
+}
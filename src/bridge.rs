@@ -0,0 +1,160 @@
+use super::log_monitor::{
+    send_event, AsyncLogMonitor, MonitorMessage, PortActivity, PortRequest, WriteProxy,
+};
+use super::serial_monitor::SerialLogMonitor;
+
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+/// One of the ports of a bridge: what it receives and how to write to it.
+pub struct BridgeEnd {
+    activity_receiver: broadcast::Receiver<PortActivity>,
+    write_proxy: WriteProxy,
+}
+
+impl BridgeEnd {
+    /// Subscribes to the port activity right away, so nothing received after the
+    /// monitor starts is missed.
+    pub fn new(monitor: &SerialLogMonitor) -> BridgeEnd {
+        BridgeEnd {
+            activity_receiver: monitor.get_activity_sender().subscribe(),
+            write_proxy: monitor.get_write_proxy(),
+        }
+    }
+}
+
+/// Forwards everything received on one port of a bridge to the other port.
+pub struct Forwarder {
+    /// Name of the direction, e.g. "/dev/ttyUSB0 -> /dev/ttyUSB1"
+    name: String,
+    activity_receiver: broadcast::Receiver<PortActivity>,
+    write_proxy: WriteProxy,
+}
+
+/// Wires two ports together, returning a forwarder for each direction.
+pub fn connect(
+    first: BridgeEnd,
+    second: BridgeEnd,
+    names: (String, String),
+) -> (Forwarder, Forwarder) {
+    let (first_name, second_name) = names;
+
+    (
+        Forwarder {
+            name: first_name,
+            activity_receiver: first.activity_receiver,
+            write_proxy: second.write_proxy,
+        },
+        Forwarder {
+            name: second_name,
+            activity_receiver: second.activity_receiver,
+            write_proxy: first.write_proxy,
+        },
+    )
+}
+
+impl Forwarder {
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.name.clone(), description, sender_queue);
+    }
+
+    pub async fn run(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        loop {
+            let activity = tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                activity = self.activity_receiver.recv() => activity,
+            };
+
+            match activity {
+                Ok(PortActivity::Received(data)) => {
+                    for byte in data {
+                        // the other monitor stopped, nothing to forward to
+                        if self.write_proxy.request(PortRequest::Write(byte)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(PortActivity::ModemLines(_)) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    let description = format!("Bridge is too slow, {} chunks dropped", n);
+                    self.send_event(description, &sender_queue);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_end() -> (
+        BridgeEnd,
+        broadcast::Sender<PortActivity>,
+        tokio::sync::mpsc::UnboundedReceiver<PortRequest>,
+    ) {
+        let (activity_sender, activity_receiver) = broadcast::channel(16);
+        let (request_sender, request_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let end = BridgeEnd {
+            activity_receiver,
+            write_proxy: WriteProxy::new(request_sender),
+        };
+        (end, activity_sender, request_receiver)
+    }
+
+    #[tokio::test]
+    async fn test_forward_both_directions() {
+        let (first, first_activity, mut first_requests) = create_end();
+        let (second, second_activity, mut second_requests) = create_end();
+        let (mut forward, mut backward) =
+            connect(first, second, ("a -> b".to_string(), "b -> a".to_string()));
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let handles = vec![
+            tokio::spawn({
+                let cancel_token = cancel_token.clone();
+                let sender = sender.clone();
+                async move { forward.run(cancel_token, sender).await }
+            }),
+            tokio::spawn({
+                let cancel_token = cancel_token.clone();
+                async move { backward.run(cancel_token, sender).await }
+            }),
+        ];
+
+        first_activity
+            .send(PortActivity::Received(b"AT".to_vec()))
+            .unwrap();
+        second_activity
+            .send(PortActivity::Received(b"OK".to_vec()))
+            .unwrap();
+        for byte in b"AT" {
+            assert_eq!(
+                second_requests.recv().await.unwrap(),
+                PortRequest::Write(*byte)
+            );
+        }
+        for byte in b"OK" {
+            assert_eq!(
+                first_requests.recv().await.unwrap(),
+                PortRequest::Write(*byte)
+            );
+        }
+
+        cancel_token.cancel();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+}
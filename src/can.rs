@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use std::fmt;
+
+const MAX_DATA_LENGTH: usize = 8;
+const STANDARD_ID_MAX: u32 = 0x7FF;
+const EXTENDED_ID_MAX: u32 = 0x1FFF_FFFF;
+const MAX_DECIMALS: usize = 6;
+/// Set in the message IDs of DBC files for extended frames
+const DBC_EXTENDED_FLAG: u32 = 0x8000_0000;
+
+/// Classic CAN frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanFrame {
+    pub id: u32,
+    pub extended: bool,
+    /// Remote transmission request, carries no data
+    pub remote: bool,
+    pub data: Vec<u8>,
+}
+
+impl CanFrame {
+    /// Parses frames in the notation of cansend: 123#DEADBEEF for standard frames,
+    /// 1F334455#11.22.33 for extended frames and 123#R for remote requests.
+    pub fn parse(text: &str) -> Result<CanFrame, String> {
+        let (id, data) = match text.split_once('#') {
+            Some(parts) => parts,
+            None => return Err(format!("Invalid CAN frame: {}", text)),
+        };
+
+        let extended = match id.len() {
+            3 => false,
+            8 => true,
+            _ => return Err(format!("Invalid CAN ID: {}", id)),
+        };
+        let id = match u32::from_str_radix(id, 16) {
+            Ok(id) if id <= STANDARD_ID_MAX || (extended && id <= EXTENDED_ID_MAX) => id,
+            _ => return Err(format!("Invalid CAN ID: {}", id)),
+        };
+
+        if data == "R" {
+            return Ok(CanFrame {
+                id,
+                extended,
+                remote: true,
+                data: Vec::new(),
+            });
+        }
+
+        let digits: Vec<char> = data.chars().filter(|c| *c != '.').collect();
+        if !digits.len().is_multiple_of(2) || digits.len() / 2 > MAX_DATA_LENGTH {
+            return Err(format!("Invalid CAN data: {}", data));
+        }
+        let data = digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("Invalid CAN data: {}", data))?;
+
+        Ok(CanFrame {
+            id,
+            extended,
+            remote: false,
+            data,
+        })
+    }
+
+    fn get_id_text(&self) -> String {
+        if self.extended {
+            format!("{:08X}", self.id)
+        } else {
+            format!("{:03X}", self.id)
+        }
+    }
+
+    /// The frame in the notation accepted by `parse`.
+    pub fn get_cansend_notation(&self) -> String {
+        let data = if self.remote {
+            "R".to_string()
+        } else {
+            self.data.iter().map(|b| format!("{:02X}", b)).collect()
+        };
+        format!("{}#{}", self.get_id_text(), data)
+    }
+}
+
+impl fmt::Display for CanFrame {
+    /// Formats the frame as ID [DLC] data, e.g. 123 [2] DE AD.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.get_id_text(), self.data.len())?;
+        if self.remote {
+            return write!(f, " remote request");
+        }
+        for byte in &self.data {
+            write!(f, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Multiplexing {
+    None,
+    /// Selects which of the multiplexed signals are present
+    Switch,
+    /// Present when the switch has the given value
+    Multiplexed(u64),
+}
+
+#[derive(Debug)]
+struct Signal {
+    name: String,
+    start_bit: usize,
+    length: usize,
+    /// Intel byte order, big-endian (Motorola) otherwise
+    little_endian: bool,
+    signed: bool,
+    factor: f64,
+    offset: f64,
+    unit: String,
+    multiplexing: Multiplexing,
+}
+
+impl Signal {
+    /// Parses the part of a signal definition after the colon,
+    /// e.g. 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+    fn parse(name: &str, multiplexing: Multiplexing, definition: &str) -> Option<Signal> {
+        let mut words = definition.split_whitespace();
+
+        let layout = words.next()?;
+        let (start_bit, rest) = layout.split_once('|')?;
+        let (length, rest) = rest.split_once('@')?;
+        let little_endian = match rest {
+            "1+" | "1-" => true,
+            "0+" | "0-" => false,
+            _ => return None,
+        };
+        let signed = rest.ends_with('-');
+
+        let scaling = words.next()?.strip_prefix('(')?.strip_suffix(')')?;
+        let (factor, offset) = scaling.split_once(',')?;
+
+        let unit = definition.split('"').nth(1).unwrap_or_default();
+
+        let length = length.parse().ok()?;
+        if length == 0 || length > 64 {
+            return None;
+        }
+
+        Some(Signal {
+            name: name.to_string(),
+            start_bit: start_bit.parse().ok()?,
+            length,
+            little_endian,
+            signed,
+            factor: factor.parse().ok()?,
+            offset: offset.parse().ok()?,
+            unit: unit.to_string(),
+            multiplexing,
+        })
+    }
+
+    /// Returns the raw value, or None if the signal does not fit into the data.
+    fn get_raw_value(&self, data: &[u8]) -> Option<u64> {
+        let get_bit = |position: usize| -> Option<u64> {
+            let byte = data.get(position / 8)?;
+            Some(((byte >> (position % 8)) & 1) as u64)
+        };
+
+        let mut value = 0u64;
+        if self.little_endian {
+            for i in 0..self.length {
+                value |= get_bit(self.start_bit + i)? << i;
+            }
+        } else {
+            // the start bit is the most significant one, following bits go down
+            // within a byte and continue at the top of the next byte
+            let mut position = self.start_bit;
+            for _ in 0..self.length {
+                value = (value << 1) | get_bit(position)?;
+                position = if position.is_multiple_of(8) {
+                    position + 15
+                } else {
+                    position - 1
+                };
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Formats the value with no more decimals than the factor has.
+    fn format_value(&self, raw: u64) -> String {
+        let decimals = match self.factor.to_string().split_once('.') {
+            Some((_, fraction)) => fraction.len().min(MAX_DECIMALS),
+            None => 0,
+        };
+        let text = format!("{:.*}", decimals, self.get_physical_value(raw));
+        if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            text
+        }
+    }
+
+    fn get_physical_value(&self, raw: u64) -> f64 {
+        let value = if self.signed && self.length < 64 && raw >> (self.length - 1) & 1 == 1 {
+            (raw | (u64::MAX << self.length)) as i64 as f64
+        } else if self.signed {
+            raw as i64 as f64
+        } else {
+            raw as f64
+        };
+        value * self.factor + self.offset
+    }
+}
+
+#[derive(Debug)]
+struct Message {
+    name: String,
+    signals: Vec<Signal>,
+}
+
+/// Message and signal definitions read from a DBC file, for decoding frames.
+#[derive(Debug)]
+pub struct Dbc {
+    /// Keyed by the ID as written in the file, with the extended flag
+    messages: HashMap<u32, Message>,
+}
+
+impl Dbc {
+    pub fn load(path: &str) -> Result<Dbc, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read DBC file {}: {}", path, e))?;
+        Dbc::parse(&text)
+    }
+
+    /// Parses the messages (BO_) and their signals (SG_), everything else is ignored.
+    pub fn parse(text: &str) -> Result<Dbc, String> {
+        let mut messages = HashMap::new();
+        let mut current_id = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if let Some(definition) = line.strip_prefix("BO_ ") {
+                let words: Vec<&str> = definition.split_whitespace().collect();
+                let (id, name) = match words.as_slice() {
+                    [id, name, ..] => (id.parse::<u32>().ok(), name.trim_end_matches(':')),
+                    _ => (None, ""),
+                };
+                let id = id.ok_or(format!("Invalid message at line {}: {}", i + 1, line))?;
+                messages.insert(
+                    id,
+                    Message {
+                        name: name.to_string(),
+                        signals: Vec::new(),
+                    },
+                );
+                current_id = Some(id);
+            } else if let Some(definition) = line.strip_prefix("SG_ ") {
+                let error = || format!("Invalid signal at line {}: {}", i + 1, line);
+                let message = current_id
+                    .and_then(|id| messages.get_mut(&id))
+                    .ok_or_else(error)?;
+                let (head, definition) = definition.split_once(':').ok_or_else(error)?;
+
+                let head: Vec<&str> = head.split_whitespace().collect();
+                let (name, multiplexing) = match head.as_slice() {
+                    [name] => (name, Multiplexing::None),
+                    [name, "M"] => (name, Multiplexing::Switch),
+                    [name, indicator] => match indicator.strip_prefix('m') {
+                        Some(value) => match value.parse() {
+                            Ok(value) => (name, Multiplexing::Multiplexed(value)),
+                            Err(_) => return Err(error()),
+                        },
+                        None => return Err(error()),
+                    },
+                    _ => return Err(error()),
+                };
+
+                let signal = Signal::parse(name, multiplexing, definition).ok_or_else(error)?;
+                message.signals.push(signal);
+            } else if !line.is_empty() {
+                // signals only follow their message directly
+                current_id = None;
+            }
+        }
+
+        Ok(Dbc { messages })
+    }
+
+    /// Describes the message and its signal values, if the frame is defined.
+    pub fn decode(&self, frame: &CanFrame) -> Option<String> {
+        if frame.remote {
+            return None;
+        }
+
+        let id = if frame.extended {
+            frame.id | DBC_EXTENDED_FLAG
+        } else {
+            frame.id
+        };
+        let message = self.messages.get(&id)?;
+
+        let switch = message
+            .signals
+            .iter()
+            .find(|s| s.multiplexing == Multiplexing::Switch)
+            .and_then(|s| s.get_raw_value(&frame.data));
+
+        let values: Vec<String> = message
+            .signals
+            .iter()
+            .filter(|s| match s.multiplexing {
+                Multiplexing::Multiplexed(value) => switch == Some(value),
+                _ => true,
+            })
+            .filter_map(|s| {
+                let value = s.format_value(s.get_raw_value(&frame.data)?);
+                Some(if s.unit.is_empty() {
+                    format!("{}={}", s.name, value)
+                } else {
+                    format!("{}={} {}", s.name, value, s.unit)
+                })
+            })
+            .collect();
+
+        Some(format!("{}: {}", message.name, values.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DBC: &str = r#"
+VERSION ""
+
+BU_: ECU
+
+BO_ 291 EngineData: 8 ECU
+ SG_ Speed : 0|16@1+ (0.5,0) [0|32767.5] "km/h" Vector__XXX
+ SG_ Temperature : 16|8@1- (1,-40) [-168|87] "degC" Vector__XXX
+ SG_ Status : 31|4@0+ (1,0) [0|15] "" Vector__XXX
+
+BO_ 2364540158 EEC1: 8 ECU
+ SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+
+BO_ 1024 Muxed: 2 ECU
+ SG_ Page M : 0|8@1+ (1,0) [0|255] "" Vector__XXX
+ SG_ Voltage m0 : 8|8@1+ (0.1,0) [0|25.5] "V" Vector__XXX
+ SG_ Current m1 : 8|8@1+ (1,0) [0|255] "mA" Vector__XXX
+
+CM_ SG_ 291 Speed "Vehicle speed";
+"#;
+
+    #[test]
+    fn test_parse_frames() {
+        assert_eq!(
+            CanFrame::parse("123#DEADBEEF").unwrap(),
+            CanFrame {
+                id: 0x123,
+                extended: false,
+                remote: false,
+                data: vec![0xDE, 0xAD, 0xBE, 0xEF],
+            }
+        );
+        assert_eq!(
+            CanFrame::parse("1F334455#11.22").unwrap(),
+            CanFrame {
+                id: 0x1F33_4455,
+                extended: true,
+                remote: false,
+                data: vec![0x11, 0x22],
+            }
+        );
+        assert!(CanFrame::parse("7FF#R").unwrap().remote);
+        assert!(CanFrame::parse("123#").unwrap().data.is_empty());
+
+        assert!(CanFrame::parse("123").is_err());
+        assert!(CanFrame::parse("12#00").is_err());
+        assert!(CanFrame::parse("800#00").is_err());
+        assert!(CanFrame::parse("20000000#00").is_err());
+        assert!(CanFrame::parse("123#0").is_err());
+        assert!(CanFrame::parse("123#XY").is_err());
+        assert!(CanFrame::parse("123#000102030405060708").is_err());
+    }
+
+    #[test]
+    fn test_format_frames() {
+        let frame = CanFrame::parse("123#DEAD").unwrap();
+        assert_eq!(frame.to_string(), "123 [2] DE AD");
+        assert_eq!(frame.get_cansend_notation(), "123#DEAD");
+
+        let frame = CanFrame::parse("0000ABCD#01").unwrap();
+        assert_eq!(frame.to_string(), "0000ABCD [1] 01");
+
+        let frame = CanFrame::parse("123#R").unwrap();
+        assert_eq!(frame.to_string(), "123 [0] remote request");
+        assert_eq!(frame.get_cansend_notation(), "123#R");
+    }
+
+    #[test]
+    fn test_decode_signals() {
+        let dbc = Dbc::parse(TEST_DBC).unwrap();
+
+        // speed 100 km/h, -10 degC, status 5 in the high nibble of byte 3
+        let frame = CanFrame::parse("123#C8001E50").unwrap();
+        assert_eq!(
+            dbc.decode(&frame).unwrap(),
+            "EngineData: Speed=100 km/h, Temperature=-10 degC, Status=5"
+        );
+
+        let frame = CanFrame::parse("0CF004FE#000000401F000000").unwrap();
+        assert_eq!(dbc.decode(&frame).unwrap(), "EEC1: EngineSpeed=1000 rpm");
+
+        // signals beyond the received data are left out
+        let frame = CanFrame::parse("123#C800").unwrap();
+        assert_eq!(dbc.decode(&frame).unwrap(), "EngineData: Speed=100 km/h");
+
+        assert!(dbc.decode(&CanFrame::parse("124#00").unwrap()).is_none());
+        assert!(dbc.decode(&CanFrame::parse("123#R").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_decode_big_endian_across_bytes() {
+        let dbc = Dbc::parse(
+            "BO_ 16 Counter: 2 ECU\n SG_ Value : 7|12@0+ (1,0) [0|4095] \"\" Vector__XXX\n",
+        )
+        .unwrap();
+        let frame = CanFrame::parse("010#ABC0").unwrap();
+        assert_eq!(dbc.decode(&frame).unwrap(), "Counter: Value=2748");
+    }
+
+    #[test]
+    fn test_decode_multiplexed_signals() {
+        let dbc = Dbc::parse(TEST_DBC).unwrap();
+        assert_eq!(
+            dbc.decode(&CanFrame::parse("400#0021").unwrap()).unwrap(),
+            "Muxed: Page=0, Voltage=3.3 V"
+        );
+        assert_eq!(
+            dbc.decode(&CanFrame::parse("400#0121").unwrap()).unwrap(),
+            "Muxed: Page=1, Current=33 mA"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_dbc() {
+        assert!(Dbc::parse(" SG_ Orphan : 0|8@1+ (1,0) [0|255] \"\" X").is_err());
+        assert!(Dbc::parse("BO_ x Name: 8 ECU").is_err());
+        assert!(Dbc::parse("BO_ 1 Name: 8 ECU\n SG_ Bad : 0|8@2+ (1,0) [0|1] \"\" X").is_err());
+        assert!(Dbc::parse("BO_ 1 Name: 8 ECU\n SG_ Bad : 0|0@1+ (1,0) [0|1] \"\" X").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use super::can::{CanFrame, Dbc};
+use super::data::CanSettings;
+use super::fd;
+use super::log_monitor::{
+    send_event, AsyncLogMonitor, Log, MonitorMessage, PortRequest, WriteProxy,
+};
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const RETRY_DELAY: u64 = 1000;
+
+/// Opens a raw CAN socket bound to the interface.
+fn open_socket(interface: &str) -> std::io::Result<AsyncFd<OwnedFd>> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let socket = unsafe {
+        let fd = fd::check(libc::socket(
+            libc::PF_CAN,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::CAN_RAW,
+        ))?;
+        OwnedFd::from_raw_fd(fd)
+    };
+
+    unsafe {
+        let mut address = std::mem::zeroed::<libc::sockaddr_can>();
+        address.can_family = libc::AF_CAN as libc::sa_family_t;
+        address.can_ifindex = index as libc::c_int;
+        fd::check(libc::bind(
+            socket.as_raw_fd(),
+            &address as *const libc::sockaddr_can as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+        ))?;
+    }
+
+    fd::register(socket)
+}
+
+fn to_raw_frame(frame: &CanFrame) -> libc::can_frame {
+    let mut raw = unsafe { std::mem::zeroed::<libc::can_frame>() };
+    raw.can_id = frame.id;
+    if frame.extended {
+        raw.can_id |= libc::CAN_EFF_FLAG;
+    }
+    if frame.remote {
+        raw.can_id |= libc::CAN_RTR_FLAG;
+    }
+    raw.can_dlc = frame.data.len() as u8;
+    raw.data[0..frame.data.len()].copy_from_slice(&frame.data);
+    raw
+}
+
+/// Returns None for error frames, which report bus errors instead of carrying data.
+fn from_raw_frame(raw: &libc::can_frame) -> Option<CanFrame> {
+    if raw.can_id & libc::CAN_ERR_FLAG != 0 {
+        return None;
+    }
+
+    let extended = raw.can_id & libc::CAN_EFF_FLAG != 0;
+    let remote = raw.can_id & libc::CAN_RTR_FLAG != 0;
+    let id = if extended {
+        raw.can_id & libc::CAN_EFF_MASK
+    } else {
+        raw.can_id & libc::CAN_SFF_MASK
+    };
+    let length = (raw.can_dlc as usize).min(raw.data.len());
+    let data = if remote {
+        Vec::new()
+    } else {
+        raw.data[0..length].to_vec()
+    };
+
+    Some(CanFrame {
+        id,
+        extended,
+        remote,
+        data,
+    })
+}
+
+async fn read_frame(socket: &AsyncFd<OwnedFd>) -> std::io::Result<libc::can_frame> {
+    let mut raw = unsafe { std::mem::zeroed::<libc::can_frame>() };
+    let buffer = unsafe {
+        std::slice::from_raw_parts_mut(
+            &mut raw as *mut libc::can_frame as *mut u8,
+            std::mem::size_of::<libc::can_frame>(),
+        )
+    };
+    fd::read_fd(socket, buffer).await?;
+    Ok(raw)
+}
+
+async fn write_frame(socket: &AsyncFd<OwnedFd>, frame: &CanFrame) -> std::io::Result<()> {
+    let raw = to_raw_frame(frame);
+    let data = unsafe {
+        std::slice::from_raw_parts(
+            &raw as *const libc::can_frame as *const u8,
+            std::mem::size_of::<libc::can_frame>(),
+        )
+    };
+    fd::write_fd(socket, data).await?;
+    Ok(())
+}
+
+/// Logs the frames seen on a SocketCAN interface, one line per frame, with the
+/// signals decoded when a DBC file is given. Frames are sent with the can command.
+pub struct CanLogMonitor {
+    settings: CanSettings,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    dbc: Option<Dbc>,
+    input_dropped: bool,
+}
+
+impl CanLogMonitor {
+    pub fn new(settings: CanSettings) -> CanLogMonitor {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+
+        CanLogMonitor {
+            settings,
+            write_receiver,
+            write_sender,
+            dbc: None,
+            input_dropped: false,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    fn format_frame(&self, frame: &CanFrame) -> String {
+        match self.dbc.as_ref().and_then(|dbc| dbc.decode(frame)) {
+            Some(signals) => format!("{} {}", frame, signals),
+            None => frame.to_string(),
+        }
+    }
+
+    fn handle_frame(&self, raw: &libc::can_frame, sender_queue: &UnboundedSender<MonitorMessage>) {
+        let message = match from_raw_frame(raw) {
+            Some(frame) => self.format_frame(&frame),
+            None => format!("error frame {:08X}", raw.can_id & libc::CAN_ERR_MASK),
+        };
+        let log = Log {
+            source_name: self.get_common_name(),
+            message,
+            timestamp: chrono::Local::now(),
+        };
+        sender_queue.send(MonitorMessage::Log(log)).unwrap();
+    }
+
+    async fn handle_write_request(
+        &mut self,
+        socket: &AsyncFd<OwnedFd>,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        let description = match request {
+            PortRequest::SendFrame(frame) => match write_frame(socket, &frame).await {
+                Ok(_) => format!("TX {}", self.format_frame(&frame)),
+                Err(e) => format!("Failed to send {}: {}", frame, e),
+            },
+            // typing would send a frame per key, frames are sent as a whole instead
+            PortRequest::Write(_) => {
+                if self.input_dropped {
+                    return;
+                }
+                self.input_dropped = true;
+                "Typed input is not sent over CAN, use the can command".to_string()
+            }
+            request => request.unsupported_description("for CAN"),
+        };
+        self.send_event(description, sender_queue);
+    }
+}
+
+impl AsyncLogMonitor for CanLogMonitor {
+    fn get_common_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        if let Some(path) = &self.settings.dbc {
+            // without the signal definitions, the frames are still logged
+            match Dbc::load(path) {
+                Ok(dbc) => self.dbc = Some(dbc),
+                Err(e) => self.send_event(e, &sender_queue),
+            }
+        }
+
+        let socket = match open_socket(&self.settings.interface) {
+            Ok(s) => s,
+            Err(e) => {
+                let description = format!("Failed to open {}: {}", self.settings.interface, e);
+                self.send_event(description, &sender_queue);
+                return;
+            }
+        };
+
+        let mut failing = false;
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if let Some(request) = request {
+                        self.handle_write_request(&socket, request, &sender_queue).await;
+                    }
+                }
+
+                read_result = read_frame(&socket) => match read_result {
+                    Ok(raw) => {
+                        failing = false;
+                        self.handle_frame(&raw, &sender_queue);
+                    }
+                    // e.g. the interface is down, reported once until it recovers
+                    Err(e) => {
+                        if !failing {
+                            failing = true;
+                            self.send_event(format!("Failed to read: {}", e), &sender_queue);
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(RETRY_DELAY)).await;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_frame_conversion() {
+        for text in ["123#DEADBEEF", "1F334455#", "7FF#R"] {
+            let frame = CanFrame::parse(text).unwrap();
+            assert_eq!(from_raw_frame(&to_raw_frame(&frame)).unwrap(), frame);
+        }
+
+        let mut raw = to_raw_frame(&CanFrame::parse("123#00").unwrap());
+        raw.can_id |= libc::CAN_ERR_FLAG;
+        assert!(from_raw_frame(&raw).is_none());
+    }
+
+    /// Needs a virtual CAN interface:
+    /// modprobe vcan && ip link add vcan0 type vcan && ip link set up vcan0
+    #[tokio::test]
+    #[ignore = "needs the vcan0 interface"]
+    async fn test_vcan_receive_and_send() {
+        let mut monitor = CanLogMonitor::new(CanSettings {
+            interface: "vcan0".to_string(),
+            dbc: None,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        // frames sent by the monitor are not received back on its own socket
+        let peer = open_socket("vcan0").unwrap();
+        let frame = CanFrame::parse("123#0102").unwrap();
+        write_frame(&peer, &frame).await.unwrap();
+        match receiver.recv().await.unwrap() {
+            MonitorMessage::Log(log) => assert_eq!(log.message, "123 [2] 01 02"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        let frame = CanFrame::parse("0000ABCD#FF").unwrap();
+        write_proxy
+            .request(PortRequest::SendFrame(frame.clone()))
+            .unwrap();
+        let raw = read_frame(&peer).await.unwrap();
+        assert_eq!(from_raw_frame(&raw).unwrap(), frame);
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+}
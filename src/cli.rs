@@ -1,39 +1,145 @@
+use crate::bridge;
+use crate::can_monitor;
+use crate::command;
+use crate::config;
 use crate::data;
+use crate::file_monitor;
+use crate::hooks;
+use crate::keymap;
 use crate::log_monitor;
 use crate::logging;
+use crate::process_monitor;
+use crate::pty;
+use crate::publisher;
+use crate::replay_monitor;
+use crate::script;
+use crate::sequence;
 use crate::serial_monitor;
+use crate::server;
+use crate::status_bar;
+use crate::tcp_monitor;
+use crate::trigger;
+use crate::udp_monitor;
+use crate::unix_monitor;
 use crate::writer;
 
 use log_monitor::AsyncLogMonitor;
 use tokio_util::sync::CancellationToken;
 
 const DEFAULT_SERIAL_PORT: &str = "/dev/ttyUSB0";
-const DEFAULT_BAUD_RATE: u32 = 115_200;
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+const AUTOBAUD_ARG: &str = "auto";
+const TCP_SCHEME: &str = "tcp://";
+const RFC2217_SCHEME: &str = "rfc2217://";
+const UDP_SCHEME: &str = "udp://";
+const PROCESS_PREFIX: &str = "cmd:";
+const FILE_PREFIX: &str = "file:";
+const CAN_PREFIX: &str = "can:";
+const UNIX_PREFIX: &str = "unix:";
+const REPLAY_COMMAND: &str = "replay";
+const DEFAULT_REPLAY_SPEED: f64 = 1.0;
 
 pub struct DrFishCli {
-    pub port_configuration: Vec<data::SerialPortSettings>,
+    pub port_configuration: Vec<data::SourceSettings>,
+    pub groups: Vec<data::PortGroup>,
+    pub aliases: Vec<data::PortAlias>,
+    pub read_only_ports: Vec<String>,
+    pub servers: Vec<data::PortServerSettings>,
+    pub ptys: Vec<data::PtySettings>,
+    pub bridges: Vec<data::BridgeSettings>,
+    pub publish_path: Option<String>,
     pub logger: logging::Logger,
+    pub status_bar: status_bar::StatusBar,
 
     writer: writer::Writer,
+    keymap: keymap::Keymap,
+    sequences: sequence::Sequences,
+    triggers: trigger::Triggers,
+    hooks: hooks::Hooks,
+    /// Ticks when the hook scripts are checked for changes
+    hooks_reload_timer: tokio::time::Interval,
+    /// Messages passed through the hooks, not handled yet
+    hooked_messages: std::collections::VecDeque<log_monitor::MonitorMessage>,
+    /// Script run against the ports, with its path
+    script: Option<(String, script::Script)>,
+    /// Set while the script runs, forwards the received messages to it
+    script_sender: Option<tokio::sync::mpsc::UnboundedSender<log_monitor::MonitorMessage>>,
+    /// Exit code of the script, set while it runs
+    script_result: Option<tokio::sync::oneshot::Receiver<i32>>,
+    /// Set while the messages are published for local clients
+    published_lines: Option<tokio::sync::broadcast::Sender<String>>,
     sender: tokio::sync::mpsc::UnboundedSender<log_monitor::MonitorMessage>,
     receiver: tokio::sync::mpsc::UnboundedReceiver<log_monitor::MonitorMessage>,
     cancel_signal: tokio_util::sync::CancellationToken,
     handles: Vec<tokio::task::JoinHandle<()>>,
+    input_mode: InputMode,
 }
 
 pub enum CliAction {
     Break,
+    /// End the session and exit with the code
+    Exit(i32),
+}
+
+/// How the next key press is interpreted.
+enum InputMode {
+    Normal,
+    /// The prefix key was pressed, the next key selects an action.
+    Prefix,
+    /// A line of text is being typed into a prompt.
+    Prompt(Prompt, String),
+}
+
+#[derive(Clone, Copy)]
+enum Prompt {
+    /// Writer name or alias
+    Writer,
+    Command,
+}
+
+impl Prompt {
+    fn get_name(&self) -> &'static str {
+        match self {
+            Prompt::Writer => "select",
+            Prompt::Command => "command",
+        }
+    }
+}
+
+pub struct Arguments {
+    pub ports: Vec<data::SourceSettings>,
+    pub groups: Vec<data::PortGroup>,
+    pub aliases: Vec<data::PortAlias>,
+    pub read_only_ports: Vec<String>,
+    pub servers: Vec<data::PortServerSettings>,
+    pub ptys: Vec<data::PtySettings>,
+    pub bridges: Vec<data::BridgeSettings>,
+    pub publish_path: Option<String>,
+    pub config_path: Option<String>,
+    pub script_path: Option<String>,
 }
 
 impl DrFishCli {
     pub fn new() -> Result<DrFishCli, String> {
-        let port_configuration = match get_port_configuration() {
-            Ok(cfg) => cfg,
+        let arguments = match get_arguments() {
+            Ok(args) => args,
             Err(e) => {
                 return Err(e);
             }
         };
 
+        let config = config::Config::load(arguments.config_path.as_deref())?;
+        let keymap = keymap::Keymap::new(&config.keymap)?;
+        let sequences = sequence::Sequences::new(&config.sequences)?;
+        let triggers = trigger::Triggers::new(&config.triggers)?;
+        let hooks = hooks::Hooks::new(&config.hooks)?;
+        let mut hooks_reload_timer = tokio::time::interval(hooks::RELOAD_INTERVAL);
+        hooks_reload_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let script = match arguments.script_path {
+            Some(path) => Some((path.clone(), script::Script::load(&path)?)),
+            None => None,
+        };
+
         let logger = match logging::Logger::new() {
             Ok(l) => l,
             Err(e) => {
@@ -48,33 +154,288 @@ impl DrFishCli {
         let handles = Vec::new();
 
         Ok(DrFishCli {
-            port_configuration: port_configuration,
-            writer: writer,
-            sender: sender,
-            receiver: receiver,
-            cancel_signal: cancel_signal,
-            handles: handles,
-            logger: logger,
+            port_configuration: arguments.ports,
+            groups: arguments.groups,
+            aliases: arguments.aliases,
+            read_only_ports: arguments.read_only_ports,
+            servers: arguments.servers,
+            ptys: arguments.ptys,
+            bridges: arguments.bridges,
+            publish_path: arguments.publish_path,
+            published_lines: None,
+            status_bar: status_bar::StatusBar::new(),
+            writer,
+            keymap,
+            sequences,
+            triggers,
+            hooks,
+            hooks_reload_timer,
+            hooked_messages: std::collections::VecDeque::new(),
+            script,
+            script_sender: None,
+            script_result: None,
+            sender,
+            receiver,
+            cancel_signal,
+            handles,
+            logger,
+            input_mode: InputMode::Normal,
         })
     }
 
     pub fn spawn_monitors(&mut self) {
-        for port in &self.port_configuration {
+        let mut bridge_ends = std::collections::HashMap::new();
+
+        for source in self.port_configuration.clone() {
+            match source {
+                data::SourceSettings::Serial(port) => {
+                    let mut port_monitor = serial_monitor::SerialLogMonitor::new(port).unwrap();
+                    let name = port_monitor.get_common_name();
+                    if let Some(bridge) = self.bridges.iter().find(|b| b.contains(&name)) {
+                        port_monitor.set_log_name(bridge.get_direction_name(&name));
+                        bridge_ends.insert(name, bridge::BridgeEnd::new(&port_monitor));
+                    }
+                    self.spawn_servers(&port_monitor);
+                    self.spawn_interposers(&port_monitor);
+                    self.spawn_monitor(port_monitor);
+                }
+                data::SourceSettings::Tcp(settings) => {
+                    self.spawn_monitor(tcp_monitor::TcpLogMonitor::new(settings));
+                }
+                data::SourceSettings::Udp(settings) => {
+                    self.spawn_monitor(udp_monitor::UdpLogMonitor::new(settings));
+                }
+                data::SourceSettings::Process(settings) => {
+                    self.spawn_monitor(process_monitor::ProcessLogMonitor::new(settings));
+                }
+                data::SourceSettings::File(settings) => {
+                    self.spawn_monitor(file_monitor::FileLogMonitor::new(settings));
+                }
+                data::SourceSettings::Can(settings) => {
+                    self.spawn_monitor(can_monitor::CanLogMonitor::new(settings));
+                }
+                data::SourceSettings::Unix(settings) => {
+                    self.spawn_monitor(unix_monitor::UnixLogMonitor::new(settings));
+                }
+                data::SourceSettings::Replay(settings) => {
+                    self.spawn_monitor(replay_monitor::ReplayLogMonitor::new(settings));
+                }
+            }
+        }
+        self.spawn_bridges(bridge_ends);
+        self.spawn_publisher();
+
+        for group in &self.groups {
+            // members are validated while parsing the arguments
+            self.writer
+                .add_group(group.name.clone(), group.ports.clone())
+                .unwrap();
+        }
+        for alias in &self.aliases {
+            if self.accepts_input(&alias.port) {
+                self.writer
+                    .add_alias(alias.name.clone(), alias.port.clone())
+                    .unwrap();
+            }
+        }
+        self.update_input_status();
+        self.spawn_script();
+    }
+
+    /// Runs the script given on the command line, once the ports are available.
+    fn spawn_script(&mut self) {
+        let (path, mut script) = match self.script.take() {
+            Some(script) => script,
+            None => return,
+        };
+
+        let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
+        self.script_result = Some(result_receiver);
+        let event = |description: String| {
+            log_monitor::MonitorMessage::Event(log_monitor::Event {
+                source_name: format!("script:{}", path),
+                description,
+                timestamp: chrono::Local::now(),
+            })
+        };
+        if let Err(e) = script.expand_commands(|c| self.expand_command(c)) {
+            self.sender.send(event(e)).unwrap();
+            let _ = result_sender.send(script::FAILURE_EXIT_CODE);
+            return;
+        }
+
+        let mut targets = std::collections::HashMap::new();
+        for target in script.get_targets() {
+            let write_proxies = match &target {
+                Some(name) => match self.writer.get_write_proxies_by_name(name) {
+                    Ok(write_proxies) => write_proxies,
+                    Err(e) => {
+                        self.sender.send(event(e)).unwrap();
+                        let _ = result_sender.send(script::FAILURE_EXIT_CODE);
+                        return;
+                    }
+                },
+                None => self.writer.get_target_write_proxies(),
+            };
+            targets.insert(target, write_proxies);
+        }
+
+        let (message_sender, message_receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.script_sender = Some(message_sender);
+        let runner = script::ScriptRunner::new(
+            format!("script:{}", path),
+            script,
+            targets,
+            message_receiver,
+            self.sender.clone(),
+        );
+        let cancel_signal_clone = self.cancel_signal.clone();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                code = runner.run() => {
+                    let _ = result_sender.send(code);
+                }
+                _ = cancel_signal_clone.cancelled() => {}
+            }
+        });
+        self.handles.push(handle);
+    }
+
+    /// Receiver of the exit code of the script, if one runs.
+    pub fn take_script_result(&mut self) -> Option<tokio::sync::oneshot::Receiver<i32>> {
+        self.script_result.take()
+    }
+
+    fn spawn_monitor<M: AsyncLogMonitor + Send + 'static>(&mut self, mut monitor: M) {
+        let cancel_signal_clone = self.cancel_signal.clone();
+        let sender_clone = self.sender.clone();
+        let name = monitor.get_common_name();
+        if self.accepts_input(&name) {
+            self.writer.add_write_proxy(name, monitor.get_write_proxy());
+        }
+
+        let handle = tokio::spawn(async move {
+            monitor.monitor(cancel_signal_clone, sender_clone).await;
+        });
+        self.handles.push(handle);
+    }
+
+    /// Whether the keyboard can write to the port. Bridged ports only receive
+    /// what the other port of the bridge receives.
+    fn accepts_input(&self, name: &str) -> bool {
+        !self.read_only_ports.iter().any(|p| p == name)
+            && !self.bridges.iter().any(|b| b.contains(name))
+    }
+
+    /// Shares the port on all addresses it should be served on.
+    fn spawn_servers(&mut self, monitor: &serial_monitor::SerialLogMonitor) {
+        let name = monitor.get_common_name();
+        let write_proxy = self.get_shared_write_proxy(monitor);
+
+        for settings in self.servers.iter().filter(|s| s.port == name) {
+            let mut port_server = server::PortServer::new(
+                settings.clone(),
+                monitor.get_baud_rate(),
+                monitor.get_activity_sender(),
+                write_proxy.clone(),
+            );
             let cancel_signal_clone = self.cancel_signal.clone();
             let sender_clone = self.sender.clone();
-            let mut port_monitor = serial_monitor::SerialLogMonitor::new(port.clone()).unwrap();
-            let write_proxy = port_monitor.get_write_proxy();
-            self.writer.add_write_proxy(port.path.clone(), write_proxy);
+            let handle = tokio::spawn(async move {
+                port_server.run(cancel_signal_clone, sender_clone).await;
+            });
+            self.handles.push(handle);
+        }
+    }
+
+    /// Relays the port through all pseudo-terminals configured for it.
+    fn spawn_interposers(&mut self, monitor: &serial_monitor::SerialLogMonitor) {
+        let name = monitor.get_common_name();
+        let write_proxy = self.get_shared_write_proxy(monitor);
 
+        for settings in self.ptys.iter().filter(|p| p.port == name) {
+            let mut interposer = pty::PtyInterposer::new(
+                settings.clone(),
+                monitor.get_activity_sender(),
+                write_proxy.clone(),
+            );
+            let cancel_signal_clone = self.cancel_signal.clone();
+            let sender_clone = self.sender.clone();
             let handle = tokio::spawn(async move {
-                port_monitor
-                    .monitor(cancel_signal_clone, sender_clone)
-                    .await;
+                interposer.run(cancel_signal_clone, sender_clone).await;
             });
             self.handles.push(handle);
         }
     }
 
+    /// Forwards the data between the ports of every bridge.
+    fn spawn_bridges(
+        &mut self,
+        mut bridge_ends: std::collections::HashMap<String, bridge::BridgeEnd>,
+    ) {
+        for settings in &self.bridges {
+            // both ports are validated while parsing the arguments
+            let first = bridge_ends.remove(&settings.first).unwrap();
+            let second = bridge_ends.remove(&settings.second).unwrap();
+            let names = (
+                settings.get_direction_name(&settings.first),
+                settings.get_direction_name(&settings.second),
+            );
+
+            let (forward, backward) = bridge::connect(first, second, names);
+            for mut forwarder in [forward, backward] {
+                let cancel_signal_clone = self.cancel_signal.clone();
+                let sender_clone = self.sender.clone();
+                let handle = tokio::spawn(async move {
+                    forwarder.run(cancel_signal_clone, sender_clone).await;
+                });
+                self.handles.push(handle);
+            }
+        }
+    }
+
+    /// Publishes the messages of all sources on a Unix socket, if requested.
+    fn spawn_publisher(&mut self) {
+        let path = match &self.publish_path {
+            Some(p) => p.clone(),
+            None => return,
+        };
+
+        let (line_sender, _) = tokio::sync::broadcast::channel(publisher::PUBLISHED_CAPACITY);
+        let mut message_publisher = publisher::MessagePublisher::new(path, line_sender.clone());
+        self.published_lines = Some(line_sender);
+
+        let cancel_signal_clone = self.cancel_signal.clone();
+        let sender_clone = self.sender.clone();
+        let handle = tokio::spawn(async move {
+            message_publisher
+                .run(cancel_signal_clone, sender_clone)
+                .await;
+        });
+        self.handles.push(handle);
+    }
+
+    /// Sends the message to the clients of the published messages, if there are any.
+    pub fn publish_monitor_message(&self, msg: &log_monitor::MonitorMessage) {
+        if let Some(line_sender) = &self.published_lines {
+            if line_sender.receiver_count() > 0 {
+                let _ = line_sender.send(msg.to_json_line());
+            }
+        }
+    }
+
+    /// Write proxy for other programs using the port, unless the port is read-only.
+    fn get_shared_write_proxy(
+        &self,
+        monitor: &serial_monitor::SerialLogMonitor,
+    ) -> Option<log_monitor::WriteProxy> {
+        if self.read_only_ports.contains(&monitor.get_common_name()) {
+            None
+        } else {
+            Some(monitor.get_write_proxy())
+        }
+    }
+
     pub async fn stop_monitors(&mut self) {
         self.cancel_signal.cancel();
         for handle in &mut self.handles {
@@ -82,23 +443,275 @@ impl DrFishCli {
         }
     }
 
+    /// Returns the next message passed through the hooks, messages dropped by the
+    /// hooks are skipped. The hook scripts are reloaded in between when changed.
     pub async fn recieve_monitor_message(&mut self) -> Option<log_monitor::MonitorMessage> {
-        self.receiver.recv().await
+        loop {
+            if let Some(msg) = self.hooked_messages.pop_front() {
+                return Some(msg);
+            }
+
+            tokio::select! {
+                msg = self.receiver.recv() => {
+                    self.run_hooks(msg?);
+                }
+
+                _ = self.hooks_reload_timer.tick(), if !self.hooks.is_empty() => {
+                    self.hooked_messages.extend(self.hooks.reload_changed());
+                }
+            }
+        }
+    }
+
+    /// Queues the messages resulting from the hooks and writes what they sent.
+    fn run_hooks(&mut self, msg: log_monitor::MonitorMessage) {
+        let result = self.hooks.process(msg);
+        self.hooked_messages.extend(result.messages);
+
+        for write in result.writes {
+            if let Err(e) = self.write_text(&write.target, &write.text) {
+                self.hooked_messages
+                    .push_back(log_monitor::MonitorMessage::Event(log_monitor::Event {
+                        source_name: format!("hook:{}", write.hook),
+                        description: e,
+                        timestamp: chrono::Local::now(),
+                    }));
+            }
+        }
+    }
+
+    /// Writes the text to the port, alias or group, without changing the input target.
+    fn write_text(&self, target: &str, text: &str) -> Result<(), String> {
+        for (port_name, write_proxy) in self.writer.get_write_proxies_by_name(target)? {
+            for byte in text.bytes() {
+                write_proxy
+                    .request(log_monitor::PortRequest::Write(byte))
+                    .map_err(|e| format!("Failed to write to {}: {}", port_name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the state shown to the user based on the received message.
+    pub fn handle_monitor_message(&mut self, msg: &log_monitor::MonitorMessage) {
+        if let Some(script_sender) = &self.script_sender {
+            let _ = script_sender.send(msg.clone());
+        }
+        if let log_monitor::MonitorMessage::ModemStatus(status) = msg {
+            self.status_bar
+                .set_field(&status.source_name, status.lines.to_string());
+        }
+    }
+
+    /// Runs the actions of the triggers fired by the message. Returns the action
+    /// ending the session, if a trigger requested it.
+    pub async fn run_triggers(&mut self, msg: &log_monitor::MonitorMessage) -> Option<CliAction> {
+        let log = match msg {
+            log_monitor::MonitorMessage::Log(log) => log,
+            _ => return None,
+        };
+
+        let mut cli_action = None;
+        for firing in self.triggers.check(log, std::time::Instant::now()) {
+            for action in firing.actions.clone() {
+                match action {
+                    trigger::TriggerAction::Notify => {
+                        print!("\x07");
+                        self.send_trigger_event(log, &firing.name, "fired".to_string());
+                    }
+                    trigger::TriggerAction::Send(text) => {
+                        self.send_trigger_text(log, &firing, &text);
+                    }
+                    trigger::TriggerAction::Commands(commands) => {
+                        self.run_trigger_commands(log, &firing, commands);
+                    }
+                    trigger::TriggerAction::Run(command) => {
+                        self.run_trigger_process(log, &firing.name, command);
+                    }
+                    trigger::TriggerAction::Stop => {
+                        self.send_trigger_event(log, &firing.name, "ended the session".to_string());
+                        cli_action.get_or_insert(CliAction::Break);
+                    }
+                    trigger::TriggerAction::Exit(code) => {
+                        let description = format!("ended the session with exit code {}", code);
+                        self.send_trigger_event(log, &firing.name, description);
+                        cli_action = Some(CliAction::Exit(code));
+                    }
+                }
+            }
+        }
+
+        if cli_action.is_some() {
+            self.stop_monitors().await;
+        }
+        cli_action
+    }
+
+    fn send_trigger_event(&self, log: &log_monitor::Log, name: &str, description: String) {
+        let event = log_monitor::Event {
+            source_name: log.source_name.clone(),
+            description: format!("Trigger {} {}", name, description),
+            timestamp: chrono::Local::now(),
+        };
+        self.sender
+            .send(log_monitor::MonitorMessage::Event(event))
+            .unwrap();
+    }
+
+    /// Write proxies of the trigger target, the source of the line by default.
+    fn get_trigger_write_proxies(
+        &self,
+        log: &log_monitor::Log,
+        firing: &trigger::Firing,
+    ) -> Result<Vec<(String, log_monitor::WriteProxy)>, String> {
+        let target = firing.target.as_ref().unwrap_or(&log.source_name);
+        self.writer.get_write_proxies_by_name(target)
+    }
+
+    fn send_trigger_text(&self, log: &log_monitor::Log, firing: &trigger::Firing, text: &str) {
+        let write_proxies = match self.get_trigger_write_proxies(log, firing) {
+            Ok(write_proxies) => write_proxies,
+            Err(e) => return self.send_trigger_event(log, &firing.name, format!("failed: {}", e)),
+        };
+
+        for (port_name, write_proxy) in write_proxies {
+            for byte in text.bytes() {
+                if let Err(e) = write_proxy.request(log_monitor::PortRequest::Write(byte)) {
+                    let description = format!("failed to write to {}: {}", port_name, e);
+                    self.send_trigger_event(log, &firing.name, description);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn run_trigger_commands(
+        &self,
+        log: &log_monitor::Log,
+        firing: &trigger::Firing,
+        commands: Vec<command::Command>,
+    ) {
+        let expanded: Result<Vec<Vec<command::Command>>, String> = commands
+            .into_iter()
+            .map(|c| self.expand_command(c))
+            .collect();
+        let write_proxies = self.get_trigger_write_proxies(log, firing);
+
+        match (expanded, write_proxies) {
+            (Ok(commands), Ok(write_proxies)) => {
+                run_commands(commands.concat(), write_proxies);
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                self.send_trigger_event(log, &firing.name, format!("failed: {}", e));
+            }
+        }
+    }
+
+    /// Runs the shell command in the background, with the line in its environment.
+    /// The output is discarded, the exit status is recorded in the log.
+    fn run_trigger_process(&self, log: &log_monitor::Log, name: &str, command: String) {
+        let mut process = tokio::process::Command::new("sh");
+        process
+            .arg("-c")
+            .arg(&command)
+            .env("DRFISH_TRIGGER", name)
+            .env("DRFISH_SOURCE", &log.source_name)
+            .env("DRFISH_LINE", &log.message)
+            .env("DRFISH_TIMESTAMP", log.timestamp.to_rfc3339())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+
+        let event_sender = self.sender.clone();
+        let source_name = log.source_name.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let description = match process.status().await {
+                Ok(status) => format!("Trigger {} command `{}` {}", name, command, status),
+                Err(e) => format!("Trigger {} failed to run `{}`: {}", name, command, e),
+            };
+            let event = log_monitor::Event {
+                source_name,
+                description,
+                timestamp: chrono::Local::now(),
+            };
+            // the session may have ended in the meantime
+            let _ = event_sender.send(log_monitor::MonitorMessage::Event(event));
+        });
+    }
+
+    /// Messages received but not handled yet, e.g. when the session ends.
+    pub fn take_pending_messages(&mut self) -> Vec<log_monitor::MonitorMessage> {
+        while let Ok(msg) = self.receiver.try_recv() {
+            self.run_hooks(msg);
+        }
+        self.hooked_messages.drain(..).collect()
+    }
+
+    pub fn get_prefix_key_name(&self) -> String {
+        keymap::format_key(&self.keymap.get_prefix())
     }
 
     pub async fn handle_key_press(&mut self, key: termion::event::Key) -> Option<CliAction> {
+        match std::mem::replace(&mut self.input_mode, InputMode::Normal) {
+            InputMode::Normal => {}
+            InputMode::Prefix => {
+                return self.handle_prefixed_key(key).await;
+            }
+            InputMode::Prompt(prompt, text) => {
+                self.handle_prompt_key(key, prompt, text);
+                return None;
+            }
+        }
+
+        if key == self.keymap.get_prefix() {
+            self.input_mode = InputMode::Prefix;
+            return None;
+        }
+
         match key {
-            termion::event::Key::Ctrl('x') => {
+            termion::event::Key::Ctrl(_) => {
+                self.write_key(key);
+            }
+
+            termion::event::Key::Char(c) => {
+                // FIXME: this is a hack to send CRLF to the serial port
+                if c == '\n' {
+                    self.write_key(termion::event::Key::Char('\r'));
+                    return None;
+                }
+
+                self.write_key(key);
+            }
+
+            _ => {}
+        }
+
+        None
+    }
+
+    async fn handle_prefixed_key(&mut self, key: termion::event::Key) -> Option<CliAction> {
+        let action = match self.keymap.get_action(&key) {
+            Some(a) => a,
+            None => {
+                print!("No action bound to {}\r\n", keymap::format_key(&key));
+                return None;
+            }
+        };
+
+        match action {
+            keymap::Action::Quit => {
                 print!("Exiting...\r\n");
                 self.stop_monitors().await;
                 return Some(CliAction::Break);
             }
 
-            termion::event::Key::Ctrl('z') => {
+            keymap::Action::NextWriter => {
                 let new_writer_name = self.writer.switch_to_next_writer();
                 match new_writer_name {
                     Some(_) => {
                         print!("Switching to {}\r\n", new_writer_name.unwrap());
+                        self.update_input_status();
                     }
                     None => {
                         print!("No other writer available\r\n");
@@ -106,25 +719,167 @@ impl DrFishCli {
                 }
             }
 
-            termion::event::Key::Ctrl(_) => {
-                self.writer.write_key(key);
+            keymap::Action::SelectWriter(index) => {
+                let result = self.writer.select_writer_by_index(index);
+                self.report_writer_selection(result);
             }
 
-            termion::event::Key::Char(c) => {
-                // FIXME: this is a hack to send CRLF to the serial port
-                if c == '\n' {
-                    self.writer.write_key(termion::event::Key::Char('\r'));
-                    return None;
+            keymap::Action::PickWriter => {
+                print!("Writers:\r\n");
+                for writer in self.writer.get_writer_list() {
+                    print!("  {}\r\n", writer);
                 }
+                self.open_prompt(Prompt::Writer);
+            }
 
-                self.writer.write_key(key);
+            keymap::Action::CommandPrompt => {
+                self.open_prompt(Prompt::Command);
             }
 
-            _ => {}
+            keymap::Action::Command(command) => {
+                self.execute_command(command);
+            }
+
+            keymap::Action::SendPrefix => {
+                self.write_key(self.keymap.get_prefix());
+            }
+
+            keymap::Action::Help => {
+                print!("Key bindings:\r\n");
+                for binding in self.keymap.describe() {
+                    print!("  {}\r\n", binding);
+                }
+            }
         }
 
         None
     }
+
+    fn open_prompt(&mut self, prompt: Prompt) {
+        self.status_bar.set_field(prompt.get_name(), String::new());
+        self.input_mode = InputMode::Prompt(prompt, String::new());
+    }
+
+    fn handle_prompt_key(&mut self, key: termion::event::Key, prompt: Prompt, mut text: String) {
+        match key {
+            termion::event::Key::Char('\n') => {
+                self.status_bar.remove_field(prompt.get_name());
+                match prompt {
+                    Prompt::Writer => {
+                        let result = self.writer.select_writer_by_name(&text);
+                        self.report_writer_selection(result);
+                    }
+                    Prompt::Command => match command::Command::parse(&text) {
+                        Ok(command) => self.execute_command(command),
+                        Err(e) => print!("{}\r\n", e),
+                    },
+                }
+                return;
+            }
+
+            termion::event::Key::Esc => {
+                self.status_bar.remove_field(prompt.get_name());
+                return;
+            }
+
+            termion::event::Key::Backspace => {
+                text.pop();
+            }
+
+            termion::event::Key::Char(c) => {
+                text.push(c);
+            }
+
+            _ => {}
+        }
+
+        self.status_bar.set_field(prompt.get_name(), text.clone());
+        self.input_mode = InputMode::Prompt(prompt, text);
+    }
+
+    /// Runs the command against the ports of the current input target.
+    fn execute_command(&mut self, command: command::Command) {
+        let commands = match self.expand_command(command) {
+            Ok(commands) => commands,
+            Err(e) => {
+                print!("{}\r\n", e);
+                return;
+            }
+        };
+
+        let write_proxies = self.writer.get_target_write_proxies();
+        if write_proxies.is_empty() {
+            print!("No port to run the command on\r\n");
+            return;
+        }
+
+        run_commands(commands, write_proxies);
+    }
+
+    /// Replaces a sequence with its commands.
+    fn expand_command(&self, command: command::Command) -> Result<Vec<command::Command>, String> {
+        match command {
+            command::Command::Sequence(name) => match self.sequences.get(&name) {
+                Some(commands) => Ok(commands.clone()),
+                None => Err(format!(
+                    "Unknown sequence {}, available: {}",
+                    name,
+                    self.sequences.get_names().join(", ")
+                )),
+            },
+            command => Ok(vec![command]),
+        }
+    }
+
+    fn report_writer_selection(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(name) => {
+                print!("Switching to {}\r\n", name);
+                self.update_input_status();
+            }
+            Err(e) => {
+                print!("{}\r\n", e);
+            }
+        }
+    }
+
+    fn write_key(&mut self, key: termion::event::Key) {
+        for failure in self.writer.write_key(key) {
+            print!(
+                "Failed to deliver input to {}: {}\r\n",
+                failure.port_name, failure.error
+            );
+        }
+    }
+
+    fn update_input_status(&mut self) {
+        let target = match self.writer.get_current_writer_name() {
+            Some(name) => name,
+            None => "none".to_string(),
+        };
+        let ports = self.writer.get_target_port_names();
+        let value = if ports.len() > 1 {
+            format!("{} ({})", target, ports.join(", "))
+        } else {
+            target
+        };
+        self.status_bar.set_field("input", value);
+    }
+}
+
+/// Runs the commands in the background, one after another.
+fn run_commands(
+    commands: Vec<command::Command>,
+    write_proxies: Vec<(String, log_monitor::WriteProxy)>,
+) {
+    tokio::spawn(async move {
+        for failure in sequence::run(commands, write_proxies).await {
+            print!(
+                "Failed to deliver command to {}: {}\r\n",
+                failure.port_name, failure.error
+            );
+        }
+    });
 }
 
 fn parse_port_arg(arg: &str) -> Result<data::SerialPortSettings, String> {
@@ -137,12 +892,21 @@ fn parse_port_arg(arg: &str) -> Result<data::SerialPortSettings, String> {
         return Ok(data::SerialPortSettings {
             path: parts[0].to_string(),
             baud_rate: DEFAULT_BAUD_RATE,
+            autobaud: false,
         });
     } else if parts.len() != 2 {
         return Err(format!("Invalid port argument: {}", arg));
     }
 
     let port_path = parts[0].to_string();
+    if parts[1] == AUTOBAUD_ARG {
+        return Ok(data::SerialPortSettings {
+            path: port_path,
+            baud_rate: DEFAULT_BAUD_RATE,
+            autobaud: true,
+        });
+    }
+
     let baud_rate = match parts[1].parse::<u32>() {
         Ok(b) => b,
         Err(_) => {
@@ -152,37 +916,520 @@ fn parse_port_arg(arg: &str) -> Result<data::SerialPortSettings, String> {
 
     Ok(data::SerialPortSettings {
         path: port_path,
-        baud_rate: baud_rate,
+        baud_rate,
+        autobaud: false,
     })
 }
 
-/// returns a collection of serial port settings from CLI arguments
-/// or a default value if no arguments are provided
+/// parses a TCP address in the form of HOST:PORT
+fn parse_tcp_arg(address: &str, protocol: data::TcpProtocol) -> Result<data::TcpSettings, String> {
+    if !is_valid_address(address) {
+        return Err(format!("Invalid TCP address: {}", address));
+    }
+
+    Ok(data::TcpSettings {
+        address: address.to_string(),
+        protocol,
+    })
+}
+
+/// parses the local address to receive datagrams on, in the form of HOST:PORT
+fn parse_udp_arg(address: &str) -> Result<data::UdpSettings, String> {
+    if !is_valid_address(address) {
+        return Err(format!("Invalid UDP address: {}", address));
+    }
+
+    Ok(data::UdpSettings {
+        address: address.to_string(),
+    })
+}
+
+fn is_valid_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+fn parse_source_arg(arg: &str) -> Result<data::SourceSettings, String> {
+    if let Some(address) = arg.strip_prefix(TCP_SCHEME) {
+        let settings = parse_tcp_arg(address, data::TcpProtocol::Raw)?;
+        return Ok(data::SourceSettings::Tcp(settings));
+    }
+
+    if let Some(address) = arg.strip_prefix(RFC2217_SCHEME) {
+        let settings = parse_tcp_arg(address, data::TcpProtocol::Rfc2217)?;
+        return Ok(data::SourceSettings::Tcp(settings));
+    }
+
+    if let Some(address) = arg.strip_prefix(UDP_SCHEME) {
+        return Ok(data::SourceSettings::Udp(parse_udp_arg(address)?));
+    }
+
+    if let Some(command) = arg.strip_prefix(PROCESS_PREFIX) {
+        if command.trim().is_empty() {
+            return Err(format!("Missing command: {}", arg));
+        }
+        return Ok(data::SourceSettings::Process(data::ProcessSettings {
+            command: command.to_string(),
+        }));
+    }
+
+    if let Some(path) = arg.strip_prefix(FILE_PREFIX) {
+        if path.is_empty() {
+            return Err(format!("Missing file path: {}", arg));
+        }
+        return Ok(data::SourceSettings::File(data::FileSettings {
+            path: path.to_string(),
+        }));
+    }
+
+    if let Some(interface) = arg.strip_prefix(CAN_PREFIX) {
+        if interface.is_empty() {
+            return Err(format!("Missing CAN interface: {}", arg));
+        }
+        return Ok(data::SourceSettings::Can(data::CanSettings {
+            interface: interface.to_string(),
+            dbc: None,
+        }));
+    }
+
+    if let Some(path) = arg.strip_prefix(UNIX_PREFIX) {
+        if path.is_empty() {
+            return Err(format!("Missing socket path: {}", arg));
+        }
+        return Ok(data::SourceSettings::Unix(data::UnixSettings {
+            path: path.to_string(),
+        }));
+    }
+
+    Ok(data::SourceSettings::Serial(parse_port_arg(arg)?))
+}
+
+/// parses a port group in the form of NAME=PORT_PATH[,PORT_PATH...]
+fn parse_group_arg(arg: &str) -> Result<data::PortGroup, String> {
+    let (name, ports) = match arg.split_once('=') {
+        Some(parts) => parts,
+        None => {
+            return Err(format!("Invalid group argument: {}", arg));
+        }
+    };
+
+    if name.is_empty() {
+        return Err(format!("Missing group name: {}", arg));
+    }
+
+    let ports: Vec<String> = ports
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_string())
+        .collect();
+    if ports.is_empty() {
+        return Err(format!("Group {} has no ports", name));
+    }
+
+    Ok(data::PortGroup {
+        name: name.to_string(),
+        ports,
+    })
+}
+
+/// parses a port alias in the form of NAME=PORT_PATH
+fn parse_alias_arg(arg: &str) -> Result<data::PortAlias, String> {
+    match arg.split_once('=') {
+        Some((name, port)) if !name.is_empty() && !port.is_empty() => Ok(data::PortAlias {
+            name: name.to_string(),
+            port: port.to_string(),
+        }),
+        _ => Err(format!("Invalid alias argument: {}", arg)),
+    }
+}
+
+/// parses a served port in the form of PORT_PATH=[tcp://|rfc2217://]HOST:PORT
+fn parse_serve_arg(arg: &str) -> Result<data::PortServerSettings, String> {
+    let (port, address) = match arg.split_once('=') {
+        Some((port, address)) if !port.is_empty() => (port, address),
+        _ => return Err(format!("Invalid serve argument: {}", arg)),
+    };
+
+    let listen = if let Some(address) = address.strip_prefix(RFC2217_SCHEME) {
+        parse_tcp_arg(address, data::TcpProtocol::Rfc2217)?
+    } else {
+        let address = address.strip_prefix(TCP_SCHEME).unwrap_or(address);
+        parse_tcp_arg(address, data::TcpProtocol::Raw)?
+    };
+
+    Ok(data::PortServerSettings {
+        port: port.to_string(),
+        listen,
+    })
+}
+
+/// parses a pseudo-terminal relaying to a port in the form of PORT_PATH=LINK_PATH
+fn parse_pty_arg(arg: &str) -> Result<data::PtySettings, String> {
+    match arg.split_once('=') {
+        Some((port, link)) if !port.is_empty() && !link.is_empty() => Ok(data::PtySettings {
+            port: port.to_string(),
+            link: link.to_string(),
+        }),
+        _ => Err(format!("Invalid PTY argument: {}", arg)),
+    }
+}
+
+/// parses two bridged serial ports in the form of PORT_PATH=PORT_PATH
+fn parse_bridge_arg(arg: &str) -> Result<data::BridgeSettings, String> {
+    match arg.split_once('=') {
+        Some((first, second)) if !first.is_empty() && !second.is_empty() && first != second => {
+            Ok(data::BridgeSettings {
+                first: first.to_string(),
+                second: second.to_string(),
+            })
+        }
+        _ => Err(format!("Invalid bridge argument: {}", arg)),
+    }
+}
+
+fn get_option_value<'a>(
+    option: &str,
+    args_iter: &mut impl Iterator<Item = &'a String>,
+) -> Result<&'a String, String> {
+    match args_iter.next() {
+        Some(value) => Ok(value),
+        None => Err(format!("Missing value for {}", option)),
+    }
+}
+
+/// parses the command line arguments (without the program name)
 ///
 /// serial ports can be passed as arguments in the form of:
-/// PORT_PATH,BAUD_RATE (e.g. /dev/ttyUSB0:115200)
-pub fn get_port_configuration() -> Result<Vec<data::SerialPortSettings>, String> {
-    let mut settings = Vec::new();
+/// PORT_PATH:BAUD_RATE (e.g. /dev/ttyUSB0:115200)
+/// or PORT_PATH:auto to detect the baud rate
+///
+/// TCP servers (e.g. ser2net) are passed in the form of:
+/// tcp://HOST:PORT
+/// or, for servers controlling the remote port with RFC 2217:
+/// rfc2217://HOST:PORT
+///
+/// UDP datagrams are received on a local address given in the form of:
+/// udp://HOST:PORT
+///
+/// the output of a command, run with sh -c, is read from a source given as:
+/// cmd:COMMAND
+///
+/// files, followed like with tail -F, and named pipes are read from sources given as:
+/// file:PATH
+///
+/// ports can be grouped for broadcasting input with:
+/// --group NAME=PORT_PATH[,PORT_PATH...]
+///
+/// ports can be given a name to select them with:
+/// --alias NAME=PORT_PATH
+///
+/// ports that must never receive input are marked with:
+/// --read-only PORT_PATH
+///
+/// serial ports are shared over TCP, raw or with RFC 2217, with:
+/// --serve PORT_PATH=[tcp://|rfc2217://]HOST:PORT
+///
+/// serial ports are relayed through a pseudo-terminal, reachable at a symbolic link, with:
+/// --pty PORT_PATH=LINK_PATH
+///
+/// two serial ports forward everything they receive to each other with:
+/// --bridge PORT_PATH=PORT_PATH
+///
+/// frames on a SocketCAN interface are read from sources given as:
+/// can:INTERFACE
+/// and their signals are decoded with the definitions of a DBC file given with:
+/// --dbc can:INTERFACE=DBC_PATH
+///
+/// Unix domain stream sockets, e.g. of simulators, are read from sources given as:
+/// unix:PATH
+///
+/// the messages of all sources are published as JSON lines on a Unix socket with:
+/// --publish PATH
+///
+/// the configuration file is read from the default location unless given with:
+/// --config PATH
+///
+/// a recorded session is replayed instead of monitoring any source with:
+/// replay LOG_PATH [--speed N] [--format auto|text|json|raw] [--baud BAUD_RATE]
+///
+/// a script is run against the sources, ending the session when it finishes, with:
+/// run SCRIPT_PATH [SOURCES AND OPTIONS]
+fn parse_args(args: &[String]) -> Result<Arguments, String> {
+    match args.first().map(String::as_str) {
+        Some(REPLAY_COMMAND) => return parse_replay_args(&args[1..]),
+        Some(script::COMMAND) => {
+            let path = match args.get(1) {
+                Some(path) if !path.starts_with("--") => path.clone(),
+                _ => return Err("Missing the path of the script".to_string()),
+            };
+            if let Some(command @ (REPLAY_COMMAND | script::COMMAND)) =
+                args.get(2).map(String::as_str)
+            {
+                return Err(format!("A script cannot be combined with {}", command));
+            }
+            let mut arguments = parse_args(&args[2..])?;
+            arguments.script_path = Some(path);
+            return Ok(arguments);
+        }
+        _ => {}
+    }
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        settings.push(data::SerialPortSettings {
+    let mut ports = Vec::new();
+    let mut groups = Vec::new();
+    let mut aliases = Vec::new();
+    let mut read_only_ports = Vec::new();
+    let mut servers = Vec::new();
+    let mut ptys = Vec::new();
+    let mut bridges = Vec::new();
+    let mut dbc_files = Vec::new();
+    let mut publish_path = None;
+    let mut config_path = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--group" => {
+                groups.push(parse_group_arg(get_option_value(arg, &mut args_iter)?)?);
+            }
+            "--alias" => {
+                aliases.push(parse_alias_arg(get_option_value(arg, &mut args_iter)?)?);
+            }
+            "--read-only" => {
+                read_only_ports.push(get_option_value(arg, &mut args_iter)?.clone());
+            }
+            "--serve" => {
+                servers.push(parse_serve_arg(get_option_value(arg, &mut args_iter)?)?);
+            }
+            "--pty" => {
+                ptys.push(parse_pty_arg(get_option_value(arg, &mut args_iter)?)?);
+            }
+            "--dbc" => {
+                let arg = get_option_value(arg, &mut args_iter)?;
+                match arg.split_once('=') {
+                    Some((source, path)) if !path.is_empty() => dbc_files.push((source, path)),
+                    _ => return Err(format!("Invalid DBC argument: {}", arg)),
+                }
+            }
+            "--publish" => {
+                publish_path = Some(get_option_value(arg, &mut args_iter)?.clone());
+            }
+            "--bridge" => {
+                bridges.push(parse_bridge_arg(get_option_value(arg, &mut args_iter)?)?);
+            }
+            "--config" => {
+                config_path = Some(get_option_value(arg, &mut args_iter)?.clone());
+            }
+            _ => {
+                ports.push(parse_source_arg(arg)?);
+            }
+        }
+    }
+
+    if ports.is_empty() {
+        ports.push(data::SourceSettings::Serial(data::SerialPortSettings {
             path: DEFAULT_SERIAL_PORT.to_string(),
             baud_rate: DEFAULT_BAUD_RATE,
+            autobaud: false,
+        }));
+    }
+
+    for (source, path) in dbc_files {
+        let settings = ports.iter_mut().find_map(|p| match p {
+            data::SourceSettings::Can(settings) if settings.get_name() == source => Some(settings),
+            _ => None,
         });
-    } else {
-        for arg in &args[1..] {
-            let port_settings = parse_port_arg(arg);
-            match port_settings {
-                Ok(s) => settings.push(s),
-                Err(e) => {
-                    return Err(e);
-                }
+        match settings {
+            Some(settings) => settings.dbc = Some(path.to_string()),
+            None => return Err(format!("DBC file for unknown CAN interface {}", source)),
+        }
+    }
+
+    // files cannot be written to
+    for port in &ports {
+        if let data::SourceSettings::File(_) = port {
+            if !read_only_ports.contains(&port.get_name()) {
+                read_only_ports.push(port.get_name());
+            }
+        }
+    }
+
+    let is_known_port = |name: &String| ports.iter().any(|p| &p.get_name() == name);
+
+    for group in &groups {
+        for member in &group.ports {
+            if !is_known_port(member) {
+                return Err(format!("Unknown port {} in group {}", member, group.name));
             }
+            if read_only_ports.contains(member) {
+                return Err(format!("Read-only port {} in group {}", member, group.name));
+            }
+        }
+    }
+
+    for alias in &aliases {
+        if !is_known_port(&alias.port) {
+            return Err(format!(
+                "Unknown port {} for alias {}",
+                alias.port, alias.name
+            ));
+        }
+    }
+
+    for path in &read_only_ports {
+        if !is_known_port(path) {
+            return Err(format!("Unknown read-only port {}", path));
+        }
+    }
+
+    let is_serial_port = |name: &String| {
+        ports.iter().any(|p| match p {
+            data::SourceSettings::Serial(settings) => &settings.path == name,
+            _ => false,
+        })
+    };
+
+    for served in &servers {
+        if !is_serial_port(&served.port) {
+            return Err(format!("Only serial ports can be served: {}", served.port));
+        }
+    }
+
+    for pty in &ptys {
+        if !is_serial_port(&pty.port) {
+            return Err(format!("Only serial ports can be relayed: {}", pty.port));
         }
     }
 
-    Ok(settings)
+    for (i, bridge) in bridges.iter().enumerate() {
+        for port in [&bridge.first, &bridge.second] {
+            if !is_serial_port(port) {
+                return Err(format!("Only serial ports can be bridged: {}", port));
+            }
+            if read_only_ports.contains(port) {
+                return Err(format!("Read-only port {} cannot be bridged", port));
+            }
+            if bridges[..i].iter().any(|b| b.contains(port)) {
+                return Err(format!("Port {} is bridged more than once", port));
+            }
+            if let Some(group) = groups.iter().find(|g| g.ports.contains(port)) {
+                return Err(format!("Bridged port {} in group {}", port, group.name));
+            }
+        }
+    }
+
+    Ok(Arguments {
+        ports,
+        groups,
+        aliases,
+        read_only_ports,
+        servers,
+        ptys,
+        bridges,
+        publish_path,
+        config_path,
+        script_path: None,
+    })
+}
+
+fn parse_log_format(arg: &str) -> Result<data::LogFormat, String> {
+    match arg {
+        "auto" => Ok(data::LogFormat::Auto),
+        "text" => Ok(data::LogFormat::Text),
+        "json" => Ok(data::LogFormat::JsonLines),
+        "raw" => Ok(data::LogFormat::Raw),
+        _ => Err(format!("Invalid log format: {}", arg)),
+    }
+}
+
+/// parses the arguments following the replay command, the recorded session is the
+/// only source, its messages can still be published and the configuration is read
+fn parse_replay_args(args: &[String]) -> Result<Arguments, String> {
+    let mut path = None;
+    let mut format = data::LogFormat::Auto;
+    let mut speed = DEFAULT_REPLAY_SPEED;
+    let mut baud_rate = DEFAULT_BAUD_RATE;
+    let mut publish_path = None;
+    let mut config_path = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--speed" => {
+                let value = get_option_value(arg, &mut args_iter)?;
+                let speed_range = replay_monitor::MIN_SPEED..=replay_monitor::MAX_SPEED;
+                speed = match value.parse::<f64>() {
+                    Ok(speed) if speed_range.contains(&speed) => speed,
+                    _ => {
+                        return Err(format!(
+                            "Invalid replay speed {}, expected {} to {}",
+                            value,
+                            speed_range.start(),
+                            speed_range.end()
+                        ))
+                    }
+                };
+            }
+            "--format" => {
+                format = parse_log_format(get_option_value(arg, &mut args_iter)?)?;
+            }
+            "--baud" => {
+                let value = get_option_value(arg, &mut args_iter)?;
+                baud_rate = match value.parse::<u32>() {
+                    Ok(baud_rate) if baud_rate > 0 => baud_rate,
+                    _ => return Err(format!("Invalid baud rate: {}", value)),
+                };
+            }
+            "--publish" => {
+                publish_path = Some(get_option_value(arg, &mut args_iter)?.clone());
+            }
+            "--config" => {
+                config_path = Some(get_option_value(arg, &mut args_iter)?.clone());
+            }
+            _ if arg.starts_with("--") => {
+                return Err(format!("Option {} is not available when replaying", arg));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("Only one session can be replayed: {}", arg)),
+        }
+    }
+
+    let path = path.ok_or("Missing the path of the replayed session")?;
+
+    Ok(Arguments {
+        ports: vec![data::SourceSettings::Replay(data::ReplaySettings {
+            path,
+            format,
+            speed,
+            baud_rate,
+        })],
+        groups: Vec::new(),
+        aliases: Vec::new(),
+        read_only_ports: Vec::new(),
+        servers: Vec::new(),
+        ptys: Vec::new(),
+        bridges: Vec::new(),
+        publish_path,
+        config_path,
+        script_path: None,
+    })
+}
+
+/// Waits until the script ends and returns its exit code, never returns when no
+/// script runs.
+pub async fn wait_for_script(result: &mut Option<tokio::sync::oneshot::Receiver<i32>>) -> i32 {
+    match result {
+        Some(result_receiver) => result_receiver.await.unwrap_or(script::FAILURE_EXIT_CODE),
+        None => std::future::pending().await,
+    }
+}
+
+/// returns the port configuration from CLI arguments
+/// or a default value if no ports are provided
+pub fn get_arguments() -> Result<Arguments, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    parse_args(&args)
 }
 
 #[cfg(test)]
@@ -223,10 +1470,317 @@ mod tests {
         assert_eq!(settings.baud_rate, 115200);
     }
 
+    #[test]
+    fn test_parse_port_autobaud() {
+        let result = parse_port_arg("/dev/ttyUSB0:auto");
+        assert!(result.is_ok());
+        let settings = result.unwrap();
+        assert!(settings.autobaud);
+        assert_eq!(settings.baud_rate, 115200);
+        assert!(!parse_port_arg("/dev/ttyUSB0:9600").unwrap().autobaud);
+    }
+
     #[test]
     fn test_parse_port_invalid_fields() {
         let port_arg = "path:115200:extra";
         let result = parse_port_arg(port_arg);
         assert!(result.is_err());
     }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_source_arg_tcp() {
+        let result = parse_source_arg("tcp://localhost:3333");
+        assert!(result.is_ok());
+        match result.unwrap() {
+            data::SourceSettings::Tcp(settings) => {
+                assert_eq!(settings.address, "localhost:3333");
+            }
+            settings => panic!("Unexpected source: {:?}", settings),
+        }
+    }
+
+    #[test]
+    fn test_parse_source_arg_rfc2217() {
+        match parse_source_arg("rfc2217://10.0.0.1:2217").unwrap() {
+            data::SourceSettings::Tcp(settings) => {
+                assert_eq!(settings.address, "10.0.0.1:2217");
+                assert_eq!(settings.protocol, data::TcpProtocol::Rfc2217);
+                assert_eq!(settings.get_name(), "rfc2217://10.0.0.1:2217");
+            }
+            settings => panic!("Unexpected source: {:?}", settings),
+        }
+        assert!(parse_source_arg("rfc2217://10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_parse_source_arg_udp() {
+        match parse_source_arg("udp://0.0.0.0:5140").unwrap() {
+            data::SourceSettings::Udp(settings) => {
+                assert_eq!(settings.address, "0.0.0.0:5140");
+                assert_eq!(settings.get_name(), "udp://0.0.0.0:5140");
+            }
+            settings => panic!("Unexpected source: {:?}", settings),
+        }
+        assert!(parse_source_arg("udp://0.0.0.0").is_err());
+    }
+
+    #[test]
+    fn test_parse_source_arg_process() {
+        match parse_source_arg("cmd:adb logcat -v time").unwrap() {
+            data::SourceSettings::Process(settings) => {
+                assert_eq!(settings.command, "adb logcat -v time");
+                assert_eq!(settings.get_name(), "cmd:adb logcat -v time");
+            }
+            settings => panic!("Unexpected source: {:?}", settings),
+        }
+        assert!(parse_source_arg("cmd: ").is_err());
+    }
+
+    #[test]
+    fn test_parse_args_files_are_read_only() {
+        let args = to_args(&["file:/tmp/test.log", "/dev/ttyUSB0"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.ports[0].get_name(), "file:/tmp/test.log");
+        assert_eq!(arguments.read_only_ports, vec!["file:/tmp/test.log"]);
+
+        let args = to_args(&["file:x", "a", "--group", "g=a,file:x"]);
+        assert!(parse_args(&args).is_err());
+        assert!(parse_source_arg("file:").is_err());
+    }
+
+    #[test]
+    fn test_parse_source_arg_tcp_invalid() {
+        assert!(parse_source_arg("tcp://localhost").is_err());
+        assert!(parse_source_arg("tcp://localhost:port").is_err());
+        assert!(parse_source_arg("tcp://:3333").is_err());
+    }
+
+    #[test]
+    fn test_parse_serve_arg() {
+        let settings = parse_serve_arg("/dev/ttyUSB0=0.0.0.0:3001").unwrap();
+        assert_eq!(settings.port, "/dev/ttyUSB0");
+        assert_eq!(settings.listen.address, "0.0.0.0:3001");
+        assert_eq!(settings.listen.protocol, data::TcpProtocol::Raw);
+
+        let settings = parse_serve_arg("/dev/ttyUSB0=rfc2217://:2217");
+        assert!(settings.is_err());
+        let settings = parse_serve_arg("/dev/ttyUSB0=rfc2217://localhost:2217").unwrap();
+        assert_eq!(settings.listen.protocol, data::TcpProtocol::Rfc2217);
+
+        assert!(parse_serve_arg("=0.0.0.0:3001").is_err());
+        assert!(parse_serve_arg("/dev/ttyUSB0").is_err());
+    }
+
+    #[test]
+    fn test_parse_args_serve_only_serial_ports() {
+        let args = to_args(&["a", "--serve", "a=tcp://0.0.0.0:3001"]);
+        assert_eq!(parse_args(&args).unwrap().servers.len(), 1);
+
+        let args = to_args(&["tcp://h:1", "--serve", "tcp://h:1=0.0.0.0:3001"]);
+        assert!(parse_args(&args).is_err());
+        let args = to_args(&["a", "--serve", "b=0.0.0.0:3001"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_pty() {
+        let args = to_args(&["a", "--pty", "a=/tmp/ttyA"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.ptys[0].port, "a");
+        assert_eq!(arguments.ptys[0].link, "/tmp/ttyA");
+
+        assert!(parse_args(&to_args(&["a", "--pty", "b=/tmp/ttyB"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--pty", "a="])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_bridge() {
+        let args = to_args(&["a", "b", "--bridge", "a=b"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.bridges[0].get_direction_name("a"), "a -> b");
+        assert_eq!(arguments.bridges[0].get_direction_name("b"), "b -> a");
+
+        assert!(parse_args(&to_args(&["a", "--bridge", "a=a"])).is_err());
+        assert!(parse_args(&to_args(&[
+            "a",
+            "udp://0.0.0.0:1",
+            "--bridge",
+            "a=udp://0.0.0.0:1"
+        ]))
+        .is_err());
+        assert!(parse_args(&to_args(&[
+            "a", "b", "c", "--bridge", "a=b", "--bridge", "c=a"
+        ]))
+        .is_err());
+        assert!(parse_args(&to_args(&["a", "b", "--bridge", "a=b", "--read-only", "b"])).is_err());
+        assert!(parse_args(&to_args(&["a", "b", "--bridge", "a=b", "--group", "g=a"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_can() {
+        let args = to_args(&["can:vcan0", "--dbc", "can:vcan0=car.dbc"]);
+        let arguments = parse_args(&args).unwrap();
+        match &arguments.ports[0] {
+            data::SourceSettings::Can(settings) => {
+                assert_eq!(settings.interface, "vcan0");
+                assert_eq!(settings.dbc.as_deref(), Some("car.dbc"));
+            }
+            _ => panic!("Expected a CAN source"),
+        }
+        assert_eq!(arguments.ports[0].get_name(), "can:vcan0");
+
+        assert!(parse_args(&to_args(&["can:"])).is_err());
+        assert!(parse_args(&to_args(&["can:vcan0", "--dbc", "can:vcan1=car.dbc"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--dbc", "a=car.dbc"])).is_err());
+        assert!(parse_args(&to_args(&["can:vcan0", "--dbc", "can:vcan0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_unix_and_publish() {
+        let args = to_args(&["unix:/tmp/uart.sock", "--publish", "/tmp/drfish.sock"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.ports[0].get_name(), "unix:/tmp/uart.sock");
+        assert_eq!(arguments.publish_path.as_deref(), Some("/tmp/drfish.sock"));
+
+        assert!(parse_args(&to_args(&["unix:"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--publish"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_script() {
+        let args = to_args(&[
+            "run",
+            "login.toml",
+            "/dev/ttyACM0:9600",
+            "--config",
+            "a.toml",
+        ]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.script_path.as_deref(), Some("login.toml"));
+        assert_eq!(arguments.ports[0].get_name(), "/dev/ttyACM0");
+        assert_eq!(arguments.config_path.as_deref(), Some("a.toml"));
+        assert_eq!(parse_args(&to_args(&["a"])).unwrap().script_path, None);
+
+        assert!(parse_args(&to_args(&["run"])).is_err());
+        assert!(parse_args(&to_args(&["run", "--config", "a.toml"])).is_err());
+        assert!(parse_args(&to_args(&["run", "login.toml", "replay", "a.txt"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_replay() {
+        let args = to_args(&["replay", "log.txt", "--speed", "0.5", "--format", "raw"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.ports.len(), 1);
+        match &arguments.ports[0] {
+            data::SourceSettings::Replay(settings) => {
+                assert_eq!(settings.path, "log.txt");
+                assert_eq!(settings.speed, 0.5);
+                assert_eq!(settings.format, data::LogFormat::Raw);
+                assert_eq!(settings.baud_rate, DEFAULT_BAUD_RATE);
+            }
+            source => panic!("Unexpected source: {:?}", source),
+        }
+
+        assert!(parse_args(&to_args(&["replay"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "b.txt"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--speed", "0"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--speed", "1e-20"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--speed", "128"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--speed", "NaN"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--speed", "64"])).is_ok());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--format", "csv"])).is_err());
+        assert!(parse_args(&to_args(&["replay", "a.txt", "--group", "g=a"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_tcp_in_group() {
+        let args = to_args(&[
+            "a",
+            "tcp://10.0.0.1:2000",
+            "--group",
+            "rack=a,tcp://10.0.0.1:2000",
+        ]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.ports[1].get_name(), "tcp://10.0.0.1:2000");
+    }
+
+    #[test]
+    fn test_parse_group_arg() {
+        let result = parse_group_arg("rack=/dev/ttyUSB0,/dev/ttyUSB1");
+        assert!(result.is_ok());
+        let group = result.unwrap();
+        assert_eq!(group.name, "rack");
+        assert_eq!(group.ports, vec!["/dev/ttyUSB0", "/dev/ttyUSB1"]);
+    }
+
+    #[test]
+    fn test_parse_group_arg_invalid() {
+        assert!(parse_group_arg("rack").is_err());
+        assert!(parse_group_arg("=/dev/ttyUSB0").is_err());
+        assert!(parse_group_arg("rack=").is_err());
+    }
+
+    #[test]
+    fn test_parse_args_with_group() {
+        let args = to_args(&["a:9600", "--group", "rack=a,b", "b"]);
+        let result = parse_args(&args);
+        assert!(result.is_ok());
+        let arguments = result.unwrap();
+        assert_eq!(arguments.ports.len(), 2);
+        assert_eq!(arguments.groups.len(), 1);
+        assert_eq!(arguments.groups[0].ports, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_parse_args_group_unknown_port() {
+        let args = to_args(&["a", "--group", "rack=a,c"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_with_alias_and_read_only() {
+        let args = to_args(&["a", "b", "--alias", "esp=a", "--read-only", "b"]);
+        let result = parse_args(&args);
+        assert!(result.is_ok());
+        let arguments = result.unwrap();
+        assert_eq!(arguments.aliases[0].name, "esp");
+        assert_eq!(arguments.aliases[0].port, "a");
+        assert_eq!(arguments.read_only_ports, vec!["b"]);
+        assert!(arguments.config_path.is_none());
+    }
+
+    #[test]
+    fn test_parse_args_config_path() {
+        let args = to_args(&["--config", "drfish.toml", "a"]);
+        let arguments = parse_args(&args).unwrap();
+        assert_eq!(arguments.config_path.unwrap(), "drfish.toml");
+        assert_eq!(arguments.ports[0].get_name(), "a");
+    }
+
+    #[test]
+    fn test_parse_args_invalid_alias_and_read_only() {
+        assert!(parse_args(&to_args(&["a", "--alias", "esp"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--alias", "esp=b"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--read-only", "b"])).is_err());
+        assert!(parse_args(&to_args(&["a", "--read-only"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_read_only_port_in_group() {
+        let args = to_args(&["a", "b", "--group", "rack=a,b", "--read-only", "b"]);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_default_port() {
+        let result = parse_args(&[]);
+        assert!(result.is_ok());
+        let arguments = result.unwrap();
+        assert_eq!(arguments.ports.len(), 1);
+        assert_eq!(arguments.ports[0].get_name(), DEFAULT_SERIAL_PORT);
+    }
 }
@@ -0,0 +1,194 @@
+use crate::can;
+use crate::data;
+
+use std::fmt;
+
+const DEFAULT_PULSE_DURATION_MS: u64 = 100;
+const DEFAULT_BREAK_DURATION_MS: u64 = 250;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlLine {
+    Dtr,
+    Rts,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LineAction {
+    Set,
+    Clear,
+    /// Sets the line and clears it after the given time.
+    Pulse(std::time::Duration),
+}
+
+/// Command typed in the command prompt, bound to a key or used as a step of a sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    ControlLine(ControlLine, LineAction),
+    Sleep(std::time::Duration),
+    Sequence(String),
+    Break(std::time::Duration),
+    Configure(data::PortSetting),
+    /// Frame sent on a CAN interface
+    SendFrame(can::CanFrame),
+}
+
+fn parse_duration_ms(arg: &str) -> Result<std::time::Duration, String> {
+    match arg.parse::<u64>() {
+        Ok(ms) => Ok(std::time::Duration::from_millis(ms)),
+        Err(_) => Err(format!("Invalid duration: {}", arg)),
+    }
+}
+
+impl Command {
+    /// Parses commands such as "dtr on", "rts pulse 50", "sleep 100",
+    /// "sequence esp32-bootloader", "break 500", "baud 921600" or "can 123#DEADBEEF".
+    pub fn parse(text: &str) -> Result<Command, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let command = match words.as_slice() {
+            [line @ ("dtr" | "rts"), action, args @ ..] => {
+                let line = match *line {
+                    "dtr" => ControlLine::Dtr,
+                    _ => ControlLine::Rts,
+                };
+                let action = match (*action, args) {
+                    ("on", []) => LineAction::Set,
+                    ("off", []) => LineAction::Clear,
+                    ("pulse", []) => LineAction::Pulse(std::time::Duration::from_millis(
+                        DEFAULT_PULSE_DURATION_MS,
+                    )),
+                    ("pulse", [duration]) => LineAction::Pulse(parse_duration_ms(duration)?),
+                    _ => return Err(format!("Invalid control line command: {}", text)),
+                };
+                Command::ControlLine(line, action)
+            }
+            ["sleep", duration] => Command::Sleep(parse_duration_ms(duration)?),
+            ["sequence", name] => Command::Sequence(name.to_string()),
+            ["break"] => {
+                Command::Break(std::time::Duration::from_millis(DEFAULT_BREAK_DURATION_MS))
+            }
+            ["break", duration] => Command::Break(parse_duration_ms(duration)?),
+            [name @ ("baud" | "parity" | "flow" | "databits" | "stopbits"), value] => {
+                Command::Configure(data::PortSetting::parse(name, value)?)
+            }
+            ["can", frame] => Command::SendFrame(can::CanFrame::parse(frame)?),
+            [] => return Err("Empty command".to_string()),
+            _ => return Err(format!("Unknown command: {}", text)),
+        };
+
+        Ok(command)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::ControlLine(line, action) => {
+                let line = match line {
+                    ControlLine::Dtr => "dtr",
+                    ControlLine::Rts => "rts",
+                };
+                match action {
+                    LineAction::Set => write!(f, "{} on", line),
+                    LineAction::Clear => write!(f, "{} off", line),
+                    LineAction::Pulse(duration) => {
+                        write!(f, "{} pulse {}", line, duration.as_millis())
+                    }
+                }
+            }
+            Command::Sleep(duration) => write!(f, "sleep {}", duration.as_millis()),
+            Command::Sequence(name) => write!(f, "sequence {}", name),
+            Command::Break(duration) => write!(f, "break {}", duration.as_millis()),
+            Command::Configure(setting) => write!(f, "{}", setting),
+            Command::SendFrame(frame) => write!(f, "can {}", frame.get_cansend_notation()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_control_line_commands() {
+        assert_eq!(
+            Command::parse("dtr on").unwrap(),
+            Command::ControlLine(ControlLine::Dtr, LineAction::Set)
+        );
+        assert_eq!(
+            Command::parse("rts off").unwrap(),
+            Command::ControlLine(ControlLine::Rts, LineAction::Clear)
+        );
+        assert_eq!(
+            Command::parse(" dtr  pulse 250 ").unwrap(),
+            Command::ControlLine(
+                ControlLine::Dtr,
+                LineAction::Pulse(std::time::Duration::from_millis(250))
+            )
+        );
+        assert_eq!(
+            Command::parse("rts pulse").unwrap(),
+            Command::ControlLine(
+                ControlLine::Rts,
+                LineAction::Pulse(std::time::Duration::from_millis(100))
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_other_commands() {
+        assert_eq!(
+            Command::parse("sleep 50").unwrap(),
+            Command::Sleep(std::time::Duration::from_millis(50))
+        );
+        assert_eq!(
+            Command::parse("sequence arduino-reset").unwrap(),
+            Command::Sequence("arduino-reset".to_string())
+        );
+        assert_eq!(
+            Command::parse("break").unwrap(),
+            Command::Break(std::time::Duration::from_millis(250))
+        );
+        assert_eq!(
+            Command::parse("break 1000").unwrap(),
+            Command::Break(std::time::Duration::from_millis(1000))
+        );
+        assert_eq!(
+            Command::parse("baud 921600").unwrap(),
+            Command::Configure(data::PortSetting::BaudRate(921_600))
+        );
+        assert_eq!(
+            Command::parse("can 123#01").unwrap(),
+            Command::SendFrame(can::CanFrame::parse("123#01").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_commands() {
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("dtr").is_err());
+        assert!(Command::parse("dtr up").is_err());
+        assert!(Command::parse("dtr on 10").is_err());
+        assert!(Command::parse("sleep soon").is_err());
+        assert!(Command::parse("break 1 2").is_err());
+        assert!(Command::parse("baud").is_err());
+        assert!(Command::parse("parity weird").is_err());
+        assert!(Command::parse("fly away").is_err());
+        assert!(Command::parse("can 123").is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for text in [
+            "dtr on",
+            "rts off",
+            "dtr pulse 20",
+            "sleep 5",
+            "sequence x",
+            "break 100",
+            "flow hardware",
+            "can 1F334455#R",
+        ] {
+            assert_eq!(Command::parse(text).unwrap().to_string(), text);
+        }
+    }
+}
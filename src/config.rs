@@ -0,0 +1,185 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+const CONFIG_DIR_NAME: &str = "drfish";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Settings read from the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keymap: KeymapConfig,
+    /// Named lists of commands, e.g. "my-reset" = ["dtr on", "sleep 100", "dtr off"].
+    pub sequences: BTreeMap<String, Vec<String>>,
+    pub diff: DiffConfig,
+    /// Rules reacting to the received lines, in the [[triggers]] tables.
+    pub triggers: Vec<TriggerConfig>,
+    /// Paths of Rhai scripts every received message passes through, in order.
+    pub hooks: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    /// Key starting every drfish command, e.g. "C-a".
+    pub prefix: Option<String>,
+    /// Keys pressed after the prefix mapped to action names.
+    pub bindings: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffConfig {
+    /// Regular expressions of volatile parts of the lines, ignored when comparing logs.
+    pub masks: Vec<String>,
+}
+
+/// Actions run when a received line matches a pattern.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// Shown in the log when the trigger fires, the pattern by default.
+    pub name: Option<String>,
+    /// Regular expression searched for in every line.
+    pub pattern: String,
+    /// Names of the sources the lines are checked for, all sources if empty.
+    pub sources: Vec<String>,
+    /// Milliseconds after firing during which the trigger is not fired again.
+    pub cooldown: u64,
+    /// Ring the terminal bell and mark the log.
+    pub notify: bool,
+    /// Text written to the target.
+    pub send: Option<String>,
+    /// drfish commands run on the target, e.g. "sequence esp32-reset".
+    pub commands: Vec<String>,
+    /// Writer receiving the text and commands, the source of the line by default.
+    pub target: Option<String>,
+    /// Shell command run with the matching line in its environment.
+    pub run: Option<String>,
+    /// End the session.
+    pub stop: bool,
+    /// End the session and exit with this code.
+    pub exit_code: Option<i32>,
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Config, String> {
+        toml::from_str(content).map_err(|e| format!("Invalid configuration: {}", e))
+    }
+
+    /// Loads the configuration from the given path, or from the default location
+    /// if no path is given. A missing default configuration file is not an error.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let path = match path {
+            Some(p) => std::path::PathBuf::from(p),
+            None => match get_default_config_path() {
+                Some(p) if p.exists() => p,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Failed to read configuration file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Config::parse(&content)
+    }
+}
+
+/// $XDG_CONFIG_HOME/drfish/config.toml or ~/.config/drfish/config.toml
+fn get_default_config_path() -> Option<std::path::PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => std::path::PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keymap() {
+        let content = r#"
+            [keymap]
+            prefix = "C-b"
+
+            [keymap.bindings]
+            q = "quit"
+            "C-n" = "next-writer"
+        "#;
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.keymap.prefix.unwrap(), "C-b");
+        assert_eq!(config.keymap.bindings["q"], "quit");
+        assert_eq!(config.keymap.bindings["C-n"], "next-writer");
+    }
+
+    #[test]
+    fn test_parse_sequences() {
+        let content = r#"
+            [sequences]
+            my-reset = ["dtr on", "sleep 100", "dtr off"]
+        "#;
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.sequences["my-reset"].len(), 3);
+    }
+
+    #[test]
+    fn test_parse_diff_masks() {
+        let content = r#"
+            [diff]
+            masks = ['heap: \d+ bytes', 'ip=\S+']
+        "#;
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.diff.masks, ["heap: \\d+ bytes", "ip=\\S+"]);
+    }
+
+    #[test]
+    fn test_parse_triggers() {
+        let content = r#"
+            [[triggers]]
+            pattern = "Guru Meditation"
+            sources = ["/dev/ttyUSB0"]
+            cooldown = 5000
+            notify = true
+            commands = ["sequence esp32-reset"]
+
+            [[triggers]]
+            pattern = "TEST PASSED"
+            exit_code = 0
+        "#;
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.triggers.len(), 2);
+        assert_eq!(config.triggers[0].sources, ["/dev/ttyUSB0"]);
+        assert_eq!(config.triggers[0].cooldown, 5000);
+        assert!(config.triggers[0].notify);
+        assert_eq!(config.triggers[1].exit_code, Some(0));
+        assert!(!config.triggers[1].stop);
+    }
+
+    #[test]
+    fn test_parse_hooks() {
+        let content = r#"
+            hooks = ["mask.rhai", "/etc/drfish/stats.rhai"]
+        "#;
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.hooks, ["mask.rhai", "/etc/drfish/stats.rhai"]);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let config = Config::parse("").unwrap();
+        assert!(config.keymap.prefix.is_none());
+        assert!(config.keymap.bindings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_unknown_field() {
+        assert!(Config::parse("[keymap]\nprefx = \"C-b\"").is_err());
+    }
+}
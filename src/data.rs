@@ -1,5 +1,334 @@
+use std::fmt;
+
 #[derive(Clone, Debug)]
 pub struct SerialPortSettings {
     pub path: String,
     pub baud_rate: u32,
+    /// Detect the baud rate before monitoring, `baud_rate` is used if that fails.
+    pub autobaud: bool,
+}
+
+/// How the data is carried over a TCP connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TcpProtocol {
+    Raw,
+    /// Telnet with the COM port control option, for remote baud rate and control lines
+    Rfc2217,
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpSettings {
+    /// HOST:PORT
+    pub address: String,
+    pub protocol: TcpProtocol,
+}
+
+impl TcpSettings {
+    pub fn get_name(&self) -> String {
+        match self.protocol {
+            TcpProtocol::Raw => format!("tcp://{}", self.address),
+            TcpProtocol::Rfc2217 => format!("rfc2217://{}", self.address),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UdpSettings {
+    /// Local HOST:PORT the datagrams are received on
+    pub address: String,
+}
+
+impl UdpSettings {
+    pub fn get_name(&self) -> String {
+        format!("udp://{}", self.address)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessSettings {
+    /// Run with sh -c
+    pub command: String,
+}
+
+impl ProcessSettings {
+    pub fn get_name(&self) -> String {
+        format!("cmd:{}", self.command)
+    }
+
+    /// Name of the source of the lines written to stderr.
+    pub fn get_stderr_name(&self) -> String {
+        format!("{} (stderr)", self.get_name())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FileSettings {
+    /// Regular file or named pipe
+    pub path: String,
+}
+
+impl FileSettings {
+    pub fn get_name(&self) -> String {
+        format!("file:{}", self.path)
+    }
+}
+
+/// Unix domain stream socket, e.g. the UART of a simulator.
+#[derive(Clone, Debug)]
+pub struct UnixSettings {
+    pub path: String,
+}
+
+impl UnixSettings {
+    pub fn get_name(&self) -> String {
+        format!("unix:{}", self.path)
+    }
+}
+
+/// SocketCAN network interface, e.g. vcan0.
+#[derive(Clone, Debug)]
+pub struct CanSettings {
+    pub interface: String,
+    /// DBC file for decoding the signals of the received frames
+    pub dbc: Option<String>,
+}
+
+impl CanSettings {
+    pub fn get_name(&self) -> String {
+        format!("can:{}", self.interface)
+    }
+}
+
+/// Format of a recorded session.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Guessed from the first line
+    Auto,
+    /// Written by the session logger
+    Text,
+    /// Messages as published with --publish
+    JsonLines,
+    /// Bytes captured from a port, without timestamps
+    Raw,
+}
+
+/// Recorded session replayed as if it was received live.
+#[derive(Clone, Debug)]
+pub struct ReplaySettings {
+    pub path: String,
+    pub format: LogFormat,
+    /// Multiplier of the original pace
+    pub speed: f64,
+    /// Pace of raw captures, which have no timestamps
+    pub baud_rate: u32,
+}
+
+impl ReplaySettings {
+    pub fn get_name(&self) -> String {
+        format!("replay:{}", self.path)
+    }
+}
+
+/// Local port shared with other machines over TCP.
+#[derive(Clone, Debug)]
+pub struct PortServerSettings {
+    pub port: String,
+    pub listen: TcpSettings,
+}
+
+/// Two serial ports forwarding everything they receive to each other.
+#[derive(Clone, Debug)]
+pub struct BridgeSettings {
+    pub first: String,
+    pub second: String,
+}
+
+impl BridgeSettings {
+    pub fn contains(&self, port: &str) -> bool {
+        self.first == port || self.second == port
+    }
+
+    /// Name of the data received on the port and forwarded to the other one.
+    pub fn get_direction_name(&self, port: &str) -> String {
+        if port == self.first {
+            format!("{} -> {}", self.first, self.second)
+        } else {
+            format!("{} -> {}", self.second, self.first)
+        }
+    }
+}
+
+/// Pseudo-terminal relaying to a local port, for other programs to use the port.
+#[derive(Clone, Debug)]
+pub struct PtySettings {
+    pub port: String,
+    /// Symbolic link pointing at the terminal
+    pub link: String,
+}
+
+impl PtySettings {
+    /// Name of the source of the data written to the terminal.
+    pub fn get_tx_name(&self) -> String {
+        format!("{} (tx)", self.port)
+    }
+}
+
+/// Anything drfish can read logs from.
+#[derive(Clone, Debug)]
+pub enum SourceSettings {
+    Serial(SerialPortSettings),
+    Tcp(TcpSettings),
+    Udp(UdpSettings),
+    Process(ProcessSettings),
+    File(FileSettings),
+    Can(CanSettings),
+    Unix(UnixSettings),
+    Replay(ReplaySettings),
+}
+
+impl SourceSettings {
+    /// Name identifying the source in the logs and when selecting writers.
+    pub fn get_name(&self) -> String {
+        match self {
+            SourceSettings::Serial(settings) => settings.path.clone(),
+            SourceSettings::Tcp(settings) => settings.get_name(),
+            SourceSettings::Udp(settings) => settings.get_name(),
+            SourceSettings::Process(settings) => settings.get_name(),
+            SourceSettings::File(settings) => settings.get_name(),
+            SourceSettings::Can(settings) => settings.get_name(),
+            SourceSettings::Unix(settings) => settings.get_name(),
+            SourceSettings::Replay(settings) => settings.get_name(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PortGroup {
+    pub name: String,
+    pub ports: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PortAlias {
+    pub name: String,
+    pub port: String,
+}
+
+/// Serial port parameter changed while the port is open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortSetting {
+    BaudRate(u32),
+    Parity(tokio_serial::Parity),
+    FlowControl(tokio_serial::FlowControl),
+    DataBits(tokio_serial::DataBits),
+    StopBits(tokio_serial::StopBits),
+}
+
+impl PortSetting {
+    /// Parses a setting name ("baud", "parity", "flow", "databits" or "stopbits")
+    /// and its value.
+    pub fn parse(name: &str, value: &str) -> Result<PortSetting, String> {
+        let setting = match (name, value) {
+            ("baud", _) => match value.parse::<u32>() {
+                Ok(b) if b > 0 => PortSetting::BaudRate(b),
+                _ => return Err(format!("Invalid baud rate: {}", value)),
+            },
+            ("parity", "none") => PortSetting::Parity(tokio_serial::Parity::None),
+            ("parity", "odd") => PortSetting::Parity(tokio_serial::Parity::Odd),
+            ("parity", "even") => PortSetting::Parity(tokio_serial::Parity::Even),
+            ("flow", "none") => PortSetting::FlowControl(tokio_serial::FlowControl::None),
+            ("flow", "software") => PortSetting::FlowControl(tokio_serial::FlowControl::Software),
+            ("flow", "hardware") => PortSetting::FlowControl(tokio_serial::FlowControl::Hardware),
+            ("databits", "5") => PortSetting::DataBits(tokio_serial::DataBits::Five),
+            ("databits", "6") => PortSetting::DataBits(tokio_serial::DataBits::Six),
+            ("databits", "7") => PortSetting::DataBits(tokio_serial::DataBits::Seven),
+            ("databits", "8") => PortSetting::DataBits(tokio_serial::DataBits::Eight),
+            ("stopbits", "1") => PortSetting::StopBits(tokio_serial::StopBits::One),
+            ("stopbits", "2") => PortSetting::StopBits(tokio_serial::StopBits::Two),
+            ("parity" | "flow" | "databits" | "stopbits", _) => {
+                return Err(format!("Invalid {} value: {}", name, value));
+            }
+            _ => return Err(format!("Unknown port setting: {}", name)),
+        };
+
+        Ok(setting)
+    }
+}
+
+impl fmt::Display for PortSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortSetting::BaudRate(baud_rate) => write!(f, "baud {}", baud_rate),
+            PortSetting::Parity(parity) => {
+                let value = match parity {
+                    tokio_serial::Parity::None => "none",
+                    tokio_serial::Parity::Odd => "odd",
+                    tokio_serial::Parity::Even => "even",
+                };
+                write!(f, "parity {}", value)
+            }
+            PortSetting::FlowControl(flow_control) => {
+                let value = match flow_control {
+                    tokio_serial::FlowControl::None => "none",
+                    tokio_serial::FlowControl::Software => "software",
+                    tokio_serial::FlowControl::Hardware => "hardware",
+                };
+                write!(f, "flow {}", value)
+            }
+            PortSetting::DataBits(data_bits) => write!(f, "databits {}", u8::from(*data_bits)),
+            PortSetting::StopBits(stop_bits) => write!(f, "stopbits {}", u8::from(*stop_bits)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_setting() {
+        assert_eq!(
+            PortSetting::parse("baud", "921600").unwrap(),
+            PortSetting::BaudRate(921_600)
+        );
+        assert_eq!(
+            PortSetting::parse("parity", "even").unwrap(),
+            PortSetting::Parity(tokio_serial::Parity::Even)
+        );
+        assert_eq!(
+            PortSetting::parse("flow", "hardware").unwrap(),
+            PortSetting::FlowControl(tokio_serial::FlowControl::Hardware)
+        );
+        assert_eq!(
+            PortSetting::parse("databits", "7").unwrap(),
+            PortSetting::DataBits(tokio_serial::DataBits::Seven)
+        );
+        assert_eq!(
+            PortSetting::parse("stopbits", "2").unwrap(),
+            PortSetting::StopBits(tokio_serial::StopBits::Two)
+        );
+    }
+
+    #[test]
+    fn test_parse_port_setting_invalid() {
+        assert!(PortSetting::parse("baud", "fast").is_err());
+        assert!(PortSetting::parse("baud", "0").is_err());
+        assert!(PortSetting::parse("parity", "mark").is_err());
+        assert!(PortSetting::parse("databits", "9").is_err());
+        assert!(PortSetting::parse("speed", "1").is_err());
+    }
+
+    #[test]
+    fn test_port_setting_display_round_trip() {
+        for text in [
+            "baud 9600",
+            "parity odd",
+            "flow software",
+            "databits 8",
+            "stopbits 1",
+        ] {
+            let (name, value) = text.split_once(' ').unwrap();
+            assert_eq!(PortSetting::parse(name, value).unwrap().to_string(), text);
+        }
+    }
 }
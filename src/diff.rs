@@ -0,0 +1,374 @@
+use super::cli::DEFAULT_BAUD_RATE;
+use super::config;
+use super::data::LogFormat;
+use super::log_monitor::MonitorMessage;
+use super::session_log;
+
+use indexmap::IndexMap;
+use similar::{DiffOp, DiffTag};
+
+pub const COMMAND: &str = "diff";
+const DEFAULT_CONTEXT: usize = 3;
+/// Replaces the masked parts of the lines.
+const MASK: &str = "*";
+/// Timestamps, addresses and counters commonly printed by firmware. Applied in
+/// order, so the Zephyr ticks come before the times of day they contain.
+const DEFAULT_MASKS: [&str; 5] = [
+    // ESP-IDF and Zephyr ticks, e.g. I (1234) or [00:00:01.123,456]
+    r"\(\d+\)|\[\d{2}:\d{2}:\d{2}\.\d{3},\d{3}\]",
+    // times of day, e.g. 12:00:01.123
+    r"\b\d{1,2}:\d{2}:\d{2}(\.\d+)?\b",
+    // kernel uptime, e.g. [    1.234567]
+    r"\[\s*\d+\.\d+\]",
+    // addresses, e.g. 0x3ffb0000
+    r"\b0x[0-9a-fA-F]+\b",
+    // long hexadecimal numbers, e.g. hashes and unprefixed addresses
+    r"\b[0-9a-fA-F]{8,}\b",
+];
+
+/// Volatile parts of the lines, replaced before comparing them.
+struct Masks {
+    patterns: Vec<regex::Regex>,
+}
+
+impl Masks {
+    fn new(patterns: &[String]) -> Result<Masks, String> {
+        let patterns = patterns
+            .iter()
+            .map(|p| regex::Regex::new(p).map_err(|e| format!("Invalid mask {}: {}", p, e)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Masks { patterns })
+    }
+
+    fn apply(&self, line: &str) -> String {
+        let mut line = line.to_string();
+        for pattern in &self.patterns {
+            line = pattern.replace_all(&line, MASK).into_owned();
+        }
+        line
+    }
+}
+
+/// Number of lines that differ, a removed line replaced by an inserted one is
+/// counted as changed.
+#[derive(Debug, Default, PartialEq)]
+struct DiffCounts {
+    inserted: usize,
+    removed: usize,
+    changed: usize,
+}
+
+impl DiffCounts {
+    fn add(&mut self, op: &DiffOp) {
+        match op.as_tag_tuple() {
+            (DiffTag::Insert, _, new) => self.inserted += new.len(),
+            (DiffTag::Delete, old, _) => self.removed += old.len(),
+            (DiffTag::Replace, old, new) => {
+                self.changed += old.len().min(new.len());
+                self.inserted += new.len().saturating_sub(old.len());
+                self.removed += old.len().saturating_sub(new.len());
+            }
+            (DiffTag::Equal, _, _) => {}
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == DiffCounts::default()
+    }
+}
+
+/// Lines of every source of a log, in the order the sources first appear.
+fn group_by_source(log: session_log::SessionLog) -> IndexMap<String, Vec<String>> {
+    let mut sources: IndexMap<String, Vec<String>> = IndexMap::new();
+    for msg in log.messages {
+        if let MonitorMessage::Log(log) = msg {
+            sources
+                .entry(log.source_name)
+                .or_default()
+                .push(log.message);
+        }
+    }
+    sources
+}
+
+/// Compares the lines of a source and formats the differences as hunks, showing
+/// the original lines with the given number of unchanged lines around them.
+fn diff_lines(
+    old: &[String],
+    new: &[String],
+    masks: &Masks,
+    context: usize,
+) -> (DiffCounts, String) {
+    let old_masked: Vec<String> = old.iter().map(|l| masks.apply(l)).collect();
+    let new_masked: Vec<String> = new.iter().map(|l| masks.apply(l)).collect();
+    let ops = similar::capture_diff_slices(similar::Algorithm::Myers, &old_masked, &new_masked);
+
+    let mut counts = DiffCounts::default();
+    let mut text = String::new();
+    for group in similar::group_diff_ops(ops, context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        text += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            first.old_range().start + 1,
+            last.old_range().end - first.old_range().start,
+            first.new_range().start + 1,
+            last.new_range().end - first.new_range().start
+        );
+
+        for op in &group {
+            counts.add(op);
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            match tag {
+                DiffTag::Equal => {
+                    for line in &old[old_range] {
+                        text += &format!("  {}\n", line);
+                    }
+                }
+                _ => {
+                    for line in &old[old_range] {
+                        text += &format!("- {}\n", line);
+                    }
+                    for line in &new[new_range] {
+                        text += &format!("+ {}\n", line);
+                    }
+                }
+            }
+        }
+    }
+
+    (counts, text)
+}
+
+fn format_counts(counts: &DiffCounts) -> String {
+    if counts.is_empty() {
+        return "identical".to_string();
+    }
+    format!(
+        "{} inserted, {} removed, {} changed",
+        counts.inserted, counts.removed, counts.changed
+    )
+}
+
+/// Compares two logs source by source.
+fn diff_logs(
+    old: session_log::SessionLog,
+    new: session_log::SessionLog,
+    masks: &Masks,
+    context: usize,
+) -> String {
+    let old = group_by_source(old);
+    let new = group_by_source(new);
+    let names = old
+        .keys()
+        .chain(new.keys().filter(|n| !old.contains_key(*n)));
+
+    let mut text = String::new();
+    for name in names {
+        let no_lines = Vec::new();
+        let old_lines = old.get(name).unwrap_or(&no_lines);
+        let new_lines = new.get(name).unwrap_or(&no_lines);
+        let (counts, hunks) = diff_lines(old_lines, new_lines, masks, context);
+        text += &format!("=== {}: {}\n{}", name, format_counts(&counts), hunks);
+    }
+    text
+}
+
+#[derive(Debug, PartialEq)]
+struct DiffArguments {
+    old_path: String,
+    new_path: String,
+    masks: Vec<String>,
+    default_masks: bool,
+    context: usize,
+    config_path: Option<String>,
+}
+
+/// parses the arguments following the diff command:
+/// OLD_LOG_PATH NEW_LOG_PATH [--mask REGEX]... [--no-default-masks] [--context LINES]
+/// [--config PATH]
+fn parse_args(args: &[String]) -> Result<DiffArguments, String> {
+    let mut paths = Vec::new();
+    let mut masks = Vec::new();
+    let mut default_masks = true;
+    let mut context = DEFAULT_CONTEXT;
+    let mut config_path = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--mask" => masks.push(args_iter.next().ok_or("Missing value for --mask")?.clone()),
+            "--no-default-masks" => default_masks = false,
+            "--context" => {
+                let value = args_iter.next().ok_or("Missing value for --context")?;
+                context = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid number of context lines: {}", value))?;
+            }
+            "--config" => {
+                config_path = Some(
+                    args_iter
+                        .next()
+                        .ok_or("Missing value for --config")?
+                        .clone(),
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let [old_path, new_path]: [String; 2] = paths
+        .try_into()
+        .map_err(|_| "Two logs must be compared".to_string())?;
+
+    Ok(DiffArguments {
+        old_path,
+        new_path,
+        masks,
+        default_masks,
+        context,
+        config_path,
+    })
+}
+
+/// Compares the logs given as arguments and prints their differences.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = parse_args(args)?;
+    let config = config::Config::load(arguments.config_path.as_deref())?;
+
+    let mut patterns: Vec<String> = Vec::new();
+    if arguments.default_masks {
+        patterns.extend(DEFAULT_MASKS.iter().map(|m| m.to_string()));
+    }
+    patterns.extend(config.diff.masks);
+    patterns.extend(arguments.masks);
+    let masks = Masks::new(&patterns)?;
+
+    let old = session_log::read(&arguments.old_path, LogFormat::Auto, DEFAULT_BAUD_RATE)?;
+    let new = session_log::read(&arguments.new_path, LogFormat::Auto, DEFAULT_BAUD_RATE)?;
+
+    print!("--- {}\n+++ {}\n", arguments.old_path, arguments.new_path);
+    print!("{}", diff_logs(old, new, &masks, arguments.context));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::Log;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn create_log(lines: &[(&str, &str)]) -> session_log::SessionLog {
+        let messages = lines
+            .iter()
+            .map(|(source_name, message)| {
+                MonitorMessage::Log(Log {
+                    source_name: source_name.to_string(),
+                    message: message.to_string(),
+                    timestamp: chrono::Local::now(),
+                })
+            })
+            .collect();
+        session_log::SessionLog {
+            messages,
+            skipped: 0,
+        }
+    }
+
+    fn get_default_masks() -> Masks {
+        let patterns: Vec<String> = DEFAULT_MASKS.iter().map(|m| m.to_string()).collect();
+        Masks::new(&patterns).unwrap()
+    }
+
+    #[test]
+    fn test_default_masks() {
+        let masks = get_default_masks();
+        assert_eq!(
+            masks.apply("I (1234) heap: free at 0x3ffb0000, build deadbeef42"),
+            "I * heap: free at *, build *"
+        );
+        assert_eq!(
+            masks.apply("[    1.234567] uptime 12:00:01.5"),
+            "* uptime *"
+        );
+        assert_eq!(
+            masks.apply("[00:00:01.123,456] <inf> main: ready"),
+            "* <inf> main: ready"
+        );
+        assert_eq!(masks.apply("version 1.2 ready"), "version 1.2 ready");
+        assert!(Masks::new(&["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_diff_logs() {
+        let old = create_log(&[
+            ("a", "I (10) boot"),
+            ("a", "I (20) init"),
+            ("a", "I (30) wifi: start"),
+            ("b", "gateway up"),
+            ("a", "I (40) wifi: timeout"),
+            ("a", "I (50) done"),
+        ]);
+        let new = create_log(&[
+            ("a", "I (11) boot"),
+            ("a", "I (21) init"),
+            ("a", "I (31) wifi: start"),
+            ("a", "I (41) wifi: connected"),
+            ("a", "I (42) mqtt: connected"),
+            ("a", "I (51) done"),
+            ("c", "sensor ready"),
+        ]);
+
+        let text = diff_logs(old, new, &get_default_masks(), 1);
+        assert_eq!(
+            text,
+            "=== a: 1 inserted, 0 removed, 1 changed\n\
+             @@ -3,3 +3,4 @@\n\
+             \x20 I (30) wifi: start\n\
+             - I (40) wifi: timeout\n\
+             + I (41) wifi: connected\n\
+             + I (42) mqtt: connected\n\
+             \x20 I (50) done\n\
+             === b: 0 inserted, 1 removed, 0 changed\n\
+             @@ -1,1 +1,0 @@\n\
+             - gateway up\n\
+             === c: 1 inserted, 0 removed, 0 changed\n\
+             @@ -1,0 +1,1 @@\n\
+             + sensor ready\n"
+        );
+
+        let log = create_log(&[("a", "I (10) boot")]);
+        let same = create_log(&[("a", "I (99) boot")]);
+        assert_eq!(
+            diff_logs(log, same, &get_default_masks(), 3),
+            "=== a: identical\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = to_args(&["a.txt", "b.txt", "--mask", "id=\\d+", "--context", "5"]);
+        assert_eq!(
+            parse_args(&args),
+            Ok(DiffArguments {
+                old_path: "a.txt".to_string(),
+                new_path: "b.txt".to_string(),
+                masks: vec!["id=\\d+".to_string()],
+                default_masks: true,
+                context: 5,
+                config_path: None,
+            })
+        );
+
+        assert!(parse_args(&to_args(&["a.txt"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "b.txt", "c.txt"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "b.txt", "--context", "-1"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "b.txt", "--mask"])).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// Turns the -1 returned by a failed libc call into the errno error.
+pub fn check<T: Copy + PartialOrd + Default>(result: T) -> std::io::Result<T> {
+    if result < T::default() {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Registers a non-blocking descriptor with the runtime.
+pub fn register(fd: OwnedFd) -> std::io::Result<AsyncFd<OwnedFd>> {
+    // the descriptor is owned, so it stays open as long as the AsyncFd
+    Ok(unsafe { AsyncFd::register(fd)? })
+}
+
+fn read_now(fd: &impl AsRawFd, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let n = unsafe {
+        libc::read(
+            fd.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    check(n).map(|n| n as usize)
+}
+
+/// Writes what fits without waiting.
+pub fn write_now(fd: &impl AsRawFd, data: &[u8]) -> std::io::Result<usize> {
+    let n = unsafe {
+        libc::write(
+            fd.as_raw_fd(),
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+        )
+    };
+    check(n).map(|n| n as usize)
+}
+
+/// Waits until the descriptor is readable and reads into the buffer.
+pub async fn read_fd(fd: &AsyncFd<OwnedFd>, buffer: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let mut guard = fd.readable().await?;
+        if let Ok(result) = guard.try_io(|fd| read_now(fd, buffer)) {
+            return result;
+        }
+    }
+}
+
+/// Waits until the descriptor is writable and writes the data with a single call.
+pub async fn write_fd(fd: &AsyncFd<OwnedFd>, data: &[u8]) -> std::io::Result<usize> {
+    loop {
+        let mut guard = fd.writable().await?;
+        if let Ok(result) = guard.try_io(|fd| write_now(fd, data)) {
+            return result;
+        }
+    }
+}
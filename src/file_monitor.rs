@@ -0,0 +1,341 @@
+use super::data::FileSettings;
+use super::log_monitor::{send_event, AsyncLogMonitor, MonitorMessage, PortRequest, WriteProxy};
+use super::read_line;
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+const POLL_INTERVAL: u64 = 100;
+
+struct OpenFile {
+    file: File,
+    /// Device and inode, to notice the file being replaced
+    id: (u64, u64),
+    position: u64,
+    is_fifo: bool,
+}
+
+/// Follows a growing file like `tail -F`, reopening it when it is truncated or
+/// replaced, or reads from a named pipe.
+pub struct FileLogMonitor {
+    settings: FileSettings,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    recv_buffer: Vec<u8>,
+    lines: read_line::LineAssembler,
+    current: Option<OpenFile>,
+    missing: bool,
+    input_dropped: bool,
+}
+
+impl FileLogMonitor {
+    pub fn new(settings: FileSettings) -> FileLogMonitor {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        let lines = read_line::LineAssembler::new(settings.get_name());
+
+        FileLogMonitor {
+            settings,
+            write_receiver,
+            write_sender,
+            recv_buffer: vec![0; DEFAULT_BUFFER_SIZE],
+            lines,
+            current: None,
+            missing: false,
+            input_dropped: false,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    /// Files are never written, the input is dropped and reported once.
+    fn handle_write_request(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        if !self.input_dropped {
+            self.input_dropped = true;
+            let description = "File is read-only, input dropped".to_string();
+            self.send_event(description, sender_queue);
+        }
+    }
+
+    /// Opens the file, skipping the content written before if `from_end` is set.
+    /// Pipes are opened in non-blocking mode, so a missing writer does not block.
+    fn open(&self, from_end: bool) -> std::io::Result<OpenFile> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.settings.path)?;
+        let metadata = file.metadata()?;
+        let is_fifo = metadata.file_type().is_fifo();
+
+        let position = if from_end && !is_fifo {
+            file.seek(SeekFrom::End(0))?
+        } else {
+            0
+        };
+
+        Ok(OpenFile {
+            file,
+            id: (metadata.dev(), metadata.ino()),
+            position,
+            is_fifo,
+        })
+    }
+
+    /// Reads everything available, returns true if anything was read.
+    fn read_available(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) -> bool {
+        let mut received = false;
+
+        while let Some(current) = &mut self.current {
+            match current.file.read(&mut self.recv_buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    current.position += n as u64;
+                    received = true;
+                    self.lines.push(&self.recv_buffer[0..n], sender_queue);
+                }
+                // a pipe with a writer, but no data
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.send_event(format!("Failed to read: {}", e), sender_queue);
+                    break;
+                }
+            }
+        }
+
+        received
+    }
+
+    fn reopen(&mut self, description: &str, sender_queue: &UnboundedSender<MonitorMessage>) {
+        // on failure, e.g. when replaced again in the meantime, the next poll retries
+        if let Ok(file) = self.open(false) {
+            self.lines.flush(sender_queue);
+            self.current = Some(file);
+            self.send_event(description.to_string(), sender_queue);
+        }
+    }
+
+    /// Reads the new content and checks whether the file was truncated or replaced.
+    pub fn poll(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        if self.current.is_none() {
+            // a file created after starting is read from its beginning
+            if let Ok(file) = self.open(false) {
+                self.current = Some(file);
+                self.missing = false;
+                self.send_event("File appeared".to_string(), sender_queue);
+            }
+        }
+
+        if !self.read_available(sender_queue) {
+            self.lines.flush(sender_queue);
+        }
+
+        let (id, position) = match &self.current {
+            Some(current) if !current.is_fifo => (current.id, current.position),
+            _ => return,
+        };
+
+        let metadata = match std::fs::metadata(&self.settings.path) {
+            Ok(m) => m,
+            Err(_) => {
+                if !self.missing {
+                    self.missing = true;
+                    let description = "File is gone, waiting for it to reappear".to_string();
+                    self.send_event(description, sender_queue);
+                }
+                return;
+            }
+        };
+        self.missing = false;
+
+        if (metadata.dev(), metadata.ino()) != id {
+            self.reopen("File was replaced, reading the new one", sender_queue);
+            self.read_available(sender_queue);
+        } else if metadata.len() < position {
+            self.reopen("File was truncated, reading from the start", sender_queue);
+            self.read_available(sender_queue);
+        }
+    }
+}
+
+impl AsyncLogMonitor for FileLogMonitor {
+    fn get_common_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        // like tail, only the lines added from now on are logged
+        match self.open(true) {
+            Ok(file) => self.current = Some(file),
+            Err(e) => {
+                self.missing = true;
+                let description = format!("Waiting for the file to appear: {}", e);
+                self.send_event(description, &sender_queue);
+            }
+        }
+
+        let mut poll_interval =
+            tokio::time::interval(std::time::Duration::from_millis(POLL_INTERVAL));
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                _ = poll_interval.tick() => {
+                    self.poll(&sender_queue);
+                }
+
+                request = self.write_receiver.recv() => {
+                    if request.is_some() {
+                        self.handle_write_request(&sender_queue);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{get_temp_path, next_message};
+    use std::io::Write;
+
+    fn get_logs(receiver: &mut UnboundedReceiver<MonitorMessage>) -> Vec<String> {
+        let mut logs = Vec::new();
+        while let Ok(msg) = receiver.try_recv() {
+            if let MonitorMessage::Log(log) = msg {
+                logs.push(log.message);
+            }
+        }
+        logs
+    }
+
+    fn append(path: &str, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follow_truncate_and_rotate() {
+        let path = get_temp_path("follow.log");
+        append(&path, "old\n");
+
+        let mut monitor = FileLogMonitor::new(FileSettings { path: path.clone() });
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        monitor.current = Some(monitor.open(true).unwrap());
+
+        append(&path, "one\ntwo\n");
+        monitor.poll(&sender);
+        assert_eq!(get_logs(&mut receiver), vec!["one", "two"]);
+
+        std::fs::write(&path, "").unwrap();
+        monitor.poll(&sender);
+        append(&path, "three\n");
+        monitor.poll(&sender);
+        assert_eq!(get_logs(&mut receiver), vec!["three"]);
+
+        let rotated = format!("{}.1", path);
+        std::fs::rename(&path, &rotated).unwrap();
+        append(&rotated, "four\n");
+        monitor.poll(&sender);
+        append(&path, "five\n");
+        monitor.poll(&sender);
+        assert_eq!(get_logs(&mut receiver), vec!["four", "five"]);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let path = get_temp_path("missing.log");
+        let mut monitor = FileLogMonitor::new(FileSettings { path: path.clone() });
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+
+        monitor.poll(&sender);
+        assert!(get_logs(&mut receiver).is_empty());
+
+        append(&path, "first\n");
+        monitor.poll(&sender);
+        assert_eq!(get_logs(&mut receiver), vec!["first"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_input_dropped() {
+        let path = get_temp_path("input.log");
+        let mut monitor = FileLogMonitor::new(FileSettings { path: path.clone() });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        for byte in b"reboot\r" {
+            write_proxy.request(PortRequest::Write(*byte)).unwrap();
+        }
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            if let MonitorMessage::Event(event) = next_message(&mut receiver).await {
+                events.push(event.description);
+            }
+        }
+        assert!(events[0].starts_with("Waiting for the file to appear"));
+        assert_eq!(events[1], "File is read-only, input dropped");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+
+    #[test]
+    fn test_fifo() {
+        let path = get_temp_path("fifo");
+        let c_path = std::ffi::CString::new(path.clone()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let mut monitor = FileLogMonitor::new(FileSettings { path: path.clone() });
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        monitor.current = Some(monitor.open(true).unwrap());
+
+        // nobody writing yet
+        monitor.poll(&sender);
+        assert!(get_logs(&mut receiver).is_empty());
+
+        for text in ["a\n", "b\n"] {
+            let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            writer.write_all(text.as_bytes()).unwrap();
+            drop(writer);
+            monitor.poll(&sender);
+        }
+        assert_eq!(get_logs(&mut receiver), vec!["a", "b"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
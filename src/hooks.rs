@@ -0,0 +1,480 @@
+use super::log_monitor::{Event, Log, MonitorMessage};
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+/// Function every hook script defines, called with the message as a map.
+const HOOK_FUNCTION: &str = "on_message";
+/// How often the scripts are checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+/// Limits the work of a single call, so a looping script cannot stall the session.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Text a hook writes to a port, alias or group.
+#[derive(Debug, PartialEq)]
+pub struct HookWrite {
+    pub hook: String,
+    pub target: String,
+    pub text: String,
+}
+
+/// Messages and writes resulting from a message passed through the hooks.
+#[derive(Debug, Default, PartialEq)]
+pub struct HookResult {
+    /// The message, unless dropped, followed by the messages the hooks added
+    pub messages: Vec<MonitorMessage>,
+    pub writes: Vec<HookWrite>,
+}
+
+/// Effects of the functions called by the scripts.
+#[derive(Default)]
+struct Effects {
+    /// Name of the hook being called
+    hook: String,
+    /// Source of the message being handled
+    source_name: Option<String>,
+    added: Vec<MonitorMessage>,
+    writes: Vec<HookWrite>,
+}
+
+impl Effects {
+    fn add_event(&mut self, source_name: String, description: String) {
+        self.added.push(MonitorMessage::Event(Event {
+            source_name,
+            description,
+            timestamp: chrono::Local::now(),
+        }));
+    }
+}
+
+fn get_source_name(path: &str) -> String {
+    format!("hook:{}", path)
+}
+
+struct Hook {
+    path: String,
+    ast: rhai::AST,
+    /// Bound to `this` in the script, kept between calls
+    state: rhai::Dynamic,
+    modified: Option<SystemTime>,
+    /// Set when a call failed, the hook is skipped until the script changes
+    failed: bool,
+}
+
+impl Hook {
+    fn compile(engine: &rhai::Engine, path: &str) -> Result<rhai::AST, String> {
+        let ast = engine
+            .compile_file(PathBuf::from(path))
+            .map_err(|e| format!("Failed to load hook {}: {}", path, e))?;
+        let has_hook_function = ast
+            .iter_functions()
+            .any(|f| f.name == HOOK_FUNCTION && f.params.len() == 1);
+        if !has_hook_function {
+            return Err(format!(
+                "Hook {} does not define {}(msg)",
+                path, HOOK_FUNCTION
+            ));
+        }
+        Ok(ast)
+    }
+
+    fn new(engine: &rhai::Engine, path: &str) -> Result<Hook, String> {
+        Ok(Hook {
+            path: path.to_string(),
+            ast: Hook::compile(engine, path)?,
+            state: rhai::Map::new().into(),
+            modified: get_modified(path),
+            failed: false,
+        })
+    }
+
+    /// Reloads the script if it changed, a script failing to load is reported and
+    /// the previous one is kept.
+    fn reload(&mut self, engine: &rhai::Engine, effects: &mut Effects) {
+        let modified = get_modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        let source_name = get_source_name(&self.path);
+        match Hook::compile(engine, &self.path) {
+            Ok(ast) => {
+                self.ast = ast;
+                self.state = rhai::Map::new().into();
+                self.failed = false;
+                effects.add_event(source_name, "Reloaded".to_string());
+            }
+            Err(e) => effects.add_event(source_name, e),
+        }
+    }
+
+    /// Passes the message to the script, returns None if the script dropped it.
+    fn call(
+        &mut self,
+        engine: &rhai::Engine,
+        msg: MonitorMessage,
+    ) -> Result<Option<MonitorMessage>, String> {
+        let map = engine
+            .parse_json(msg.to_json_line(), true)
+            .map_err(|e| e.to_string())?;
+        let options = rhai::CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result: rhai::Dynamic = engine
+            .call_fn_with_options(
+                options,
+                &mut rhai::Scope::new(),
+                &self.ast,
+                HOOK_FUNCTION,
+                (map,),
+            )
+            .map_err(|e| e.to_string())?;
+
+        if result.is_unit() {
+            return Ok(Some(msg));
+        }
+        if let Ok(keep) = result.as_bool() {
+            return Ok(keep.then_some(msg));
+        }
+        if let Some(map) = result.read_lock::<rhai::Map>() {
+            return MonitorMessage::from_json(&rhai::format_map_as_json(&map)).map(Some);
+        }
+        if result.is_string() {
+            return set_text(msg, result.to_string()).map(Some);
+        }
+        Err(format!("Unexpected result of type {}", result.type_name()))
+    }
+}
+
+fn get_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Replaces the text of the message with the one returned by a hook.
+fn set_text(msg: MonitorMessage, text: String) -> Result<MonitorMessage, String> {
+    match msg {
+        MonitorMessage::Log(log) => Ok(MonitorMessage::Log(Log {
+            message: text,
+            ..log
+        })),
+        MonitorMessage::UnsolictedMessage(_) => Ok(MonitorMessage::UnsolictedMessage(text)),
+        MonitorMessage::Event(event) => Ok(MonitorMessage::Event(Event {
+            description: text,
+            ..event
+        })),
+        MonitorMessage::ModemStatus(_) => Err("Modem status has no text".to_string()),
+    }
+}
+
+/// Rhai scripts every received message passes through, in the configured order.
+pub struct Hooks {
+    engine: rhai::Engine,
+    hooks: Vec<Hook>,
+    effects: Rc<RefCell<Effects>>,
+}
+
+impl Hooks {
+    pub fn new(paths: &[String]) -> Result<Hooks, String> {
+        let effects = Rc::new(RefCell::new(Effects::default()));
+        let engine = create_engine(&effects);
+        let hooks = paths
+            .iter()
+            .map(|path| Hook::new(&engine, path))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Hooks {
+            engine,
+            hooks,
+            effects,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Reloads the changed scripts, returns the events reporting the reloads.
+    pub fn reload_changed(&mut self) -> Vec<MonitorMessage> {
+        let mut effects = self.effects.borrow_mut();
+        for hook in &mut self.hooks {
+            hook.reload(&self.engine, &mut effects);
+        }
+        std::mem::take(&mut effects.added)
+    }
+
+    /// Passes the message through the hooks. A hook failing is reported and skipped
+    /// until its script changes.
+    pub fn process(&mut self, msg: MonitorMessage) -> HookResult {
+        let mut current = Some(msg);
+        for hook in self.hooks.iter_mut().filter(|h| !h.failed) {
+            let msg = match current.take() {
+                Some(msg) => msg,
+                None => break,
+            };
+            {
+                let mut effects = self.effects.borrow_mut();
+                effects.hook = hook.path.clone();
+                effects.source_name = match &msg {
+                    MonitorMessage::Log(log) => Some(log.source_name.clone()),
+                    MonitorMessage::Event(event) => Some(event.source_name.clone()),
+                    MonitorMessage::ModemStatus(status) => Some(status.source_name.clone()),
+                    MonitorMessage::UnsolictedMessage(_) => None,
+                };
+            }
+
+            let fallback = msg.clone();
+            current = match hook.call(&self.engine, msg) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    hook.failed = true;
+                    let description = format!("Failed, disabled until changed: {}", e);
+                    self.effects
+                        .borrow_mut()
+                        .add_event(get_source_name(&hook.path), description);
+                    Some(fallback)
+                }
+            };
+        }
+
+        let mut effects = self.effects.borrow_mut();
+        let mut messages: Vec<MonitorMessage> = current.into_iter().collect();
+        messages.append(&mut effects.added);
+        HookResult {
+            messages,
+            writes: std::mem::take(&mut effects.writes),
+        }
+    }
+}
+
+/// Engine with the functions available to the scripts:
+/// emit(source, text) adds a line, annotate(text) adds an event to the source of
+/// the message and send(target, text) writes to a port, alias or group.
+fn create_engine(effects: &Rc<RefCell<Effects>>) -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let emit_effects = effects.clone();
+    engine.register_fn("emit", move |source_name: &str, text: &str| {
+        emit_effects
+            .borrow_mut()
+            .added
+            .push(MonitorMessage::Log(Log {
+                source_name: source_name.to_string(),
+                message: text.to_string(),
+                timestamp: chrono::Local::now(),
+            }));
+    });
+
+    let annotate_effects = effects.clone();
+    engine.register_fn("annotate", move |text: &str| {
+        let mut effects = annotate_effects.borrow_mut();
+        let source_name = match &effects.source_name {
+            Some(source_name) => source_name.clone(),
+            None => get_source_name(&effects.hook),
+        };
+        effects.add_event(source_name, text.to_string());
+    });
+
+    let send_effects = effects.clone();
+    engine.register_fn("send", move |target: &str, text: &str| {
+        let mut effects = send_effects.borrow_mut();
+        let write = HookWrite {
+            hook: effects.hook.clone(),
+            target: target.to_string(),
+            text: text.to_string(),
+        };
+        effects.writes.push(write);
+    });
+
+    // the terminal is in raw mode, printed text is logged instead
+    let print_effects = effects.clone();
+    engine.on_print(move |text| {
+        let mut effects = print_effects.borrow_mut();
+        let source_name = get_source_name(&effects.hook);
+        effects.add_event(source_name, text.to_string());
+    });
+    let debug_effects = effects.clone();
+    engine.on_debug(move |text, _, _| {
+        let mut effects = debug_effects.borrow_mut();
+        let source_name = get_source_name(&effects.hook);
+        effects.add_event(source_name, text.to_string());
+    });
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_temp_path;
+
+    fn write_hook(name: &str, content: &str) -> String {
+        let path = get_temp_path(&format!("{}.rhai", name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn get_log(source_name: &str, message: &str) -> MonitorMessage {
+        MonitorMessage::Log(Log {
+            source_name: source_name.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Local::now(),
+        })
+    }
+
+    fn get_descriptions(result: &HookResult) -> Vec<(String, String)> {
+        result
+            .messages
+            .iter()
+            .filter_map(|msg| match msg {
+                MonitorMessage::Event(event) => {
+                    Some((event.source_name.clone(), event.description.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_process() {
+        let path = write_hook(
+            "process",
+            r#"
+            fn on_message(msg) {
+                if msg.type != "log" { return; }
+                if msg.message.contains("heartbeat") { return false; }
+                if msg.message.starts_with("temp=") {
+                    let celsius = parse_int(msg.message.sub_string(5));
+                    emit("sensor", `temperature ${celsius} C`);
+                }
+                if msg.message.contains("panic") {
+                    annotate("crash detected");
+                    send("debug", "bt\r");
+                }
+                if msg.source == "gw" {
+                    msg.source = "gateway";
+                    return msg;
+                }
+                msg.message.to_upper()
+            }
+            "#,
+        );
+        let mut hooks = Hooks::new(std::slice::from_ref(&path)).unwrap();
+
+        assert!(hooks.process(get_log("a", "heartbeat")).messages.is_empty());
+
+        let result = hooks.process(get_log("a", "temp=21"));
+        match &result.messages[..] {
+            [MonitorMessage::Log(first), MonitorMessage::Log(second)] => {
+                assert_eq!(first.message, "TEMP=21");
+                assert_eq!(second.source_name, "sensor");
+                assert_eq!(second.message, "temperature 21 C");
+            }
+            messages => panic!("Unexpected messages: {:?}", messages),
+        }
+
+        let result = hooks.process(get_log("gw", "kernel panic"));
+        match &result.messages[0] {
+            MonitorMessage::Log(log) => {
+                assert_eq!(log.source_name, "gateway");
+                assert_eq!(log.message, "kernel panic");
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        assert_eq!(
+            get_descriptions(&result),
+            [("gw".to_string(), "crash detected".to_string())]
+        );
+        assert_eq!(
+            result.writes,
+            [HookWrite {
+                hook: path.clone(),
+                target: "debug".to_string(),
+                text: "bt\r".to_string(),
+            }]
+        );
+
+        let partial = MonitorMessage::UnsolictedMessage("login: ".to_string());
+        assert_eq!(hooks.process(partial.clone()).messages, [partial]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_state_and_reload() {
+        let path = write_hook(
+            "reload",
+            "fn on_message(msg) { this.count = (this.count ?? 0) + 1; print(this.count); }",
+        );
+        let mut hooks = Hooks::new(std::slice::from_ref(&path)).unwrap();
+        let source_name = get_source_name(&path);
+
+        hooks.process(get_log("a", "1"));
+        let result = hooks.process(get_log("a", "2"));
+        assert_eq!(
+            get_descriptions(&result),
+            [(source_name.clone(), "2".to_string())]
+        );
+        assert!(hooks.reload_changed().is_empty());
+
+        std::fs::write(&path, "fn on_message(msg) { throw \"broken\"; }").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let events = hooks.reload_changed();
+        assert_eq!(events.len(), 1);
+        let result = hooks.process(get_log("a", "3"));
+        assert!(get_descriptions(&result)[0]
+            .1
+            .starts_with("Failed, disabled until changed"));
+        match &result.messages[0] {
+            MonitorMessage::Log(log) => assert_eq!(log.message, "3"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        // the failed hook is skipped
+        let result = hooks.process(get_log("a", "4"));
+        assert_eq!(result.messages.len(), 1);
+
+        // a script failing to load keeps the previous one
+        std::fs::write(&path, "fn on_message(msg) {").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(20))
+            .unwrap();
+        match &hooks.reload_changed()[..] {
+            [MonitorMessage::Event(event)] => {
+                assert!(event.description.starts_with("Failed to load hook"))
+            }
+            events => panic!("Unexpected events: {:?}", events),
+        }
+
+        std::fs::write(&path, "fn on_message(msg) { this.count ?? \"reset\" }").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(30))
+            .unwrap();
+        match &hooks.reload_changed()[..] {
+            [MonitorMessage::Event(event)] => {
+                assert_eq!(event.source_name, source_name);
+                assert_eq!(event.description, "Reloaded");
+            }
+            events => panic!("Unexpected events: {:?}", events),
+        }
+        // the state is reset
+        let result = hooks.process(get_log("a", "5"));
+        match &result.messages[..] {
+            [MonitorMessage::Log(log)] => assert_eq!(log.message, "reset"),
+            messages => panic!("Unexpected messages: {:?}", messages),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid() {
+        let path = write_hook("invalid", "fn process(msg) { msg }");
+        assert!(Hooks::new(std::slice::from_ref(&path)).is_err());
+        std::fs::write(&path, "fn on_message(msg) {").unwrap();
+        assert!(Hooks::new(std::slice::from_ref(&path)).is_err());
+        std::fs::remove_file(path).unwrap();
+        assert!(Hooks::new(&["/nonexistent/hook.rhai".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::command;
+use crate::config;
+
+use termion::event::Key;
+
+const DEFAULT_PREFIX: Key = Key::Ctrl('a');
+
+/// Action triggered by a key pressed after the command prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    NextWriter,
+    /// Selects the writer with the given index, starting from 0.
+    SelectWriter(usize),
+    PickWriter,
+    SendPrefix,
+    Help,
+    CommandPrompt,
+    Command(command::Command),
+}
+
+impl Action {
+    pub fn parse(name: &str) -> Result<Action, String> {
+        let action = match name {
+            "quit" => Action::Quit,
+            "next-writer" => Action::NextWriter,
+            "pick-writer" => Action::PickWriter,
+            "send-prefix" => Action::SendPrefix,
+            "help" => Action::Help,
+            "command-prompt" => Action::CommandPrompt,
+            _ => {
+                let index = name
+                    .strip_prefix("select-writer-")
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .filter(|i| *i > 0);
+                match (index, command::Command::parse(name)) {
+                    (Some(i), _) => Action::SelectWriter(i - 1),
+                    (None, Ok(command)) => Action::Command(command),
+                    (None, Err(_)) => return Err(format!("Unknown action: {}", name)),
+                }
+            }
+        };
+
+        Ok(action)
+    }
+
+    pub fn get_name(&self) -> String {
+        match self {
+            Action::Quit => "quit".to_string(),
+            Action::NextWriter => "next-writer".to_string(),
+            Action::SelectWriter(index) => format!("select-writer-{}", index + 1),
+            Action::PickWriter => "pick-writer".to_string(),
+            Action::SendPrefix => "send-prefix".to_string(),
+            Action::Help => "help".to_string(),
+            Action::CommandPrompt => "command-prompt".to_string(),
+            Action::Command(command) => command.to_string(),
+        }
+    }
+}
+
+/// Parses key names such as "x", "C-x", "Esc" or "F1".
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    let key = if let Some(c) = name.strip_prefix("C-") {
+        let mut chars = c.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Key::Ctrl(c.to_ascii_lowercase()),
+            _ => return Err(format!("Invalid key: {}", name)),
+        }
+    } else {
+        match name {
+            "Esc" => Key::Esc,
+            "Tab" => Key::Char('\t'),
+            "Space" => Key::Char(' '),
+            "Enter" => Key::Char('\n'),
+            "Backspace" => Key::Backspace,
+            _ => {
+                let function_key = name
+                    .strip_prefix('F')
+                    .and_then(|n| n.parse::<u8>().ok())
+                    .filter(|n| (1..=12).contains(n));
+                let mut chars = name.chars();
+                match (function_key, chars.next(), chars.next()) {
+                    (Some(n), _, _) => Key::F(n),
+                    (None, Some(c), None) => Key::Char(c),
+                    _ => return Err(format!("Invalid key: {}", name)),
+                }
+            }
+        }
+    };
+
+    Ok(key)
+}
+
+pub fn format_key(key: &Key) -> String {
+    match key {
+        Key::Ctrl(c) => format!("C-{}", c),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Esc => "Esc".to_string(),
+        Key::Backspace => "Backspace".to_string(),
+        Key::F(n) => format!("F{}", n),
+        _ => format!("{:?}", key),
+    }
+}
+
+/// Maps keys pressed after the command prefix to actions.
+pub struct Keymap {
+    prefix: Key,
+    bindings: indexmap::IndexMap<Key, Action>,
+}
+
+impl Keymap {
+    /// Builds the default keymap and applies the configured bindings on top of it.
+    /// Binding a key to "none" removes its default action.
+    pub fn new(keymap_config: &config::KeymapConfig) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+
+        if let Some(prefix) = &keymap_config.prefix {
+            let prefix_key = parse_key(prefix)?;
+            keymap.bindings.shift_remove(&keymap.prefix);
+            keymap.bindings.insert(prefix_key, Action::SendPrefix);
+            keymap.prefix = prefix_key;
+        }
+
+        for (key_name, action_name) in &keymap_config.bindings {
+            let key = parse_key(key_name)?;
+            if action_name == "none" {
+                keymap.bindings.shift_remove(&key);
+            } else {
+                keymap.bindings.insert(key, Action::parse(action_name)?);
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn get_prefix(&self) -> Key {
+        self.prefix
+    }
+
+    pub fn get_action(&self, key: &Key) -> Option<Action> {
+        self.bindings.get(key).cloned()
+    }
+
+    /// Describes the bindings, one "PREFIX KEY: action" entry per binding.
+    pub fn describe(&self) -> Vec<String> {
+        self.bindings
+            .iter()
+            .map(|(key, action)| {
+                format!(
+                    "{} {}: {}",
+                    format_key(&self.prefix),
+                    format_key(key),
+                    action.get_name()
+                )
+            })
+            .collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut bindings = indexmap::IndexMap::new();
+        bindings.insert(Key::Char('x'), Action::Quit);
+        bindings.insert(Key::Char('z'), Action::NextWriter);
+        for i in 1..=9 {
+            let digit = std::char::from_digit(i as u32, 10).unwrap();
+            bindings.insert(Key::Char(digit), Action::SelectWriter(i - 1));
+        }
+        bindings.insert(Key::Char('\''), Action::PickWriter);
+        bindings.insert(Key::Char('?'), Action::Help);
+        bindings.insert(Key::Char(':'), Action::CommandPrompt);
+        bindings.insert(
+            Key::Char('d'),
+            Action::Command(command::Command::parse("dtr pulse").unwrap()),
+        );
+        bindings.insert(
+            Key::Char('r'),
+            Action::Command(command::Command::parse("rts pulse").unwrap()),
+        );
+        bindings.insert(
+            Key::Char('b'),
+            Action::Command(command::Command::parse("break").unwrap()),
+        );
+        bindings.insert(DEFAULT_PREFIX, Action::SendPrefix);
+
+        Keymap {
+            prefix: DEFAULT_PREFIX,
+            bindings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("x").unwrap(), Key::Char('x'));
+        assert_eq!(parse_key("C-a").unwrap(), Key::Ctrl('a'));
+        assert_eq!(parse_key("C-B").unwrap(), Key::Ctrl('b'));
+        assert_eq!(parse_key("Esc").unwrap(), Key::Esc);
+        assert_eq!(parse_key("F5").unwrap(), Key::F(5));
+        assert_eq!(parse_key("Space").unwrap(), Key::Char(' '));
+    }
+
+    #[test]
+    fn test_parse_key_invalid() {
+        assert!(parse_key("").is_err());
+        assert!(parse_key("C-").is_err());
+        assert!(parse_key("C-ab").is_err());
+        assert!(parse_key("F13").is_err());
+        assert!(parse_key("xy").is_err());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(Action::parse("quit").unwrap(), Action::Quit);
+        assert_eq!(
+            Action::parse("select-writer-3").unwrap(),
+            Action::SelectWriter(2)
+        );
+        assert_eq!(
+            Action::parse("sequence esp32-bootloader").unwrap(),
+            Action::Command(command::Command::Sequence("esp32-bootloader".to_string()))
+        );
+        assert!(Action::parse("select-writer-0").is_err());
+        assert!(Action::parse("fly").is_err());
+    }
+
+    #[test]
+    fn test_default_keymap() {
+        let keymap = Keymap::new(&config::KeymapConfig::default()).unwrap();
+        assert_eq!(keymap.get_prefix(), Key::Ctrl('a'));
+        assert_eq!(keymap.get_action(&Key::Char('x')), Some(Action::Quit));
+        assert_eq!(keymap.get_action(&Key::Ctrl('a')), Some(Action::SendPrefix));
+        assert_eq!(keymap.get_action(&Key::Ctrl('x')), None);
+    }
+
+    #[test]
+    fn test_configured_keymap() {
+        let mut keymap_config = config::KeymapConfig {
+            prefix: Some("C-b".to_string()),
+            ..Default::default()
+        };
+        keymap_config
+            .bindings
+            .insert("q".to_string(), "quit".to_string());
+        keymap_config
+            .bindings
+            .insert("x".to_string(), "none".to_string());
+
+        let keymap = Keymap::new(&keymap_config).unwrap();
+        assert_eq!(keymap.get_prefix(), Key::Ctrl('b'));
+        assert_eq!(keymap.get_action(&Key::Ctrl('b')), Some(Action::SendPrefix));
+        assert_eq!(keymap.get_action(&Key::Ctrl('a')), None);
+        assert_eq!(keymap.get_action(&Key::Char('q')), Some(Action::Quit));
+        assert_eq!(keymap.get_action(&Key::Char('x')), None);
+    }
+
+    #[test]
+    fn test_configured_keymap_invalid_action() {
+        let mut keymap_config = config::KeymapConfig::default();
+        keymap_config
+            .bindings
+            .insert("q".to_string(), "fly".to_string());
+        assert!(Keymap::new(&keymap_config).is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use chrono;
+use crate::can::CanFrame;
+use crate::data::PortSetting;
+
+use std::fmt;
+use std::future::Future;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::sync::CancellationToken;
 
@@ -9,17 +13,292 @@ pub struct Log {
     pub timestamp: chrono::DateTime<chrono::Local>,
 }
 
+/// Something that happened to a source other than receiving data, e.g. a failed write.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Event {
+    pub source_name: String,
+    pub description: String,
+    pub timestamp: chrono::DateTime<chrono::Local>,
+}
+
+/// Reports something that happened to the named source.
+pub fn send_event(
+    source_name: String,
+    description: String,
+    sender_queue: &UnboundedSender<MonitorMessage>,
+) {
+    let event = Event {
+        source_name,
+        description,
+        timestamp: chrono::Local::now(),
+    };
+    sender_queue.send(MonitorMessage::Event(event)).unwrap();
+}
+
+/// Levels of the modem status lines.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ModemLines {
+    pub cts: bool,
+    pub dsr: bool,
+    pub dcd: bool,
+    pub ri: bool,
+}
+
+impl fmt::Display for ModemLines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CTS={} DSR={} DCD={} RI={}",
+            self.cts as u8, self.dsr as u8, self.dcd as u8, self.ri as u8
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModemStatus {
+    pub source_name: String,
+    pub lines: ModemLines,
+    pub timestamp: chrono::DateTime<chrono::Local>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum MonitorMessage {
     Log(Log),
     UnsolictedMessage(String),
+    Event(Event),
+    /// Sent when any of the modem status lines changes.
+    ModemStatus(ModemStatus),
+}
+
+fn parse_timestamp(value: &serde_json::Value) -> Result<chrono::DateTime<chrono::Local>, String> {
+    let text = value.as_str().ok_or("Missing timestamp")?;
+    chrono::DateTime::parse_from_rfc3339(text)
+        .map(|t| t.with_timezone(&chrono::Local))
+        .map_err(|e| format!("Invalid timestamp {}: {}", text, e))
+}
+
+fn get_string(object: &serde_json::Value, key: &str) -> Result<String, String> {
+    match object[key].as_str() {
+        Some(value) => Ok(value.to_string()),
+        None => Err(format!("Missing {}", key)),
+    }
+}
+
+impl MonitorMessage {
+    /// When the message was received, unsolicited messages carry no timestamp.
+    pub fn get_timestamp(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        match self {
+            MonitorMessage::Log(log) => Some(log.timestamp),
+            MonitorMessage::UnsolictedMessage(_) => None,
+            MonitorMessage::Event(event) => Some(event.timestamp),
+            MonitorMessage::ModemStatus(status) => Some(status.timestamp),
+        }
+    }
+
+    /// Encodes the message as a single line of JSON, ending with a newline.
+    pub fn to_json_line(&self) -> String {
+        let value = match self {
+            MonitorMessage::Log(log) => serde_json::json!({
+                "type": "log",
+                "timestamp": log.timestamp.to_rfc3339(),
+                "source": log.source_name,
+                "message": log.message,
+            }),
+            MonitorMessage::UnsolictedMessage(text) => serde_json::json!({
+                "type": "partial",
+                "text": text,
+            }),
+            MonitorMessage::Event(event) => serde_json::json!({
+                "type": "event",
+                "timestamp": event.timestamp.to_rfc3339(),
+                "source": event.source_name,
+                "description": event.description,
+            }),
+            MonitorMessage::ModemStatus(status) => serde_json::json!({
+                "type": "modem_status",
+                "timestamp": status.timestamp.to_rfc3339(),
+                "source": status.source_name,
+                "cts": status.lines.cts,
+                "dsr": status.lines.dsr,
+                "dcd": status.lines.dcd,
+                "ri": status.lines.ri,
+            }),
+        };
+        format!("{}\n", value)
+    }
+
+    /// Decodes a message encoded with `to_json_line`.
+    pub fn from_json(line: &str) -> Result<MonitorMessage, String> {
+        let object: serde_json::Value =
+            serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+
+        let message = match object["type"].as_str() {
+            Some("log") => MonitorMessage::Log(Log {
+                source_name: get_string(&object, "source")?,
+                message: get_string(&object, "message")?,
+                timestamp: parse_timestamp(&object["timestamp"])?,
+            }),
+            Some("partial") => MonitorMessage::UnsolictedMessage(get_string(&object, "text")?),
+            Some("event") => MonitorMessage::Event(Event {
+                source_name: get_string(&object, "source")?,
+                description: get_string(&object, "description")?,
+                timestamp: parse_timestamp(&object["timestamp"])?,
+            }),
+            Some("modem_status") => MonitorMessage::ModemStatus(ModemStatus {
+                source_name: get_string(&object, "source")?,
+                lines: ModemLines {
+                    cts: object["cts"].as_bool().unwrap_or_default(),
+                    dsr: object["dsr"].as_bool().unwrap_or_default(),
+                    dcd: object["dcd"].as_bool().unwrap_or_default(),
+                    ri: object["ri"].as_bool().unwrap_or_default(),
+                },
+                timestamp: parse_timestamp(&object["timestamp"])?,
+            }),
+            _ => return Err(format!("Unknown message type: {}", object["type"])),
+        };
+
+        Ok(message)
+    }
+}
+
+/// Raw activity of a port, published for sharing the port over the network.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortActivity {
+    Received(Vec<u8>),
+    ModemLines(ModemLines),
+}
+
+/// Request handled by the monitor task.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortRequest {
+    Write(u8),
+    SetDtr(bool),
+    SetRts(bool),
+    /// Holds the TX line in the break condition for the given time.
+    Break(std::time::Duration),
+    /// Sets or clears the break condition, which holds until changed again.
+    SetBreak(bool),
+    Configure(PortSetting),
+    SendFrame(CanFrame),
+}
+
+impl PortRequest {
+    /// Reports the request as not supported by a kind of source, e.g.
+    /// "Break is not available over UDP" for the kind "over UDP".
+    pub fn unsupported_description(&self, kind: &str) -> String {
+        let subject = match self {
+            PortRequest::Write(_) => "Input is",
+            PortRequest::SetDtr(_) | PortRequest::SetRts(_) => "Control lines are",
+            PortRequest::Break(_) | PortRequest::SetBreak(_) => "Break is",
+            PortRequest::Configure(_) => "Port settings are",
+            PortRequest::SendFrame(_) => "CAN frames are",
+        };
+        format!("{} not available {}", subject, kind)
+    }
+}
+
+/// Handle for sending requests to a running monitor.
+#[derive(Clone)]
+pub struct WriteProxy {
+    write_sender: UnboundedSender<PortRequest>,
+}
+
+impl WriteProxy {
+    pub fn new(write_sender: UnboundedSender<PortRequest>) -> WriteProxy {
+        WriteProxy { write_sender }
+    }
+
+    pub fn request(&self, request: PortRequest) -> Result<(), String> {
+        self.write_sender
+            .send(request)
+            .map_err(|_| "port monitor is not running".to_string())
+    }
 }
 
 pub trait AsyncLogMonitor {
     fn get_common_name(&self) -> String;
-    async fn monitor(
+    fn get_write_proxy(&self) -> WriteProxy;
+    fn monitor(
         &mut self,
         cancel_token: CancellationToken,
         sender_queue: UnboundedSender<MonitorMessage>,
-    );
+    ) -> impl Future<Output = ()> + Send;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modem_lines_display() {
+        let lines = ModemLines {
+            cts: true,
+            dsr: false,
+            dcd: true,
+            ri: false,
+        };
+        assert_eq!(lines.to_string(), "CTS=1 DSR=0 DCD=1 RI=0");
+    }
+
+    #[test]
+    fn test_unsupported_description() {
+        assert_eq!(
+            PortRequest::SetRts(true).unsupported_description("for processes"),
+            "Control lines are not available for processes"
+        );
+        assert_eq!(
+            PortRequest::SetBreak(true).unsupported_description("over UDP"),
+            "Break is not available over UDP"
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let timestamp = chrono::Local::now();
+        let messages = [
+            MonitorMessage::Log(Log {
+                source_name: "/dev/ttyUSB0".to_string(),
+                message: "say \"hi\"".to_string(),
+                timestamp,
+            }),
+            MonitorMessage::UnsolictedMessage("> ".to_string()),
+            MonitorMessage::Event(Event {
+                source_name: "can:vcan0".to_string(),
+                description: "DTR set".to_string(),
+                timestamp,
+            }),
+            MonitorMessage::ModemStatus(ModemStatus {
+                source_name: "/dev/ttyUSB0".to_string(),
+                lines: ModemLines {
+                    cts: true,
+                    dsr: false,
+                    dcd: true,
+                    ri: false,
+                },
+                timestamp,
+            }),
+        ];
+
+        let types = ["log", "partial", "event", "modem_status"];
+        for (msg, expected_type) in messages.iter().zip(types) {
+            let line = msg.to_json_line();
+            assert!(line.ends_with('\n'));
+            assert_eq!(line.matches('\n').count(), 1);
+            let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(value["type"], expected_type);
+            assert_eq!(&MonitorMessage::from_json(&line).unwrap(), msg);
+        }
+
+        assert!(MonitorMessage::from_json("{}").is_err());
+        assert!(MonitorMessage::from_json("{\"type\": \"log\"}").is_err());
+        assert!(MonitorMessage::from_json("log").is_err());
+
+        let value: serde_json::Value = serde_json::from_str(&messages[0].to_json_line()).unwrap();
+        assert_eq!(value["source"], "/dev/ttyUSB0");
+        assert_eq!(value["message"], "say \"hi\"");
+        assert_eq!(value["timestamp"], timestamp.to_rfc3339());
+        let value: serde_json::Value = serde_json::from_str(&messages[3].to_json_line()).unwrap();
+        assert_eq!(value["cts"], true);
+        assert_eq!(value["dsr"], false);
+    }
 }
@@ -1,6 +1,5 @@
 use crate::log_monitor;
 
-use chrono;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 
@@ -14,7 +13,6 @@ impl Logger {
         let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
         let log_file_name = format!("log_{}.txt", timestamp);
         let log_file = match OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open(&log_file_name)
@@ -26,24 +24,13 @@ impl Logger {
         };
 
         Ok(Logger {
-            log_file: log_file,
+            log_file,
             last_msg_was_unsolicited: false,
         })
     }
 
     pub fn log_monitor_message_to_file(&mut self, msg: &log_monitor::MonitorMessage) {
-        match msg {
-            log_monitor::MonitorMessage::UnsolictedMessage(msg) => {
-                write!(self.log_file, ">>> {}", msg).unwrap();
-            }
-            log_monitor::MonitorMessage::Log(msg) => {
-                let log_msg = format!(
-                    ">> [{}] | {}: {}\r\n",
-                    msg.timestamp, msg.source_name, msg.message
-                );
-                write!(self.log_file, "{}", &log_msg).unwrap();
-            }
-        }
+        write!(self.log_file, "{}", format_record(msg)).unwrap();
     }
 
     pub fn log_monitor_message_to_stdout(
@@ -69,6 +56,40 @@ impl Logger {
                 print!("{}", &log_msg);
                 stdout.flush().unwrap();
             }
+            log_monitor::MonitorMessage::Event(event) => {
+                if self.last_msg_was_unsolicited {
+                    print!("\r\n");
+                }
+                self.last_msg_was_unsolicited = false;
+                let log_msg = format!(
+                    ">! [{}] | {}: {}\r\n",
+                    event.timestamp, event.source_name, event.description
+                );
+                print!("{}", &log_msg);
+                stdout.flush().unwrap();
+            }
+            // shown in the status bar only
+            log_monitor::MonitorMessage::ModemStatus(_) => {}
         }
     }
 }
+
+/// Formats a message the way it is written to the session log. Partial lines are
+/// written without a line ending, the rest of the line follows them.
+pub fn format_record(msg: &log_monitor::MonitorMessage) -> String {
+    match msg {
+        log_monitor::MonitorMessage::UnsolictedMessage(msg) => format!(">>> {}", msg),
+        log_monitor::MonitorMessage::Log(msg) => format!(
+            ">> [{}] | {}: {}\r\n",
+            msg.timestamp, msg.source_name, msg.message
+        ),
+        log_monitor::MonitorMessage::Event(event) => format!(
+            ">! [{}] | {}: {}\r\n",
+            event.timestamp, event.source_name, event.description
+        ),
+        log_monitor::MonitorMessage::ModemStatus(status) => format!(
+            ">~ [{}] | {}: {}\r\n",
+            status.timestamp, status.source_name, status.lines
+        ),
+    }
+}
@@ -2,14 +2,47 @@ use std::io::Error;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 
+mod analyze;
+mod autobaud;
+mod bridge;
+mod can;
+mod can_monitor;
 mod cli;
+mod command;
+mod config;
 mod data;
+mod diff;
+mod fd;
+mod file_monitor;
+mod hooks;
+mod keymap;
 mod log_monitor;
 mod logging;
+mod merge;
+mod process_monitor;
+mod pty;
+mod publisher;
 mod read_line;
+mod replay_monitor;
+mod rfc2217;
+mod script;
+mod sequence;
 mod serial_monitor;
+mod server;
+mod session_log;
+mod status_bar;
+mod stream_monitor;
+mod tcp_monitor;
+#[cfg(test)]
+mod test_util;
+mod trigger;
+mod udp_monitor;
+mod unix_monitor;
 mod writer;
 
+/// Command run without starting a session, with the arguments following its name.
+type OfflineCommand = fn(&[String]) -> Result<(), String>;
+
 /// Asynchronously gets single key from the user.
 async fn get_key(
     stdin: &mut termion::input::Keys<termion::AsyncReader>,
@@ -29,6 +62,22 @@ async fn get_key(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // offline commands print only their output
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let offline_command: Option<OfflineCommand> = match args.first().map(String::as_str) {
+        Some(analyze::COMMAND) => Some(analyze::run),
+        Some(diff::COMMAND) => Some(diff::run),
+        Some(merge::COMMAND) => Some(merge::run),
+        _ => None,
+    };
+    if let Some(run) = offline_command {
+        if let Err(e) = run(&args[1..]) {
+            println!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("DrFish is a fish doctor! 🐟");
 
     let mut dr_fish = match cli::DrFishCli::new() {
@@ -39,22 +88,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    println!("Starting to read from serial ports. Press CTRL-X to exit and CTRL-Z to switch input.");
+    println!(
+        "Starting to read from serial ports. Press {} followed by ? to list key bindings.",
+        dr_fish.get_prefix_key_name()
+    );
 
     dr_fish.spawn_monitors();
+    let mut script_result = dr_fish.take_script_result();
 
     let mut stdout = std::io::stdout().into_raw_mode().unwrap();
     let mut stdin = termion::async_stdin().keys();
+    dr_fish.status_bar.draw(&mut stdout);
+    let mut exit_code = None;
 
     loop {
         tokio::select! {
             msg = dr_fish.recieve_monitor_message() => {
                 if let Some(msg) = msg {
+                    dr_fish.handle_monitor_message(&msg);
+                    dr_fish.publish_monitor_message(&msg);
                     dr_fish.logger.log_monitor_message_to_file(&msg);
                     dr_fish.logger.log_monitor_message_to_stdout(&msg, &mut stdout);
+
+                    match dr_fish.run_triggers(&msg).await {
+                        Some(cli::CliAction::Exit(code)) => {
+                            exit_code = Some(code);
+                            break;
+                        }
+                        Some(cli::CliAction::Break) => break,
+                        None => {}
+                    }
                 }
             }
 
+            code = cli::wait_for_script(&mut script_result) => {
+                dr_fish.stop_monitors().await;
+                exit_code = Some(code);
+                break;
+            }
+
             _ = tokio::signal::ctrl_c() => {
                 print!("Captured Ctrl+c, bye fisherman!\r\n");
                 break;
@@ -70,6 +142,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+
+        dr_fish.status_bar.draw(&mut stdout);
+    }
+
+    // e.g. the events of the trigger ending the session
+    for msg in dr_fish.take_pending_messages() {
+        dr_fish.logger.log_monitor_message_to_file(&msg);
+        dr_fish
+            .logger
+            .log_monitor_message_to_stdout(&msg, &mut stdout);
+    }
+    dr_fish.status_bar.clear(&mut stdout);
+
+    if let Some(code) = exit_code {
+        // restores the terminal, which exiting would skip
+        drop(stdout);
+        std::process::exit(code);
     }
 
     Ok(())
@@ -0,0 +1,321 @@
+use super::cli::DEFAULT_BAUD_RATE;
+use super::data::LogFormat;
+use super::log_monitor::MonitorMessage;
+use super::logging;
+use super::session_log;
+
+use std::io::Write;
+
+pub const COMMAND: &str = "merge";
+
+/// Log merged into the timeline, with the corrections of its messages.
+#[derive(Debug, PartialEq)]
+struct MergedFile {
+    path: String,
+    /// Added to every timestamp, to correct the clock of the host writing the log
+    offset: chrono::TimeDelta,
+    /// Timestamp format of a log written by another tool
+    pattern: Option<String>,
+    /// Source name of a log written by another tool, or the prefix of the source
+    /// names of a session log
+    name: Option<String>,
+}
+
+impl MergedFile {
+    fn read(&self) -> Result<session_log::SessionLog, String> {
+        match &self.pattern {
+            Some(pattern) => {
+                let name = self.name.as_deref().unwrap_or(&self.path);
+                session_log::read_with_pattern(&self.path, pattern, name)
+            }
+            None => session_log::read(&self.path, LogFormat::Auto, DEFAULT_BAUD_RATE),
+        }
+    }
+
+    /// Applies the clock offset and the name to a message of the file.
+    fn correct(&self, msg: MonitorMessage) -> MonitorMessage {
+        let tag = |source_name: String| match (&self.name, &self.pattern) {
+            (Some(name), None) => format!("{}:{}", name, source_name),
+            _ => source_name,
+        };
+
+        match msg {
+            MonitorMessage::Log(mut log) => {
+                log.source_name = tag(log.source_name);
+                log.timestamp += self.offset;
+                MonitorMessage::Log(log)
+            }
+            MonitorMessage::Event(mut event) => {
+                event.source_name = tag(event.source_name);
+                event.timestamp += self.offset;
+                MonitorMessage::Event(event)
+            }
+            MonitorMessage::ModemStatus(mut status) => {
+                status.source_name = tag(status.source_name);
+                status.timestamp += self.offset;
+                MonitorMessage::ModemStatus(status)
+            }
+            msg => msg,
+        }
+    }
+}
+
+/// Orders the messages of all the logs by their timestamps. Partial lines stay
+/// after the message they followed in their log.
+fn merge(logs: Vec<(&MergedFile, session_log::SessionLog)>) -> Vec<MonitorMessage> {
+    let mut timeline = Vec::new();
+
+    for (file, log) in logs {
+        let mut last_timestamp = None;
+        for msg in log.messages {
+            let msg = file.correct(msg);
+            last_timestamp = msg.get_timestamp().or(last_timestamp);
+            timeline.push((last_timestamp, msg));
+        }
+    }
+
+    // the sort is stable, the order of messages with the same time is kept
+    timeline.sort_by_key(|(timestamp, _)| *timestamp);
+    timeline.into_iter().map(|(_, msg)| msg).collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct MergeArguments {
+    files: Vec<MergedFile>,
+    output_path: Option<String>,
+}
+
+fn parse_offset(arg: &str) -> Result<chrono::TimeDelta, String> {
+    match arg.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() => Ok(chrono::TimeDelta::microseconds(
+            (seconds * 1e6).round() as i64,
+        )),
+        _ => Err(format!("Invalid clock offset: {}", arg)),
+    }
+}
+
+/// parses the arguments following the merge command:
+/// LOG_PATH... [--offset LOG_PATH=SECONDS] [--pattern LOG_PATH=FORMAT]
+/// [--name LOG_PATH=NAME] [--output PATH]
+fn parse_args(args: &[String]) -> Result<MergeArguments, String> {
+    let mut paths: Vec<String> = Vec::new();
+    let mut file_options = Vec::new();
+    let mut output_path = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--offset" | "--pattern" | "--name" => {
+                let value = args_iter
+                    .next()
+                    .ok_or(format!("Missing value for {}", arg))?;
+                match value.split_once('=') {
+                    Some((path, value)) if !value.is_empty() => {
+                        file_options.push((arg.as_str(), path, value))
+                    }
+                    _ => return Err(format!("Invalid {} argument: {}", arg, value)),
+                }
+            }
+            "--output" => {
+                output_path = Some(
+                    args_iter
+                        .next()
+                        .ok_or("Missing value for --output")?
+                        .clone(),
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("Missing the paths of the merged logs".to_string());
+    }
+
+    let mut files: Vec<MergedFile> = paths
+        .into_iter()
+        .map(|path| MergedFile {
+            path,
+            offset: chrono::TimeDelta::zero(),
+            pattern: None,
+            name: None,
+        })
+        .collect();
+
+    for (option, path, value) in file_options {
+        let file = match files.iter_mut().find(|f| f.path == path) {
+            Some(file) => file,
+            None => return Err(format!("{} given for unknown log {}", option, path)),
+        };
+        match option {
+            "--offset" => file.offset = parse_offset(value)?,
+            "--pattern" => file.pattern = Some(value.to_string()),
+            _ => file.name = Some(value.to_string()),
+        }
+    }
+
+    Ok(MergeArguments { files, output_path })
+}
+
+/// Merges the logs given as arguments and writes the timeline in the format of
+/// the session log.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = parse_args(args)?;
+
+    let mut logs = Vec::new();
+    let mut skipped = 0;
+    for file in &arguments.files {
+        let log = file.read()?;
+        skipped += log.skipped;
+        logs.push((file, log));
+    }
+    let timeline = merge(logs);
+
+    let output: Box<dyn Write> = match &arguments.output_path {
+        Some(path) => Box::new(
+            std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut output = std::io::BufWriter::new(output);
+    for msg in &timeline {
+        let mut record = logging::format_record(msg);
+        // records of other logs don't continue a partial line
+        if let MonitorMessage::UnsolictedMessage(_) = msg {
+            record += "\r\n";
+        }
+        output
+            .write_all(record.as_bytes())
+            .map_err(|e| format!("Failed to write the merged log: {}", e))?;
+    }
+    output
+        .flush()
+        .map_err(|e| format!("Failed to write the merged log: {}", e))?;
+
+    eprintln!(
+        "Merged {} messages from {} logs, {} unreadable lines skipped",
+        timeline.len(),
+        arguments.files.len(),
+        skipped
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::{Event, Log};
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn get_file(path: &str, offset: i64, name: Option<&str>) -> MergedFile {
+        MergedFile {
+            path: path.to_string(),
+            offset: chrono::TimeDelta::seconds(offset),
+            pattern: None,
+            name: name.map(|n| n.to_string()),
+        }
+    }
+
+    fn get_log(source_name: &str, message: &str, second: i64) -> MonitorMessage {
+        let start = chrono::DateTime::parse_from_rfc3339("2024-03-01T12:00:00+00:00")
+            .unwrap()
+            .with_timezone(&chrono::Local);
+        MonitorMessage::Log(Log {
+            source_name: source_name.to_string(),
+            message: message.to_string(),
+            timestamp: start + chrono::TimeDelta::seconds(second),
+        })
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = get_file("a.txt", 0, None);
+        let second = get_file("b.txt", -60, Some("host-b"));
+        let mut event = match get_log("cmd:test", "", 62) {
+            MonitorMessage::Log(log) => Event {
+                source_name: log.source_name,
+                description: "Process exited".to_string(),
+                timestamp: log.timestamp,
+            },
+            _ => unreachable!(),
+        };
+
+        let timeline = merge(vec![
+            (
+                &first,
+                session_log::SessionLog {
+                    messages: vec![
+                        get_log("/dev/ttyUSB0", "boot", 0),
+                        MonitorMessage::UnsolictedMessage("login: ".to_string()),
+                        get_log("/dev/ttyUSB0", "ready", 3),
+                    ],
+                    skipped: 0,
+                },
+            ),
+            (
+                &second,
+                session_log::SessionLog {
+                    messages: vec![
+                        get_log("/dev/ttyUSB0", "flash", 61),
+                        MonitorMessage::Event(event.clone()),
+                    ],
+                    skipped: 0,
+                },
+            ),
+        ]);
+
+        event.source_name = "host-b:cmd:test".to_string();
+        event.timestamp -= chrono::TimeDelta::seconds(60);
+        assert_eq!(
+            timeline,
+            vec![
+                get_log("/dev/ttyUSB0", "boot", 0),
+                MonitorMessage::UnsolictedMessage("login: ".to_string()),
+                get_log("host-b:/dev/ttyUSB0", "flash", 1),
+                MonitorMessage::Event(event),
+                get_log("/dev/ttyUSB0", "ready", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = to_args(&[
+            "a.txt",
+            "b.log",
+            "--offset",
+            "b.log=-1.5",
+            "--pattern",
+            "b.log=%Y-%m-%d %H:%M:%S",
+            "--name",
+            "b.log=gateway",
+            "--output",
+            "merged.txt",
+        ]);
+        assert_eq!(
+            parse_args(&args),
+            Ok(MergeArguments {
+                files: vec![
+                    get_file("a.txt", 0, None),
+                    MergedFile {
+                        path: "b.log".to_string(),
+                        offset: chrono::TimeDelta::milliseconds(-1500),
+                        pattern: Some("%Y-%m-%d %H:%M:%S".to_string()),
+                        name: Some("gateway".to_string()),
+                    },
+                ],
+                output_path: Some("merged.txt".to_string()),
+            })
+        );
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "--offset", "b.txt=1"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "--offset", "a.txt=soon"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "--name", "a.txt"])).is_err());
+        assert!(parse_args(&to_args(&["a.txt", "--output"])).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use super::data::ProcessSettings;
+use super::log_monitor::{send_event, AsyncLogMonitor, MonitorMessage, PortRequest, WriteProxy};
+use super::read_line;
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::ChildStdin;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_BUFFER_SIZE: usize = 128;
+const BUFFER_COMPLETION_TIMEOUT: u64 = 50;
+
+/// Runs a command and reads logs from its output, e.g. an emulator with its serial
+/// console on stdio. Lines written to stderr are logged under a separate name.
+pub struct ProcessLogMonitor {
+    settings: ProcessSettings,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    stdout_lines: read_line::LineAssembler,
+    stderr_lines: read_line::LineAssembler,
+}
+
+impl ProcessLogMonitor {
+    pub fn new(settings: ProcessSettings) -> ProcessLogMonitor {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        let stdout_lines = read_line::LineAssembler::new(settings.get_name());
+        let stderr_lines = read_line::LineAssembler::new(settings.get_stderr_name());
+
+        ProcessLogMonitor {
+            settings,
+            write_receiver,
+            write_sender,
+            stdout_lines,
+            stderr_lines,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    async fn handle_write_request(
+        &mut self,
+        stdin: &mut Option<ChildStdin>,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        let byte = match request {
+            // the process reads lines from a pipe, not from a terminal
+            PortRequest::Write(b'\r') => b'\n',
+            PortRequest::Write(byte) => byte,
+            request => {
+                let description = request.unsupported_description("for processes");
+                self.send_event(description, sender_queue);
+                return;
+            }
+        };
+
+        let description = match stdin {
+            Some(pipe) => match pipe.write_all(&[byte]).await {
+                Ok(_) => return,
+                Err(e) => {
+                    *stdin = None;
+                    format!("Failed to write to the process: {}", e)
+                }
+            },
+            None => "Process input is closed, input dropped".to_string(),
+        };
+        self.send_event(description, sender_queue);
+    }
+
+    /// Reads the output of the process until it exits. Returns false if the monitor
+    /// was cancelled before.
+    async fn run_process(
+        &mut self,
+        cancel_token: &CancellationToken,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> bool {
+        let spawn_result = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.settings.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match spawn_result {
+            Ok(c) => c,
+            Err(e) => {
+                self.send_event(format!("Failed to start the process: {}", e), sender_queue);
+                return true;
+            }
+        };
+
+        let mut stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let mut stdout_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        let mut stderr_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        let mut stdout_open = true;
+        let mut stderr_open = true;
+        let flush_timeout = std::time::Duration::from_millis(BUFFER_COMPLETION_TIMEOUT);
+
+        let status = loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return false;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if let Some(request) = request {
+                        self.handle_write_request(&mut stdin, request, sender_queue).await;
+                    }
+                }
+
+                read_result = stdout.read(&mut stdout_buffer), if stdout_open => match read_result {
+                    Ok(n) if n > 0 => self.stdout_lines.push(&stdout_buffer[0..n], sender_queue),
+                    _ => stdout_open = false,
+                },
+
+                read_result = stderr.read(&mut stderr_buffer), if stderr_open => match read_result {
+                    Ok(n) if n > 0 => self.stderr_lines.push(&stderr_buffer[0..n], sender_queue),
+                    _ => stderr_open = false,
+                },
+
+                // the exit is reported once all of the output is logged
+                status = child.wait(), if !stdout_open && !stderr_open => {
+                    break status;
+                }
+
+                _ = tokio::time::sleep(flush_timeout) => {
+                    self.stdout_lines.flush(sender_queue);
+                    self.stderr_lines.flush(sender_queue);
+                }
+            }
+        };
+
+        self.stdout_lines.flush(sender_queue);
+        self.stderr_lines.flush(sender_queue);
+        let description = match status {
+            Ok(status) => match (status.code(), status.signal()) {
+                (Some(code), _) => format!("Process exited with code {}", code),
+                (None, Some(signal)) => format!("Process killed by signal {}", signal),
+                (None, None) => format!("Process exited: {}", status),
+            },
+            Err(e) => format!("Failed to wait for the process: {}", e),
+        };
+        self.send_event(description, sender_queue);
+        true
+    }
+}
+
+impl AsyncLogMonitor for ProcessLogMonitor {
+    fn get_common_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        if !self.run_process(&cancel_token, &sender_queue).await {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if request.is_some() {
+                        let description = "Process is not running, input dropped".to_string();
+                        self.send_event(description, &sender_queue);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::next_message;
+
+    #[tokio::test]
+    async fn test_output_input_and_exit_status() {
+        let mut monitor = ProcessLogMonitor::new(ProcessSettings {
+            command: "echo out; echo err >&2; read line; echo \"got $line\"; exit 3".to_string(),
+        });
+        let name = monitor.get_common_name();
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        let mut logs = Vec::new();
+        while logs.len() < 2 {
+            if let MonitorMessage::Log(log) = next_message(&mut receiver).await {
+                logs.push((log.source_name, log.message));
+            }
+        }
+        logs.sort();
+        assert_eq!(
+            logs,
+            vec![
+                (name.clone(), "out".to_string()),
+                (format!("{} (stderr)", name), "err".to_string()),
+            ]
+        );
+
+        for byte in b"hi\r" {
+            write_proxy.request(PortRequest::Write(*byte)).unwrap();
+        }
+        loop {
+            match next_message(&mut receiver).await {
+                MonitorMessage::Log(log) => {
+                    assert_eq!(log.message, "got hi");
+                    break;
+                }
+                MonitorMessage::UnsolictedMessage(_) => {}
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+        }
+        loop {
+            if let MonitorMessage::Event(event) = next_message(&mut receiver).await {
+                assert_eq!(event.description, "Process exited with code 3");
+                break;
+            }
+        }
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use super::data::PtySettings;
+use super::fd::{self, check};
+use super::log_monitor::{send_event, MonitorMessage, PortActivity, PortRequest, WriteProxy};
+use super::read_line;
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+const BUFFER_COMPLETION_TIMEOUT: u64 = 50;
+
+/// Pseudo-terminal pair. The slave side is kept open, so the master does not
+/// report a hang-up while no other program has the terminal open.
+struct Pty {
+    master: AsyncFd<OwnedFd>,
+    _slave: OwnedFd,
+    slave_path: String,
+}
+
+fn open_pty() -> std::io::Result<Pty> {
+    let master = unsafe {
+        let fd = check(libc::posix_openpt(
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK,
+        ))?;
+        OwnedFd::from_raw_fd(fd)
+    };
+    let mut name = [0 as libc::c_char; 128];
+    unsafe {
+        check(libc::grantpt(master.as_raw_fd()))?;
+        check(libc::unlockpt(master.as_raw_fd()))?;
+        let result = libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len());
+        if result != 0 {
+            return Err(std::io::Error::from_raw_os_error(result));
+        }
+    }
+    let slave_path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .to_string();
+
+    let slave = unsafe {
+        let fd = check(libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY))?;
+        OwnedFd::from_raw_fd(fd)
+    };
+    // the bytes are relayed as they are, without echo or line editing
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+        libc::cfmakeraw(&mut termios);
+        check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+    }
+
+    let master = fd::register(master)?;
+
+    Ok(Pty {
+        master,
+        _slave: slave,
+        slave_path,
+    })
+}
+
+/// Points the link at the terminal, replacing a link left by an earlier session.
+fn create_link(target: &str, link: &str) -> Result<(), String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(link) {
+        if !metadata.file_type().is_symlink() {
+            return Err(format!("{} exists and is not a symbolic link", link));
+        }
+        std::fs::remove_file(link).map_err(|e| format!("Failed to replace {}: {}", link, e))?;
+    }
+
+    std::os::unix::fs::symlink(target, link)
+        .map_err(|e| format!("Failed to create {}: {}", link, e))
+}
+
+/// Lets other programs use a local port through a pseudo-terminal, while the port
+/// is still monitored. What the programs write is logged under its own name.
+pub struct PtyInterposer {
+    settings: PtySettings,
+    activity_sender: broadcast::Sender<PortActivity>,
+    /// None for read-only ports
+    write_proxy: Option<WriteProxy>,
+    tx_lines: read_line::LineAssembler,
+    input_dropped: bool,
+}
+
+impl PtyInterposer {
+    pub fn new(
+        settings: PtySettings,
+        activity_sender: broadcast::Sender<PortActivity>,
+        write_proxy: Option<WriteProxy>,
+    ) -> PtyInterposer {
+        let tx_lines = read_line::LineAssembler::new(settings.get_tx_name());
+
+        PtyInterposer {
+            settings,
+            activity_sender,
+            write_proxy,
+            tx_lines,
+            input_dropped: false,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.settings.port.clone(), description, sender_queue);
+    }
+
+    fn forward_to_port(&mut self, data: &[u8], sender_queue: &UnboundedSender<MonitorMessage>) {
+        self.tx_lines.push(data, sender_queue);
+
+        let write_proxy = match &self.write_proxy {
+            Some(w) => w,
+            None => {
+                if !self.input_dropped {
+                    self.input_dropped = true;
+                    let description = "Port is read-only, input from the PTY dropped".to_string();
+                    self.send_event(description, sender_queue);
+                }
+                return;
+            }
+        };
+
+        for byte in data {
+            if write_proxy.request(PortRequest::Write(*byte)).is_err() {
+                return;
+            }
+        }
+    }
+
+    pub async fn run(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        let pty = match open_pty() {
+            Ok(p) => p,
+            Err(e) => {
+                self.send_event(format!("Failed to open a PTY: {}", e), &sender_queue);
+                return;
+            }
+        };
+        if let Err(e) = create_link(&pty.slave_path, &self.settings.link) {
+            self.send_event(e, &sender_queue);
+            return;
+        }
+        let description = format!(
+            "Relaying through {} linked as {}",
+            pty.slave_path, self.settings.link
+        );
+        self.send_event(description, &sender_queue);
+
+        let mut activity_receiver = self.activity_sender.subscribe();
+        let mut recv_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        let flush_timeout = std::time::Duration::from_millis(BUFFER_COMPLETION_TIMEOUT);
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    break;
+                }
+
+                activity = activity_receiver.recv() => match activity {
+                    // a full buffer means nobody reads the terminal, so the data is dropped
+                    Ok(PortActivity::Received(data)) => {
+                        let _ = fd::write_now(&pty.master, &data);
+                    }
+                    Ok(PortActivity::ModemLines(_)) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                },
+
+                read_result = fd::read_fd(&pty.master, &mut recv_buffer) => match read_result {
+                    Ok(n) => self.forward_to_port(&recv_buffer[0..n], &sender_queue),
+                    Err(e) => {
+                        let description = format!("Failed to read from the PTY: {}", e);
+                        self.send_event(description, &sender_queue);
+                        break;
+                    }
+                },
+
+                _ = tokio::time::sleep(flush_timeout) => {
+                    self.tx_lines.flush(&sender_queue);
+                }
+            }
+        }
+
+        let _ = std::fs::remove_file(&self.settings.link);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get_temp_path;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    #[tokio::test]
+    async fn test_relay_both_directions() {
+        let link = get_temp_path("pty");
+        let (activity_sender, _) = broadcast::channel(16);
+        let (request_sender, mut request_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut interposer = PtyInterposer::new(
+            PtySettings {
+                port: "/dev/ttyUSB0".to_string(),
+                link: link.clone(),
+            },
+            activity_sender.clone(),
+            Some(WriteProxy::new(request_sender)),
+        );
+        let cancel_token = CancellationToken::new();
+        let interposer_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            interposer.run(interposer_cancel_token, sender).await;
+        });
+
+        match receiver.recv().await.unwrap() {
+            MonitorMessage::Event(event) => assert!(event.description.starts_with("Relaying")),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        let mut terminal = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&link)
+            .unwrap();
+
+        terminal.write_all(b"AT\r\n").unwrap();
+        for byte in b"AT\r\n" {
+            assert_eq!(
+                request_receiver.recv().await.unwrap(),
+                PortRequest::Write(*byte)
+            );
+        }
+        match receiver.recv().await.unwrap() {
+            MonitorMessage::Log(log) => {
+                assert_eq!(log.source_name, "/dev/ttyUSB0 (tx)");
+                assert_eq!(log.message, "AT");
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        activity_sender
+            .send(PortActivity::Received(b"OK\r\n".to_vec()))
+            .unwrap();
+        let mut received = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while received.len() < 4 && std::time::Instant::now() < deadline {
+            let mut buffer = [0u8; 16];
+            match terminal.read(&mut buffer) {
+                Ok(n) => received.extend_from_slice(&buffer[0..n]),
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(5)).await,
+            }
+        }
+        assert_eq!(received, b"OK\r\n");
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+        assert!(std::fs::symlink_metadata(&link).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use super::log_monitor::{send_event, MonitorMessage};
+
+use std::os::unix::fs::FileTypeExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+/// Lines kept for slow clients of the published messages.
+pub const PUBLISHED_CAPACITY: usize = 1024;
+const ACCEPT_RETRY_DELAY: u64 = 1000;
+
+/// Publishes the merged messages of all sources on a Unix domain socket, as JSON
+/// lines, to any number of local clients.
+pub struct MessagePublisher {
+    path: String,
+    line_sender: broadcast::Sender<String>,
+}
+
+impl MessagePublisher {
+    pub fn new(path: String, line_sender: broadcast::Sender<String>) -> MessagePublisher {
+        MessagePublisher { path, line_sender }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.path.clone(), description, sender_queue);
+    }
+
+    /// Binds the socket, replacing one left by an earlier session.
+    fn bind(&self) -> Result<UnixListener, String> {
+        if let Ok(metadata) = std::fs::symlink_metadata(&self.path) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket", self.path));
+            }
+            std::fs::remove_file(&self.path)
+                .map_err(|e| format!("Failed to replace {}: {}", self.path, e))?;
+        }
+
+        UnixListener::bind(&self.path).map_err(|e| format!("Failed to publish: {}", e))
+    }
+
+    pub async fn run(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        let listener = match self.bind() {
+            Ok(l) => l,
+            Err(e) => {
+                self.send_event(e, &sender_queue);
+                return;
+            }
+        };
+        self.send_event("Publishing messages".to_string(), &sender_queue);
+
+        let mut failing = false;
+        loop {
+            let stream = tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    break;
+                }
+
+                result = listener.accept() => match result {
+                    Ok((stream, _)) => {
+                        failing = false;
+                        stream
+                    }
+                    // e.g. too many open files, reported once until a client is accepted
+                    Err(e) => {
+                        if !failing {
+                            failing = true;
+                            let description = format!("Failed to accept a client: {}", e);
+                            self.send_event(description, &sender_queue);
+                        }
+                        let delay = std::time::Duration::from_millis(ACCEPT_RETRY_DELAY);
+                        tokio::select! {
+                            _ = cancel_token.cancelled() => break,
+                            _ = tokio::time::sleep(delay) => continue,
+                        }
+                    }
+                }
+            };
+
+            let line_receiver = self.line_sender.subscribe();
+            let client_cancel_token = cancel_token.clone();
+            tokio::spawn(async move {
+                send_lines(stream, line_receiver, client_cancel_token).await;
+            });
+        }
+
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends the published lines to a client until it goes away. A client too slow to
+/// keep up misses the lines dropped in the meantime.
+async fn send_lines(
+    mut stream: UnixStream,
+    mut line_receiver: broadcast::Receiver<String>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        let line = tokio::select! {
+            _ = cancel_token.cancelled() => {
+                return;
+            }
+
+            line = line_receiver.recv() => match line {
+                Ok(line) => line,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        };
+
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::Log;
+    use crate::test_util::get_temp_path;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[tokio::test]
+    async fn test_publish_to_clients() {
+        let path = get_temp_path("publish.sock");
+        let (line_sender, _) = broadcast::channel(PUBLISHED_CAPACITY);
+        let mut publisher = MessagePublisher::new(path.clone(), line_sender.clone());
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let publisher_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            publisher.run(publisher_cancel_token, sender).await;
+        });
+
+        match receiver.recv().await.unwrap() {
+            MonitorMessage::Event(event) => assert_eq!(event.description, "Publishing messages"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        let client = UnixStream::connect(&path).await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        while line_sender.receiver_count() == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let msg = MonitorMessage::Log(Log {
+            source_name: "/dev/ttyUSB0".to_string(),
+            message: "hello".to_string(),
+            timestamp: chrono::Local::now(),
+        });
+        line_sender.send(msg.to_json_line()).unwrap();
+        let line = tokio::time::timeout(std::time::Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(format!("{}\n", line), msg.to_json_line());
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+        assert!(std::fs::symlink_metadata(&path).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+use crate::log_monitor::{Log, MonitorMessage};
+
+use tokio::sync::mpsc::UnboundedSender;
+
 pub fn read_line_from_buffer(buffer: &mut Vec<u8>) -> Option<String> {
     if let Some(i) = buffer.iter().position(|&x| x == b'\n') {
         let line = String::from_utf8_lossy(&buffer[..=i]).to_string();
@@ -8,6 +12,65 @@ pub fn read_line_from_buffer(buffer: &mut Vec<u8>) -> Option<String> {
     }
 }
 
+/// Turns data received from a single source into log messages,
+/// one per complete line. Data without a line ending is sent as an unsolicited
+/// message when the source goes quiet.
+pub struct LineAssembler {
+    source_name: String,
+    process_buffer: Vec<u8>,
+    /// When the first byte of the buffered line arrived
+    line_start: chrono::DateTime<chrono::Local>,
+}
+
+impl LineAssembler {
+    pub fn new(source_name: String) -> LineAssembler {
+        LineAssembler {
+            source_name,
+            process_buffer: vec![],
+            line_start: chrono::Local::now(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8], sender_queue: &UnboundedSender<MonitorMessage>) {
+        // lines are stamped with the arrival of their first byte, not of their end
+        let now = chrono::Local::now();
+        if self.process_buffer.is_empty() {
+            self.line_start = now;
+        }
+
+        // concatenate the new data to the process buffer
+        self.process_buffer.extend_from_slice(data);
+
+        while let Some(line) = read_line_from_buffer(&mut self.process_buffer) {
+            let stripped_line = line.trim().to_string();
+            let message = Log {
+                source_name: self.source_name.clone(),
+                message: stripped_line,
+                timestamp: self.line_start,
+            };
+            sender_queue.send(MonitorMessage::Log(message)).unwrap();
+            self.line_start = now;
+        }
+    }
+
+    /// Sends the incomplete line, if any.
+    pub fn flush(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        if self.process_buffer.is_empty() {
+            return;
+        }
+
+        let unsolicted_msg = String::from_utf8_lossy(self.process_buffer.as_slice()).to_string();
+        self.process_buffer.clear();
+        sender_queue
+            .send(MonitorMessage::UnsolictedMessage(unsolicted_msg))
+            .unwrap();
+    }
+
+    pub fn clear(&mut self) {
+        self.process_buffer.clear();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,4 +107,28 @@ mod tests {
         assert_eq!(line3, "end\n");
         assert!(buffer2.is_empty());
     }
+
+    #[test]
+    fn test_line_assembler() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let mut lines = LineAssembler::new("port".to_string());
+
+        lines.push(b"hello\r\nwor", &sender);
+        match receiver.try_recv().unwrap() {
+            MonitorMessage::Log(log) => {
+                assert_eq!(log.source_name, "port");
+                assert_eq!(log.message, "hello");
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        assert!(receiver.try_recv().is_err());
+
+        lines.flush(&sender);
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            MonitorMessage::UnsolictedMessage("wor".to_string())
+        );
+        lines.flush(&sender);
+        assert!(receiver.try_recv().is_err());
+    }
 }
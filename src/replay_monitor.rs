@@ -0,0 +1,372 @@
+use super::data::ReplaySettings;
+use super::log_monitor::{send_event, AsyncLogMonitor, MonitorMessage, PortRequest, WriteProxy};
+use super::session_log;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+const SEEK_STEP: chrono::TimeDelta = chrono::TimeDelta::seconds(10);
+pub const MIN_SPEED: f64 = 1.0 / 64.0;
+pub const MAX_SPEED: f64 = 64.0;
+
+/// Key controlling the replay, typed while the replay is the input target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Control {
+    TogglePause,
+    Faster,
+    Slower,
+    SeekForward,
+    SeekBackward,
+}
+
+impl Control {
+    fn from_key(byte: u8) -> Option<Control> {
+        match byte {
+            b' ' => Some(Control::TogglePause),
+            b'+' => Some(Control::Faster),
+            b'-' => Some(Control::Slower),
+            b'>' => Some(Control::SeekForward),
+            b'<' => Some(Control::SeekBackward),
+            _ => None,
+        }
+    }
+}
+
+/// Sends the messages of a recorded session again, keeping the original sources,
+/// timestamps and gaps between the messages, so they are handled like live ones.
+pub struct ReplayLogMonitor {
+    settings: ReplaySettings,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    messages: Vec<MonitorMessage>,
+    /// Time of every message, unsolicited messages take the time of the one before
+    times: Vec<chrono::DateTime<chrono::Local>>,
+    /// Index of the next message to send
+    position: usize,
+    speed: f64,
+    /// When the next message is due, None while paused
+    deadline: Option<Instant>,
+    /// Time left until the next message when paused
+    remaining: std::time::Duration,
+    paused: bool,
+}
+
+impl ReplayLogMonitor {
+    pub fn new(settings: ReplaySettings) -> ReplayLogMonitor {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        let speed = settings.speed;
+
+        ReplayLogMonitor {
+            settings,
+            write_receiver,
+            write_sender,
+            messages: Vec::new(),
+            times: Vec::new(),
+            position: 0,
+            speed,
+            deadline: None,
+            remaining: std::time::Duration::ZERO,
+            paused: false,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    fn load(&mut self, messages: Vec<MonitorMessage>) {
+        let mut last_time = None;
+        self.times = messages
+            .iter()
+            .map(|msg| {
+                let time = msg.get_timestamp().or(last_time).unwrap_or_default();
+                last_time = Some(time);
+                time
+            })
+            .collect();
+        self.messages = messages;
+        self.position = 0;
+    }
+
+    /// Time from the previous message to the next one, at the current speed.
+    fn get_delay(&self) -> std::time::Duration {
+        if self.position == 0 || self.position >= self.times.len() {
+            return std::time::Duration::ZERO;
+        }
+
+        let gap = self.times[self.position] - self.times[self.position - 1];
+        gap.to_std().unwrap_or_default().div_f64(self.speed)
+    }
+
+    /// Position in the recording, counted from its first message.
+    fn get_elapsed_text(&self) -> String {
+        let (first, current) = match (self.times.first(), self.times.get(self.position)) {
+            (Some(first), Some(current)) => (*first, *current),
+            (Some(first), None) => (*first, *self.times.last().unwrap()),
+            _ => return "00:00:00".to_string(),
+        };
+        let seconds = (current - first).num_seconds().max(0);
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+
+    fn seek(&mut self, step: chrono::TimeDelta) {
+        let current = match self.times.get(self.position).or(self.times.last()) {
+            Some(t) => *t,
+            None => return,
+        };
+        let target = current + step;
+        self.position = self
+            .times
+            .iter()
+            .position(|t| *t >= target)
+            .unwrap_or(self.times.len());
+    }
+
+    /// Applies a control key and returns the description of the new state.
+    fn apply(&mut self, control: Control) -> String {
+        match control {
+            Control::TogglePause => {
+                self.paused = !self.paused;
+                if self.paused {
+                    self.remaining = match self.deadline.take() {
+                        Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                        None => std::time::Duration::ZERO,
+                    };
+                    return format!("Paused at {}", self.get_elapsed_text());
+                }
+                self.deadline = Some(Instant::now() + self.remaining);
+                return format!("Resumed at {}", self.get_elapsed_text());
+            }
+            Control::Faster => self.speed = (self.speed * 2.0).min(MAX_SPEED),
+            Control::Slower => self.speed = (self.speed / 2.0).max(MIN_SPEED),
+            Control::SeekForward => self.seek(SEEK_STEP),
+            Control::SeekBackward => self.seek(-SEEK_STEP),
+        }
+
+        // the next message is due again after its gap at the new speed or position
+        self.deadline = None;
+        self.remaining = std::time::Duration::ZERO;
+        match control {
+            Control::Faster | Control::Slower => format!("Replay speed {}x", self.speed),
+            _ => format!("Seeked to {}", self.get_elapsed_text()),
+        }
+    }
+
+    fn handle_write_request(
+        &mut self,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        let description = match request {
+            PortRequest::Write(byte) => match Control::from_key(byte) {
+                Some(control) => self.apply(control),
+                None => "Replay is controlled with space (pause), + and - (speed), < and > (seek)"
+                    .to_string(),
+            },
+            _ => "Only the replay controls are available when replaying".to_string(),
+        };
+        self.send_event(description, sender_queue);
+    }
+
+    /// Sends the next message, returns false once all of them were sent.
+    fn send_next(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) -> bool {
+        match self.messages.get(self.position) {
+            Some(msg) => {
+                sender_queue.send(msg.clone()).unwrap();
+                self.position += 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl AsyncLogMonitor for ReplayLogMonitor {
+    fn get_common_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        let read_result = session_log::read(
+            &self.settings.path,
+            self.settings.format,
+            self.settings.baud_rate,
+        );
+        match read_result {
+            Ok(log) => {
+                let mut description = format!("Replaying {} messages", log.messages.len());
+                if log.skipped > 0 {
+                    description += &format!(", {} unreadable lines skipped", log.skipped);
+                }
+                self.send_event(description, &sender_queue);
+                self.load(log.messages);
+            }
+            Err(e) => self.send_event(e, &sender_queue),
+        }
+
+        let mut finished = false;
+
+        loop {
+            if self.deadline.is_none() && !self.paused {
+                self.deadline = Some(Instant::now() + self.get_delay());
+            }
+            let playing = !self.paused && !finished;
+            let deadline = self.deadline.unwrap_or_else(Instant::now);
+
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if let Some(request) = request {
+                        self.handle_write_request(request, &sender_queue);
+                        // seeking back restarts a finished replay
+                        finished = self.position >= self.messages.len() && finished;
+                    }
+                }
+
+                _ = tokio::time::sleep_until(deadline), if playing => {
+                    self.deadline = None;
+                    if !self.send_next(&sender_queue) {
+                        finished = true;
+                        self.send_event("Replay finished".to_string(), &sender_queue);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::LogFormat;
+    use crate::log_monitor::Log;
+    use crate::test_util::{get_temp_path, next_message};
+
+    fn create_monitor(count: usize, gap: chrono::TimeDelta) -> ReplayLogMonitor {
+        let mut monitor = ReplayLogMonitor::new(ReplaySettings {
+            path: "session.txt".to_string(),
+            format: LogFormat::Auto,
+            speed: 1.0,
+            baud_rate: 115_200,
+        });
+        let start = chrono::Local::now();
+        let messages = (0..count)
+            .map(|i| {
+                MonitorMessage::Log(Log {
+                    source_name: "/dev/ttyUSB0".to_string(),
+                    message: format!("line {}", i),
+                    timestamp: start + gap * i as i32,
+                })
+            })
+            .collect();
+        monitor.load(messages);
+        monitor
+    }
+
+    #[test]
+    fn test_delay_follows_speed() {
+        let mut monitor = create_monitor(3, chrono::TimeDelta::milliseconds(400));
+        assert_eq!(monitor.get_delay(), std::time::Duration::ZERO);
+
+        monitor.position = 1;
+        assert_eq!(monitor.get_delay(), std::time::Duration::from_millis(400));
+        assert_eq!(monitor.apply(Control::Faster), "Replay speed 2x");
+        assert_eq!(monitor.get_delay(), std::time::Duration::from_millis(200));
+        monitor.apply(Control::Slower);
+        monitor.apply(Control::Slower);
+        assert_eq!(monitor.get_delay(), std::time::Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut monitor = create_monitor(10, chrono::TimeDelta::seconds(5));
+
+        assert_eq!(monitor.apply(Control::SeekForward), "Seeked to 00:00:10");
+        assert_eq!(monitor.position, 2);
+        monitor.apply(Control::SeekForward);
+        monitor.apply(Control::SeekBackward);
+        assert_eq!(monitor.position, 2);
+        monitor.apply(Control::SeekBackward);
+        monitor.apply(Control::SeekBackward);
+        assert_eq!(monitor.position, 0);
+
+        monitor.position = 9;
+        monitor.apply(Control::SeekForward);
+        assert_eq!(monitor.position, 10);
+        assert_eq!(monitor.get_elapsed_text(), "00:00:45");
+    }
+
+    #[tokio::test]
+    async fn test_replay_with_pause() {
+        let path = get_temp_path("replay.jsonl");
+        let source = create_monitor(2, chrono::TimeDelta::milliseconds(400));
+        let text: String = source.messages.iter().map(|m| m.to_json_line()).collect();
+        std::fs::write(&path, text).unwrap();
+
+        let mut monitor = ReplayLogMonitor::new(ReplaySettings {
+            path: path.clone(),
+            format: LogFormat::Auto,
+            speed: 2.0,
+            baud_rate: 115_200,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        let start = Instant::now();
+        match next_message(&mut receiver).await {
+            MonitorMessage::Event(event) => assert_eq!(event.description, "Replaying 2 messages"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        match next_message(&mut receiver).await {
+            MonitorMessage::Log(log) => assert_eq!(log.message, "line 0"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        write_proxy.request(PortRequest::Write(b' ')).unwrap();
+        match next_message(&mut receiver).await {
+            MonitorMessage::Event(event) => assert_eq!(event.description, "Paused at 00:00:00"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        write_proxy.request(PortRequest::Write(b' ')).unwrap();
+        next_message(&mut receiver).await;
+
+        // the 400 ms gap takes 200 ms at double speed, after the pause
+        match next_message(&mut receiver).await {
+            MonitorMessage::Log(log) => assert_eq!(log.message, "line 1"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        assert!(start.elapsed() >= std::time::Duration::from_millis(450));
+        match next_message(&mut receiver).await {
+            MonitorMessage::Event(event) => assert_eq!(event.description, "Replay finished"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,814 @@
+//! Telnet framing and the COM port control option defined by RFC 2217.
+
+use crate::data::PortSetting;
+use crate::log_monitor::{ModemLines, PortRequest};
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const BINARY: u8 = 0;
+pub const SUPPRESS_GO_AHEAD: u8 = 3;
+pub const COM_PORT_OPTION: u8 = 44;
+
+/// Servers answer with the client command code increased by this offset.
+const SERVER_OFFSET: u8 = 100;
+
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_LINESTATE: u8 = 6;
+const NOTIFY_MODEMSTATE: u8 = 7;
+
+pub const CONTROL_REQUEST_FLOW_CONTROL: u8 = 0;
+pub const CONTROL_NO_FLOW_CONTROL: u8 = 1;
+pub const CONTROL_XON_XOFF: u8 = 2;
+pub const CONTROL_HARDWARE_FLOW_CONTROL: u8 = 3;
+pub const CONTROL_REQUEST_BREAK: u8 = 4;
+pub const CONTROL_BREAK_ON: u8 = 5;
+pub const CONTROL_BREAK_OFF: u8 = 6;
+pub const CONTROL_REQUEST_DTR: u8 = 7;
+pub const CONTROL_DTR_ON: u8 = 8;
+pub const CONTROL_DTR_OFF: u8 = 9;
+pub const CONTROL_REQUEST_RTS: u8 = 10;
+pub const CONTROL_RTS_ON: u8 = 11;
+pub const CONTROL_RTS_OFF: u8 = 12;
+pub const CONTROL_REQUEST_INBOUND_FLOW_CONTROL: u8 = 13;
+pub const CONTROL_INBOUND_NO_FLOW_CONTROL: u8 = 14;
+pub const CONTROL_INBOUND_XON_XOFF: u8 = 15;
+pub const CONTROL_INBOUND_HARDWARE_FLOW_CONTROL: u8 = 16;
+pub const CONTROL_DSR_FLOW_CONTROL: u8 = 19;
+
+const MODEMSTATE_CTS: u8 = 0x10;
+const MODEMSTATE_DSR: u8 = 0x20;
+const MODEMSTATE_RI: u8 = 0x40;
+const MODEMSTATE_DCD: u8 = 0x80;
+
+/// Part of a telnet stream, in the order of arrival.
+#[derive(Debug, PartialEq)]
+pub enum TelnetItem {
+    /// Unescaped data bytes
+    Data(Vec<u8>),
+    /// DO, DONT, WILL or WONT with the option code
+    Negotiation(u8, u8),
+    /// Option code followed by the option payload
+    Subnegotiation(Vec<u8>),
+}
+
+#[derive(Clone, Copy)]
+enum DecoderState {
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Splits a telnet stream into data and protocol items. Keeps the state
+/// between calls, so sequences split across reads are handled.
+pub struct TelnetDecoder {
+    state: DecoderState,
+    subnegotiation: Vec<u8>,
+}
+
+impl TelnetDecoder {
+    pub fn new() -> TelnetDecoder {
+        TelnetDecoder {
+            state: DecoderState::Data,
+            subnegotiation: Vec::new(),
+        }
+    }
+
+    pub fn decode(&mut self, input: &[u8], items: &mut Vec<TelnetItem>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (DecoderState::Data, IAC) => DecoderState::Iac,
+                (DecoderState::Data, _) | (DecoderState::Iac, IAC) => {
+                    match items.last_mut() {
+                        Some(TelnetItem::Data(data)) => data.push(byte),
+                        _ => items.push(TelnetItem::Data(vec![byte])),
+                    }
+                    DecoderState::Data
+                }
+                (DecoderState::Iac, DO | DONT | WILL | WONT) => DecoderState::Negotiation(byte),
+                (DecoderState::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    DecoderState::Subnegotiation
+                }
+                // other telnet commands carry no meaning for a serial link
+                (DecoderState::Iac, _) => DecoderState::Data,
+                (DecoderState::Negotiation(verb), option) => {
+                    items.push(TelnetItem::Negotiation(verb, option));
+                    DecoderState::Data
+                }
+                (DecoderState::Subnegotiation, IAC) => DecoderState::SubnegotiationIac,
+                (DecoderState::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    DecoderState::Subnegotiation
+                }
+                (DecoderState::SubnegotiationIac, SE) => {
+                    items.push(TelnetItem::Subnegotiation(std::mem::take(
+                        &mut self.subnegotiation,
+                    )));
+                    DecoderState::Data
+                }
+                (DecoderState::SubnegotiationIac, _) => {
+                    self.subnegotiation.push(byte);
+                    DecoderState::Subnegotiation
+                }
+            };
+        }
+    }
+}
+
+/// Doubles every IAC byte, so the data is not mistaken for a telnet command.
+pub fn escape_data(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+
+    escaped
+}
+
+pub fn encode_negotiation(verb: u8, option: u8) -> Vec<u8> {
+    vec![IAC, verb, option]
+}
+
+/// COM port control command, sent by the client or answered by the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ComPortCommand {
+    SetBaudRate(u32),
+    SetDataSize(u8),
+    SetParity(u8),
+    SetStopSize(u8),
+    SetControl(u8),
+    NotifyLineState(u8),
+    NotifyModemState(u8),
+    Other(u8, Vec<u8>),
+}
+
+impl ComPortCommand {
+    /// Encodes the whole subnegotiation, including the IAC SB and IAC SE framing.
+    pub fn encode(&self, from_server: bool) -> Vec<u8> {
+        let (code, payload) = match self {
+            ComPortCommand::SetBaudRate(baud_rate) => {
+                (SET_BAUDRATE, baud_rate.to_be_bytes().to_vec())
+            }
+            ComPortCommand::SetDataSize(value) => (SET_DATASIZE, vec![*value]),
+            ComPortCommand::SetParity(value) => (SET_PARITY, vec![*value]),
+            ComPortCommand::SetStopSize(value) => (SET_STOPSIZE, vec![*value]),
+            ComPortCommand::SetControl(value) => (SET_CONTROL, vec![*value]),
+            ComPortCommand::NotifyLineState(value) => (NOTIFY_LINESTATE, vec![*value]),
+            ComPortCommand::NotifyModemState(value) => (NOTIFY_MODEMSTATE, vec![*value]),
+            ComPortCommand::Other(code, payload) => (*code, payload.clone()),
+        };
+        let code = if from_server {
+            code + SERVER_OFFSET
+        } else {
+            code
+        };
+
+        let mut encoded = vec![IAC, SB, COM_PORT_OPTION, code];
+        encoded.extend(escape_data(&payload));
+        encoded.extend([IAC, SE]);
+        encoded
+    }
+
+    /// Parses the payload of a COM port subnegotiation (starting with the option code).
+    /// Returns the command and whether it was sent by the server.
+    pub fn parse(subnegotiation: &[u8]) -> Option<(ComPortCommand, bool)> {
+        let (code, payload) = match subnegotiation {
+            [COM_PORT_OPTION, code, payload @ ..] => (*code, payload),
+            _ => return None,
+        };
+        let (code, from_server) = if code >= SERVER_OFFSET {
+            (code - SERVER_OFFSET, true)
+        } else {
+            (code, false)
+        };
+
+        let command = match (code, payload) {
+            (SET_BAUDRATE, [a, b, c, d]) => {
+                ComPortCommand::SetBaudRate(u32::from_be_bytes([*a, *b, *c, *d]))
+            }
+            (SET_DATASIZE, [value]) => ComPortCommand::SetDataSize(*value),
+            (SET_PARITY, [value]) => ComPortCommand::SetParity(*value),
+            (SET_STOPSIZE, [value]) => ComPortCommand::SetStopSize(*value),
+            (SET_CONTROL, [value]) => ComPortCommand::SetControl(*value),
+            (NOTIFY_LINESTATE, [value]) => ComPortCommand::NotifyLineState(*value),
+            (NOTIFY_MODEMSTATE, [value]) => ComPortCommand::NotifyModemState(*value),
+            _ => ComPortCommand::Other(code, payload.to_vec()),
+        };
+
+        Some((command, from_server))
+    }
+
+    /// Describes a change confirmed by the server, the way local ports report it.
+    pub fn describe(&self) -> Option<String> {
+        let setting = match self {
+            ComPortCommand::SetBaudRate(0) => return None,
+            ComPortCommand::SetBaudRate(baud_rate) => format!("baud {}", baud_rate),
+            ComPortCommand::SetDataSize(value) => format!("databits {}", value),
+            ComPortCommand::SetParity(1) => "parity none".to_string(),
+            ComPortCommand::SetParity(2) => "parity odd".to_string(),
+            ComPortCommand::SetParity(3) => "parity even".to_string(),
+            ComPortCommand::SetStopSize(value @ (1 | 2)) => format!("stopbits {}", value),
+            ComPortCommand::SetControl(CONTROL_NO_FLOW_CONTROL) => "flow none".to_string(),
+            ComPortCommand::SetControl(CONTROL_XON_XOFF) => "flow software".to_string(),
+            ComPortCommand::SetControl(CONTROL_HARDWARE_FLOW_CONTROL) => {
+                "flow hardware".to_string()
+            }
+            ComPortCommand::SetControl(CONTROL_DTR_ON) => return Some("DTR set".to_string()),
+            ComPortCommand::SetControl(CONTROL_DTR_OFF) => return Some("DTR cleared".to_string()),
+            ComPortCommand::SetControl(CONTROL_RTS_ON) => return Some("RTS set".to_string()),
+            ComPortCommand::SetControl(CONTROL_RTS_OFF) => return Some("RTS cleared".to_string()),
+            _ => return None,
+        };
+
+        Some(format!("Port settings changed: {}", setting))
+    }
+}
+
+pub fn get_setting_command(setting: &PortSetting) -> ComPortCommand {
+    match setting {
+        PortSetting::BaudRate(baud_rate) => ComPortCommand::SetBaudRate(*baud_rate),
+        PortSetting::Parity(parity) => ComPortCommand::SetParity(match parity {
+            tokio_serial::Parity::None => 1,
+            tokio_serial::Parity::Odd => 2,
+            tokio_serial::Parity::Even => 3,
+        }),
+        PortSetting::FlowControl(flow_control) => ComPortCommand::SetControl(match flow_control {
+            tokio_serial::FlowControl::None => CONTROL_NO_FLOW_CONTROL,
+            tokio_serial::FlowControl::Software => CONTROL_XON_XOFF,
+            tokio_serial::FlowControl::Hardware => CONTROL_HARDWARE_FLOW_CONTROL,
+        }),
+        PortSetting::DataBits(data_bits) => ComPortCommand::SetDataSize(u8::from(*data_bits)),
+        PortSetting::StopBits(stop_bits) => ComPortCommand::SetStopSize(u8::from(*stop_bits)),
+    }
+}
+
+/// Translates a command sent by a client into a request for the local port.
+/// Breaks are not included, they are sent as two separate commands.
+pub fn get_port_request(command: &ComPortCommand) -> Option<PortRequest> {
+    let setting = match command {
+        ComPortCommand::SetBaudRate(0) => return None,
+        ComPortCommand::SetBaudRate(baud_rate) => PortSetting::BaudRate(*baud_rate),
+        ComPortCommand::SetDataSize(value) => PortSetting::DataBits(match value {
+            5 => tokio_serial::DataBits::Five,
+            6 => tokio_serial::DataBits::Six,
+            7 => tokio_serial::DataBits::Seven,
+            8 => tokio_serial::DataBits::Eight,
+            _ => return None,
+        }),
+        ComPortCommand::SetParity(value) => PortSetting::Parity(match value {
+            1 => tokio_serial::Parity::None,
+            2 => tokio_serial::Parity::Odd,
+            3 => tokio_serial::Parity::Even,
+            _ => return None,
+        }),
+        ComPortCommand::SetStopSize(value) => PortSetting::StopBits(match value {
+            1 => tokio_serial::StopBits::One,
+            2 => tokio_serial::StopBits::Two,
+            _ => return None,
+        }),
+        ComPortCommand::SetControl(value) => match *value {
+            CONTROL_NO_FLOW_CONTROL => PortSetting::FlowControl(tokio_serial::FlowControl::None),
+            CONTROL_XON_XOFF => PortSetting::FlowControl(tokio_serial::FlowControl::Software),
+            CONTROL_HARDWARE_FLOW_CONTROL => {
+                PortSetting::FlowControl(tokio_serial::FlowControl::Hardware)
+            }
+            CONTROL_DTR_ON => return Some(PortRequest::SetDtr(true)),
+            CONTROL_DTR_OFF => return Some(PortRequest::SetDtr(false)),
+            CONTROL_RTS_ON => return Some(PortRequest::SetRts(true)),
+            CONTROL_RTS_OFF => return Some(PortRequest::SetRts(false)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(PortRequest::Configure(setting))
+}
+
+/// Settings of a served port, as reported to the clients asking for them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PortState {
+    pub baud_rate: u32,
+    pub data_size: u8,
+    pub parity: u8,
+    pub stop_size: u8,
+    /// One of the outbound flow control values of SET-CONTROL
+    pub flow_control: u8,
+    pub break_on: bool,
+    pub dtr: bool,
+    pub rts: bool,
+}
+
+impl PortState {
+    /// State of a port just opened at the given baud rate, 8N1 without flow control.
+    pub fn new(baud_rate: u32) -> PortState {
+        PortState {
+            baud_rate,
+            data_size: 8,
+            parity: 1,
+            stop_size: 1,
+            flow_control: CONTROL_NO_FLOW_CONTROL,
+            break_on: false,
+            dtr: true,
+            rts: true,
+        }
+    }
+
+    /// Records a change applied to the port.
+    pub fn apply(&mut self, command: &ComPortCommand) {
+        match command {
+            ComPortCommand::SetBaudRate(0) => {}
+            ComPortCommand::SetBaudRate(baud_rate) => self.baud_rate = *baud_rate,
+            ComPortCommand::SetDataSize(value @ 5..=8) => self.data_size = *value,
+            ComPortCommand::SetParity(value @ 1..=3) => self.parity = *value,
+            ComPortCommand::SetStopSize(value @ (1 | 2)) => self.stop_size = *value,
+            ComPortCommand::SetControl(value) => match *value {
+                CONTROL_NO_FLOW_CONTROL | CONTROL_XON_XOFF | CONTROL_HARDWARE_FLOW_CONTROL => {
+                    self.flow_control = *value
+                }
+                CONTROL_BREAK_ON => self.break_on = true,
+                CONTROL_BREAK_OFF => self.break_on = false,
+                CONTROL_DTR_ON => self.dtr = true,
+                CONTROL_DTR_OFF => self.dtr = false,
+                CONTROL_RTS_ON => self.rts = true,
+                CONTROL_RTS_OFF => self.rts = false,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Answer of the server to a SET command: the value in effect, which is the
+    /// current one for queries and for values that were not applied.
+    pub fn get_reply(&self, command: &ComPortCommand) -> Option<ComPortCommand> {
+        let reply = match command {
+            ComPortCommand::SetBaudRate(_) => ComPortCommand::SetBaudRate(self.baud_rate),
+            ComPortCommand::SetDataSize(_) => ComPortCommand::SetDataSize(self.data_size),
+            ComPortCommand::SetParity(_) => ComPortCommand::SetParity(self.parity),
+            ComPortCommand::SetStopSize(_) => ComPortCommand::SetStopSize(self.stop_size),
+            ComPortCommand::SetControl(value) => ComPortCommand::SetControl(match *value {
+                CONTROL_REQUEST_FLOW_CONTROL..=CONTROL_HARDWARE_FLOW_CONTROL => self.flow_control,
+                CONTROL_REQUEST_BREAK..=CONTROL_BREAK_OFF => {
+                    if self.break_on {
+                        CONTROL_BREAK_ON
+                    } else {
+                        CONTROL_BREAK_OFF
+                    }
+                }
+                CONTROL_REQUEST_DTR..=CONTROL_DTR_OFF => {
+                    if self.dtr {
+                        CONTROL_DTR_ON
+                    } else {
+                        CONTROL_DTR_OFF
+                    }
+                }
+                CONTROL_REQUEST_RTS..=CONTROL_RTS_OFF => {
+                    if self.rts {
+                        CONTROL_RTS_ON
+                    } else {
+                        CONTROL_RTS_OFF
+                    }
+                }
+                // inbound flow control follows the outbound one on local ports
+                CONTROL_REQUEST_INBOUND_FLOW_CONTROL..=CONTROL_DSR_FLOW_CONTROL => {
+                    match self.flow_control {
+                        CONTROL_XON_XOFF => CONTROL_INBOUND_XON_XOFF,
+                        CONTROL_HARDWARE_FLOW_CONTROL => CONTROL_INBOUND_HARDWARE_FLOW_CONTROL,
+                        _ => CONTROL_INBOUND_NO_FLOW_CONTROL,
+                    }
+                }
+                _ => return None,
+            }),
+            _ => return None,
+        };
+
+        Some(reply)
+    }
+}
+
+pub fn get_modem_lines(modem_state: u8) -> ModemLines {
+    ModemLines {
+        cts: modem_state & MODEMSTATE_CTS != 0,
+        dsr: modem_state & MODEMSTATE_DSR != 0,
+        dcd: modem_state & MODEMSTATE_DCD != 0,
+        ri: modem_state & MODEMSTATE_RI != 0,
+    }
+}
+
+pub fn get_modem_state(lines: &ModemLines) -> u8 {
+    let mut modem_state = 0;
+    for (set, bit) in [
+        (lines.cts, MODEMSTATE_CTS),
+        (lines.dsr, MODEMSTATE_DSR),
+        (lines.dcd, MODEMSTATE_DCD),
+        (lines.ri, MODEMSTATE_RI),
+    ] {
+        if set {
+            modem_state |= bit;
+        }
+    }
+
+    modem_state
+}
+
+/// Options both sides want enabled, as (request received, option) of the client.
+const CLIENT_OPTIONS: &[(u8, u8)] = &[
+    (DO, COM_PORT_OPTION),
+    (DO, BINARY),
+    (WILL, BINARY),
+    (WILL, SUPPRESS_GO_AHEAD),
+];
+const SERVER_OPTIONS: &[(u8, u8)] = &[
+    (WILL, COM_PORT_OPTION),
+    (WILL, BINARY),
+    (DO, BINARY),
+    (DO, SUPPRESS_GO_AHEAD),
+];
+
+/// Data and commands received by a session, in the order of arrival.
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Data(Vec<u8>),
+    Command(ComPortCommand),
+}
+
+/// One side of the protocol: negotiates the options, and splits the received
+/// stream into data and the COM port commands sent by the other side.
+pub struct Session {
+    decoder: TelnetDecoder,
+    is_server: bool,
+    answered: std::collections::HashSet<(u8, u8)>,
+}
+
+impl Session {
+    pub fn client() -> Session {
+        Session {
+            decoder: TelnetDecoder::new(),
+            is_server: false,
+            answered: std::collections::HashSet::new(),
+        }
+    }
+
+    pub fn server() -> Session {
+        Session {
+            is_server: true,
+            ..Session::client()
+        }
+    }
+
+    fn get_options(&self) -> &'static [(u8, u8)] {
+        if self.is_server {
+            SERVER_OPTIONS
+        } else {
+            CLIENT_OPTIONS
+        }
+    }
+
+    /// Options requested right after connecting. The other side agreeing to them
+    /// is not answered again.
+    pub fn get_greeting(&mut self) -> Vec<u8> {
+        let mut greeting = Vec::new();
+        for &(verb, option) in self.get_options() {
+            let request = if verb == DO { WILL } else { DO };
+            greeting.extend(encode_negotiation(request, option));
+            self.answered.insert((verb, option));
+        }
+        greeting
+    }
+
+    /// Encodes everything but timed breaks, which take two commands with a delay in
+    /// between, and CAN frames, which have no equivalent.
+    pub fn encode_request(&self, request: &PortRequest) -> Vec<u8> {
+        match request {
+            PortRequest::Write(byte) => escape_data(&[*byte]),
+            PortRequest::SetDtr(level) => {
+                let value = if *level {
+                    CONTROL_DTR_ON
+                } else {
+                    CONTROL_DTR_OFF
+                };
+                ComPortCommand::SetControl(value).encode(self.is_server)
+            }
+            PortRequest::SetRts(level) => {
+                let value = if *level {
+                    CONTROL_RTS_ON
+                } else {
+                    CONTROL_RTS_OFF
+                };
+                ComPortCommand::SetControl(value).encode(self.is_server)
+            }
+            PortRequest::SetBreak(on) => {
+                let value = if *on {
+                    CONTROL_BREAK_ON
+                } else {
+                    CONTROL_BREAK_OFF
+                };
+                ComPortCommand::SetControl(value).encode(self.is_server)
+            }
+            PortRequest::Break(_) | PortRequest::SendFrame(_) => Vec::new(),
+            PortRequest::Configure(setting) => get_setting_command(setting).encode(self.is_server),
+        }
+    }
+
+    /// Decodes the received stream into data and commands sent by the other side.
+    /// Returns the bytes to send back in response to the option negotiation.
+    pub fn receive(&mut self, input: &[u8], received: &mut Vec<Received>) -> Vec<u8> {
+        let mut items = Vec::new();
+        self.decoder.decode(input, &mut items);
+
+        let mut replies = Vec::new();
+        for item in items {
+            match item {
+                TelnetItem::Data(data) => received.push(Received::Data(data)),
+                TelnetItem::Negotiation(verb, option) => {
+                    let accepted = self.get_options().contains(&(verb, option));
+                    let reply = match (verb, accepted) {
+                        (DO, true) => WILL,
+                        (DO, false) | (DONT, _) => WONT,
+                        (WILL, true) => DO,
+                        _ => DONT,
+                    };
+                    // answer every option once, to avoid negotiation loops
+                    if self.answered.insert((verb, option)) {
+                        replies.extend(encode_negotiation(reply, option));
+                    }
+                }
+                TelnetItem::Subnegotiation(subnegotiation) => {
+                    match ComPortCommand::parse(&subnegotiation) {
+                        Some((command, from_server)) if from_server != self.is_server => {
+                            received.push(Received::Command(command));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        replies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_data_and_commands() {
+        let mut decoder = TelnetDecoder::new();
+        let mut items = Vec::new();
+        let input = [
+            b'a',
+            IAC,
+            IAC,
+            IAC,
+            DO,
+            COM_PORT_OPTION,
+            b'b',
+            IAC,
+            SB,
+            COM_PORT_OPTION,
+            107,
+            0x30,
+            IAC,
+            SE,
+        ];
+        decoder.decode(&input, &mut items);
+        assert_eq!(
+            items,
+            vec![
+                TelnetItem::Data(vec![b'a', IAC]),
+                TelnetItem::Negotiation(DO, COM_PORT_OPTION),
+                TelnetItem::Data(vec![b'b']),
+                TelnetItem::Subnegotiation(vec![COM_PORT_OPTION, 107, 0x30]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_split_across_reads() {
+        let mut decoder = TelnetDecoder::new();
+        let mut items = Vec::new();
+        decoder.decode(&[b'x', IAC], &mut items);
+        decoder.decode(&[WILL], &mut items);
+        decoder.decode(&[BINARY, b'y'], &mut items);
+        assert_eq!(
+            items,
+            vec![
+                TelnetItem::Data(vec![b'x']),
+                TelnetItem::Negotiation(WILL, BINARY),
+                TelnetItem::Data(vec![b'y']),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_data() {
+        assert_eq!(escape_data(&[1, IAC, 2]), vec![1, IAC, IAC, 2]);
+    }
+
+    #[test]
+    fn test_com_port_command_round_trip() {
+        let commands = [
+            ComPortCommand::SetBaudRate(921_600),
+            ComPortCommand::SetParity(3),
+            ComPortCommand::SetControl(CONTROL_DTR_ON),
+            ComPortCommand::NotifyModemState(0xff),
+        ];
+        for command in commands {
+            for from_server in [false, true] {
+                let encoded = command.encode(from_server);
+                let mut decoder = TelnetDecoder::new();
+                let mut items = Vec::new();
+                decoder.decode(&encoded, &mut items);
+                assert_eq!(items.len(), 1);
+                let subnegotiation = match &items[0] {
+                    TelnetItem::Subnegotiation(s) => s.clone(),
+                    item => panic!("Unexpected item: {:?}", item),
+                };
+                assert_eq!(
+                    ComPortCommand::parse(&subnegotiation).unwrap(),
+                    (command.clone(), from_server)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_port_request_round_trip() {
+        for text in [
+            "baud 57600",
+            "parity odd",
+            "flow hardware",
+            "databits 7",
+            "stopbits 2",
+        ] {
+            let (name, value) = text.split_once(' ').unwrap();
+            let setting = PortSetting::parse(name, value).unwrap();
+            assert_eq!(
+                get_port_request(&get_setting_command(&setting)),
+                Some(PortRequest::Configure(setting))
+            );
+        }
+        assert_eq!(
+            get_port_request(&ComPortCommand::SetControl(CONTROL_RTS_ON)),
+            Some(PortRequest::SetRts(true))
+        );
+        assert!(get_port_request(&ComPortCommand::SetBaudRate(0)).is_none());
+        assert!(get_port_request(&ComPortCommand::SetControl(CONTROL_BREAK_ON)).is_none());
+    }
+
+    #[test]
+    fn test_port_state_replies() {
+        let mut state = PortState::new(115_200);
+        for (command, reply) in [
+            (
+                ComPortCommand::SetBaudRate(0),
+                ComPortCommand::SetBaudRate(115_200),
+            ),
+            (ComPortCommand::SetParity(0), ComPortCommand::SetParity(1)),
+            (
+                ComPortCommand::SetControl(CONTROL_REQUEST_DTR),
+                ComPortCommand::SetControl(CONTROL_DTR_ON),
+            ),
+            (
+                ComPortCommand::SetControl(CONTROL_REQUEST_INBOUND_FLOW_CONTROL),
+                ComPortCommand::SetControl(CONTROL_INBOUND_NO_FLOW_CONTROL),
+            ),
+        ] {
+            assert_eq!(state.get_reply(&command), Some(reply));
+        }
+
+        for command in [
+            ComPortCommand::SetBaudRate(9600),
+            ComPortCommand::SetStopSize(2),
+            ComPortCommand::SetControl(CONTROL_HARDWARE_FLOW_CONTROL),
+            ComPortCommand::SetControl(CONTROL_BREAK_ON),
+            ComPortCommand::SetControl(CONTROL_RTS_OFF),
+        ] {
+            state.apply(&command);
+            assert_eq!(state.get_reply(&command), Some(command));
+        }
+        assert_eq!(
+            state.get_reply(&ComPortCommand::SetControl(CONTROL_REQUEST_FLOW_CONTROL)),
+            Some(ComPortCommand::SetControl(CONTROL_HARDWARE_FLOW_CONTROL))
+        );
+
+        // values that are not supported are answered with the current ones
+        let mark_parity = ComPortCommand::SetParity(4);
+        state.apply(&mark_parity);
+        assert_eq!(
+            state.get_reply(&mark_parity),
+            Some(ComPortCommand::SetParity(1))
+        );
+        assert!(state
+            .get_reply(&ComPortCommand::NotifyModemState(0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            ComPortCommand::SetBaudRate(9600).describe().unwrap(),
+            "Port settings changed: baud 9600"
+        );
+        assert_eq!(
+            ComPortCommand::SetControl(CONTROL_DTR_OFF)
+                .describe()
+                .unwrap(),
+            "DTR cleared"
+        );
+        assert!(ComPortCommand::SetBaudRate(0).describe().is_none());
+        assert!(ComPortCommand::SetControl(CONTROL_BREAK_ON)
+            .describe()
+            .is_none());
+    }
+
+    #[test]
+    fn test_modem_state() {
+        let lines = get_modem_lines(MODEMSTATE_CTS | MODEMSTATE_DCD | 0x01);
+        assert!(lines.cts && lines.dcd && !lines.dsr && !lines.ri);
+        assert_eq!(get_modem_state(&lines), MODEMSTATE_CTS | MODEMSTATE_DCD);
+    }
+
+    #[test]
+    fn test_client_answers_negotiation_once() {
+        let input = [IAC, DO, COM_PORT_OPTION, IAC, DO, 24, IAC, DO, 24];
+        let mut received = Vec::new();
+        let mut session = Session::client();
+        let replies = session.receive(&input, &mut received);
+        assert_eq!(replies, vec![IAC, WILL, COM_PORT_OPTION, IAC, WONT, 24]);
+
+        // options requested in the greeting are not acknowledged again
+        let mut session = Session::client();
+        session.get_greeting();
+        let replies = session.receive(&input, &mut received);
+        assert_eq!(replies, vec![IAC, WONT, 24]);
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn test_session_reports_commands_of_other_side() {
+        let mut input = ComPortCommand::SetBaudRate(9600).encode(true);
+        input.extend(b"ok");
+        input.extend(ComPortCommand::SetBaudRate(1200).encode(false));
+
+        let mut received = Vec::new();
+        Session::client().receive(&input, &mut received);
+        assert_eq!(
+            received,
+            vec![
+                Received::Command(ComPortCommand::SetBaudRate(9600)),
+                Received::Data(b"ok".to_vec()),
+            ]
+        );
+
+        let mut received = Vec::new();
+        Session::server().receive(&input, &mut received);
+        assert_eq!(
+            received,
+            vec![
+                Received::Data(b"ok".to_vec()),
+                Received::Command(ComPortCommand::SetBaudRate(1200)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_encodes_requests() {
+        let session = Session::client();
+        assert_eq!(
+            session.encode_request(&PortRequest::Write(IAC)),
+            vec![IAC, IAC]
+        );
+        assert_eq!(
+            session.encode_request(&PortRequest::SetRts(false)),
+            vec![
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_CONTROL,
+                CONTROL_RTS_OFF,
+                IAC,
+                SE
+            ]
+        );
+        assert_eq!(
+            session.encode_request(&PortRequest::Configure(PortSetting::BaudRate(115_200))),
+            ComPortCommand::SetBaudRate(115_200).encode(false)
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use super::command::{Command, ControlLine, LineAction};
+use super::data::PortSetting;
+use super::log_monitor::{Event, MonitorMessage, PortRequest, WriteProxy};
+use super::sequence;
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+pub const COMMAND: &str = "run";
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Exit code of the session when the script fails.
+pub const FAILURE_EXIT_CODE: i32 = 1;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScriptConfig {
+    target: Option<String>,
+    variables: BTreeMap<String, String>,
+    steps: Vec<StepConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StepConfig {
+    send: Option<String>,
+    expect: Option<String>,
+    /// Milliseconds to wait for the expected line
+    timeout: Option<u64>,
+    /// Milliseconds
+    sleep: Option<u64>,
+    set_baud: Option<u32>,
+    /// Milliseconds the DTR line is set for
+    toggle_dtr: Option<u64>,
+    command: Option<String>,
+    target: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepAction {
+    Send(String),
+    /// Regular expression searched for in the received lines
+    Expect(String, Duration),
+    Sleep(Duration),
+    Commands(Vec<Command>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// Writer the step applies to, the target of the script if None
+    pub target: Option<String>,
+    pub action: StepAction,
+}
+
+impl Step {
+    fn new(config: StepConfig, index: usize) -> Result<Step, String> {
+        let error = |description: &str| format!("Step {}: {}", index + 1, description);
+
+        let mut actions = Vec::new();
+        if let Some(text) = config.send {
+            actions.push(StepAction::Send(text));
+        }
+        if let Some(pattern) = config.expect {
+            regex::Regex::new(&pattern).map_err(|e| error(&e.to_string()))?;
+            let timeout = config
+                .timeout
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_EXPECT_TIMEOUT);
+            actions.push(StepAction::Expect(pattern, timeout));
+        } else if config.timeout.is_some() {
+            return Err(error("timeout is only used with expect"));
+        }
+        if let Some(ms) = config.sleep {
+            actions.push(StepAction::Sleep(Duration::from_millis(ms)));
+        }
+        if let Some(baud_rate) = config.set_baud {
+            let setting = PortSetting::BaudRate(baud_rate);
+            actions.push(StepAction::Commands(vec![Command::Configure(setting)]));
+        }
+        if let Some(ms) = config.toggle_dtr {
+            let action = LineAction::Pulse(Duration::from_millis(ms));
+            let command = Command::ControlLine(ControlLine::Dtr, action);
+            actions.push(StepAction::Commands(vec![command]));
+        }
+        if let Some(text) = config.command {
+            let command = Command::parse(&text).map_err(|e| error(&e))?;
+            actions.push(StepAction::Commands(vec![command]));
+        }
+
+        match actions.len() {
+            1 => Ok(Step {
+                target: config.target,
+                action: actions.pop().unwrap(),
+            }),
+            0 => Err(error("no action")),
+            _ => Err(error("more than one action")),
+        }
+    }
+}
+
+/// Steps interacting with the ports, like sending a login and waiting for the
+/// prompt.
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    /// Writer the steps apply to, the input target of the session if None
+    pub target: Option<String>,
+    pub variables: HashMap<String, String>,
+    pub steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn parse(content: &str) -> Result<Script, String> {
+        let config: ScriptConfig =
+            toml::from_str(content).map_err(|e| format!("Invalid script: {}", e))?;
+
+        let steps = config
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, step)| Step::new(step, i))
+            .collect::<Result<Vec<_>, String>>()?;
+        if steps.is_empty() {
+            return Err("Script without steps".to_string());
+        }
+
+        Ok(Script {
+            target: config.target,
+            variables: config.variables.into_iter().collect(),
+            steps,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Script, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read script {}: {}", path, e))?;
+        Script::parse(&content)
+    }
+
+    /// Replaces the commands of the steps, e.g. sequences with their commands.
+    pub fn expand_commands(
+        &mut self,
+        expand: impl Fn(Command) -> Result<Vec<Command>, String>,
+    ) -> Result<(), String> {
+        for step in &mut self.steps {
+            if let StepAction::Commands(commands) = &mut step.action {
+                let mut expanded = Vec::new();
+                for command in commands.drain(..) {
+                    expanded.extend(expand(command)?);
+                }
+                *commands = expanded;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the writers used by the steps.
+    pub fn get_targets(&self) -> Vec<Option<String>> {
+        let mut targets = Vec::new();
+        for step in &self.steps {
+            let target = step.target.clone().or(self.target.clone());
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        targets
+    }
+}
+
+/// Replaces ${NAME} with the value of the variable.
+fn substitute(
+    text: &str,
+    variables: &HashMap<String, String>,
+    escape: fn(&str) -> String,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or(format!("Unterminated variable in {}", text))?;
+        let name = &rest[start + 2..start + end];
+        let value = variables
+            .get(name)
+            .ok_or(format!("Unknown variable {}", name))?;
+        result += &rest[..start];
+        result += &escape(value);
+        rest = &rest[start + end + 1..];
+    }
+
+    result += rest;
+    Ok(result)
+}
+
+/// Runs a script against the ports of the session, reading the messages the
+/// session receives.
+pub struct ScriptRunner {
+    name: String,
+    script: Script,
+    /// Write proxies of every target of the steps
+    targets: HashMap<Option<String>, Vec<(String, WriteProxy)>>,
+    message_receiver: UnboundedReceiver<MonitorMessage>,
+    event_sender: UnboundedSender<MonitorMessage>,
+    /// Source of the last line received, partial lines don't carry theirs
+    last_source: Option<String>,
+}
+
+impl ScriptRunner {
+    pub fn new(
+        name: String,
+        script: Script,
+        targets: HashMap<Option<String>, Vec<(String, WriteProxy)>>,
+        message_receiver: UnboundedReceiver<MonitorMessage>,
+        event_sender: UnboundedSender<MonitorMessage>,
+    ) -> ScriptRunner {
+        ScriptRunner {
+            name,
+            script,
+            targets,
+            message_receiver,
+            event_sender,
+            last_source: None,
+        }
+    }
+
+    fn send_event(&self, description: String) {
+        let event = Event {
+            source_name: self.name.clone(),
+            description,
+            timestamp: chrono::Local::now(),
+        };
+        // the session may have ended in the meantime
+        let _ = self.event_sender.send(MonitorMessage::Event(event));
+    }
+
+    fn get_write_proxies(&self, step: &Step) -> &[(String, WriteProxy)] {
+        let target = step.target.clone().or(self.script.target.clone());
+        match self.targets.get(&target) {
+            Some(write_proxies) => write_proxies,
+            None => &[],
+        }
+    }
+
+    /// Waits for a line of the target ports matching the pattern, partial lines like
+    /// prompts are matched as well when they follow a line of a target port. The named
+    /// groups of the pattern set variables.
+    async fn expect(
+        &mut self,
+        step: &Step,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let pattern = substitute(pattern, &self.script.variables, regex::escape)?;
+        let regex = regex::Regex::new(&pattern).map_err(|e| e.to_string())?;
+        let port_names: Vec<String> = self
+            .get_write_proxies(step)
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let msg = tokio::time::timeout_at(deadline, self.message_receiver.recv())
+                .await
+                .map_err(|_| {
+                    format!(
+                        "`{}` not received within {} ms",
+                        pattern,
+                        timeout.as_millis()
+                    )
+                })?
+                .ok_or("Session ended")?;
+            let text = match &msg {
+                MonitorMessage::Log(log) => {
+                    self.last_source = Some(log.source_name.clone());
+                    if !port_names.contains(&log.source_name) {
+                        continue;
+                    }
+                    &log.message
+                }
+                MonitorMessage::UnsolictedMessage(text)
+                    if self
+                        .last_source
+                        .as_ref()
+                        .is_some_and(|source| port_names.contains(source)) =>
+                {
+                    text
+                }
+                _ => continue,
+            };
+
+            if let Some(captures) = regex.captures(text) {
+                for name in regex.capture_names().flatten() {
+                    if let Some(value) = captures.name(name) {
+                        self.script
+                            .variables
+                            .insert(name.to_string(), value.as_str().to_string());
+                    }
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    async fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match &step.action {
+            StepAction::Send(text) => {
+                let text = substitute(text, &self.script.variables, |v| v.to_string())?;
+                for (name, write_proxy) in self.get_write_proxies(step) {
+                    for byte in text.bytes() {
+                        write_proxy
+                            .request(PortRequest::Write(byte))
+                            .map_err(|e| format!("Failed to write to {}: {}", name, e))?;
+                    }
+                }
+            }
+            StepAction::Expect(pattern, timeout) => {
+                self.expect(step, pattern, *timeout).await?;
+            }
+            StepAction::Sleep(duration) => {
+                tokio::time::sleep(*duration).await;
+            }
+            StepAction::Commands(commands) => {
+                let write_proxies = self.get_write_proxies(step).to_vec();
+                let failures = sequence::run(commands.clone(), write_proxies).await;
+                if let Some(failure) = failures.first() {
+                    return Err(format!(
+                        "Failed to deliver command to {}: {}",
+                        failure.port_name, failure.error
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the steps until one of them fails, returns the exit code of the session.
+    pub async fn run(mut self) -> i32 {
+        if let Some(target) = self
+            .script
+            .get_targets()
+            .into_iter()
+            .find(|t| self.targets.get(t).is_none_or(|proxies| proxies.is_empty()))
+        {
+            let name = target.unwrap_or("the input target".to_string());
+            self.send_event(format!("No port to run the script on: {}", name));
+            return FAILURE_EXIT_CODE;
+        }
+
+        let steps = self.script.steps.clone();
+        for (i, step) in steps.iter().enumerate() {
+            if let Err(e) = self.run_step(step).await {
+                self.send_event(format!("Step {} failed: {}", i + 1, e));
+                return FAILURE_EXIT_CODE;
+            }
+        }
+
+        self.send_event("Script finished".to_string());
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::Log;
+
+    const LOGIN_SCRIPT: &str = r#"
+        target = "board"
+
+        [variables]
+        user = "root"
+
+        [[steps]]
+        expect = "login: "
+        timeout = 2000
+
+        [[steps]]
+        send = "${user}\r"
+
+        [[steps]]
+        expect = 'IP: (?P<ip>[\d.]+)'
+
+        [[steps]]
+        send = "ping ${ip}\r"
+
+        [[steps]]
+        toggle_dtr = 0
+    "#;
+
+    fn get_log(source_name: &str, message: &str) -> MonitorMessage {
+        MonitorMessage::Log(Log {
+            source_name: source_name.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Local::now(),
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(LOGIN_SCRIPT).unwrap();
+        assert_eq!(script.target.as_deref(), Some("board"));
+        assert_eq!(script.variables["user"], "root");
+        assert_eq!(
+            script.steps[0].action,
+            StepAction::Expect("login: ".to_string(), Duration::from_secs(2))
+        );
+        assert_eq!(
+            script.steps[2].action,
+            StepAction::Expect(r"IP: (?P<ip>[\d.]+)".to_string(), DEFAULT_EXPECT_TIMEOUT)
+        );
+        assert_eq!(script.get_targets(), [Some("board".to_string())]);
+
+        assert!(Script::parse("").is_err());
+        assert!(Script::parse("[[steps]]\nsend = \"a\"\nsleep = 10").is_err());
+        assert!(Script::parse("[[steps]]\ntimeout = 10").is_err());
+        assert!(Script::parse("[[steps]]\nexpect = \"(\"").is_err());
+        assert!(Script::parse("[[steps]]\ncommand = \"reboot\"").is_err());
+        assert!(Script::parse("[[steps]]\nsned = \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let variables = HashMap::from([("ip".to_string(), "10.0.0.1".to_string())]);
+        let text = substitute("ping ${ip}", &variables, |v| v.to_string());
+        assert_eq!(text.unwrap(), "ping 10.0.0.1");
+        let pattern = substitute("from ${ip}:", &variables, regex::escape);
+        assert_eq!(pattern.unwrap(), r"from 10\.0\.0\.1:");
+        assert!(substitute("${mask}", &variables, |v| v.to_string()).is_err());
+        assert!(substitute("${ip", &variables, |v| v.to_string()).is_err());
+    }
+
+    fn create_runner(
+        content: &str,
+    ) -> (
+        ScriptRunner,
+        UnboundedSender<MonitorMessage>,
+        UnboundedReceiver<PortRequest>,
+        UnboundedReceiver<MonitorMessage>,
+    ) {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (message_sender, message_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let script = Script::parse(content).unwrap();
+        let targets = HashMap::from([(
+            Some("board".to_string()),
+            vec![("/dev/ttyUSB0".to_string(), WriteProxy::new(write_sender))],
+        )]);
+        let runner = ScriptRunner::new(
+            "script.toml".to_string(),
+            script,
+            targets,
+            message_receiver,
+            event_sender,
+        );
+        (runner, message_sender, write_receiver, event_receiver)
+    }
+
+    fn get_written(write_receiver: &mut UnboundedReceiver<PortRequest>) -> Vec<PortRequest> {
+        let mut requests = Vec::new();
+        while let Ok(request) = write_receiver.try_recv() {
+            requests.push(request);
+        }
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let (runner, message_sender, mut write_receiver, mut event_receiver) =
+            create_runner(LOGIN_SCRIPT);
+
+        message_sender
+            .send(get_log("/dev/ttyUSB1", "IP: 1.1.1.1"))
+            .unwrap();
+        message_sender
+            .send(get_log("/dev/ttyUSB0", "Welcome"))
+            .unwrap();
+        message_sender
+            .send(MonitorMessage::UnsolictedMessage("login: ".to_string()))
+            .unwrap();
+        message_sender
+            .send(get_log("/dev/ttyUSB1", "IP: 1.1.1.1"))
+            .unwrap();
+        message_sender
+            .send(get_log("/dev/ttyUSB0", "IP: 10.0.0.7"))
+            .unwrap();
+        assert_eq!(runner.run().await, 0);
+
+        let text: Vec<u8> = get_written(&mut write_receiver)
+            .into_iter()
+            .filter_map(|request| match request {
+                PortRequest::Write(byte) => Some(byte),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, b"root\rping 10.0.0.7\r");
+        match event_receiver.try_recv().unwrap() {
+            MonitorMessage::Event(event) => assert_eq!(event.description, "Script finished"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_expect_timeout() {
+        let content = "target = \"board\"\n[[steps]]\nexpect = \"OK\"\ntimeout = 50\n";
+        let (runner, message_sender, _write_receiver, mut event_receiver) = create_runner(content);
+
+        message_sender
+            .send(get_log("/dev/ttyUSB0", "ERROR"))
+            .unwrap();
+        assert_eq!(runner.run().await, FAILURE_EXIT_CODE);
+        match event_receiver.try_recv().unwrap() {
+            MonitorMessage::Event(event) => assert_eq!(
+                event.description,
+                "Step 1 failed: `OK` not received within 50 ms"
+            ),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        // the partial line continues the line of the other port
+        let (runner, message_sender, _write_receiver, mut event_receiver) = create_runner(content);
+        message_sender
+            .send(get_log("/dev/ttyUSB0", "Welcome"))
+            .unwrap();
+        message_sender
+            .send(get_log("/dev/ttyUSB1", "Welcome"))
+            .unwrap();
+        message_sender
+            .send(MonitorMessage::UnsolictedMessage("OK".to_string()))
+            .unwrap();
+        assert_eq!(runner.run().await, FAILURE_EXIT_CODE);
+        match event_receiver.try_recv().unwrap() {
+            MonitorMessage::Event(event) => assert_eq!(
+                event.description,
+                "Step 1 failed: `OK` not received within 50 ms"
+            ),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        let (runner, _, _, mut event_receiver) = create_runner("[[steps]]\nsleep = 1\n");
+        assert_eq!(runner.run().await, FAILURE_EXIT_CODE);
+        match event_receiver.try_recv().unwrap() {
+            MonitorMessage::Event(event) => assert_eq!(
+                event.description,
+                "No port to run the script on: the input target"
+            ),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::command::{Command, ControlLine, LineAction};
+use crate::log_monitor::{PortRequest, WriteProxy};
+use crate::writer::DeliveryFailure;
+
+use std::collections::BTreeMap;
+
+/// Control line sequences available without any configuration.
+const BUILTIN_SEQUENCES: &[(&str, &[&str])] = &[
+    // EN is driven by RTS and GPIO0 by DTR (inverted), as expected by esptool
+    (
+        "esp32-bootloader",
+        &[
+            "dtr off",
+            "rts on",
+            "sleep 100",
+            "dtr on",
+            "rts off",
+            "sleep 50",
+            "dtr off",
+        ],
+    ),
+    (
+        "esp32-reset",
+        &["dtr off", "rts on", "sleep 100", "rts off"],
+    ),
+    // the reset pin is pulsed through a capacitor on the DTR line
+    (
+        "arduino-reset",
+        &[
+            "dtr off",
+            "rts off",
+            "sleep 250",
+            "dtr on",
+            "rts on",
+            "sleep 50",
+        ],
+    ),
+];
+
+/// Named lists of commands, run on request against the current input target.
+pub struct Sequences {
+    sequences: indexmap::IndexMap<String, Vec<Command>>,
+}
+
+fn parse_sequence(name: &str, steps: &[&str]) -> Result<Vec<Command>, String> {
+    let mut commands = Vec::new();
+    for step in steps {
+        let command = Command::parse(step)
+            .map_err(|e| format!("Invalid step in sequence {}: {}", name, e))?;
+        if let Command::Sequence(_) = command {
+            return Err(format!("Sequence {} cannot run other sequences", name));
+        }
+        commands.push(command);
+    }
+
+    Ok(commands)
+}
+
+impl Sequences {
+    /// Builds the built-in sequences and adds the configured ones,
+    /// which take precedence over the built-in sequences of the same name.
+    pub fn new(configured: &BTreeMap<String, Vec<String>>) -> Result<Sequences, String> {
+        let mut sequences = indexmap::IndexMap::new();
+
+        for (name, steps) in BUILTIN_SEQUENCES {
+            sequences.insert(name.to_string(), parse_sequence(name, steps).unwrap());
+        }
+
+        for (name, steps) in configured {
+            let steps: Vec<&str> = steps.iter().map(|s| s.as_str()).collect();
+            sequences.insert(name.clone(), parse_sequence(name, &steps)?);
+        }
+
+        Ok(Sequences { sequences })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<Command>> {
+        self.sequences.get(name)
+    }
+
+    pub fn get_names(&self) -> Vec<String> {
+        self.sequences.keys().cloned().collect()
+    }
+}
+
+fn get_line_request(line: ControlLine, level: bool) -> PortRequest {
+    match line {
+        ControlLine::Dtr => PortRequest::SetDtr(level),
+        ControlLine::Rts => PortRequest::SetRts(level),
+    }
+}
+
+fn send_request(
+    request: PortRequest,
+    write_proxies: &[(String, WriteProxy)],
+    failures: &mut Vec<DeliveryFailure>,
+) {
+    for (name, write_proxy) in write_proxies {
+        if let Err(e) = write_proxy.request(request.clone()) {
+            failures.push(DeliveryFailure {
+                port_name: name.clone(),
+                error: e,
+            });
+        }
+    }
+}
+
+/// Runs the commands one by one against all given ports.
+pub async fn run(
+    commands: Vec<Command>,
+    write_proxies: Vec<(String, WriteProxy)>,
+) -> Vec<DeliveryFailure> {
+    let mut failures = Vec::new();
+
+    for command in commands {
+        match command {
+            Command::ControlLine(line, LineAction::Set) => {
+                send_request(get_line_request(line, true), &write_proxies, &mut failures);
+            }
+            Command::ControlLine(line, LineAction::Clear) => {
+                send_request(get_line_request(line, false), &write_proxies, &mut failures);
+            }
+            Command::ControlLine(line, LineAction::Pulse(duration)) => {
+                send_request(get_line_request(line, true), &write_proxies, &mut failures);
+                tokio::time::sleep(duration).await;
+                send_request(get_line_request(line, false), &write_proxies, &mut failures);
+            }
+            Command::Sleep(duration) => {
+                tokio::time::sleep(duration).await;
+            }
+            Command::Break(duration) => {
+                send_request(PortRequest::Break(duration), &write_proxies, &mut failures);
+            }
+            Command::Configure(setting) => {
+                send_request(
+                    PortRequest::Configure(setting),
+                    &write_proxies,
+                    &mut failures,
+                );
+            }
+            Command::SendFrame(frame) => {
+                send_request(PortRequest::SendFrame(frame), &write_proxies, &mut failures);
+            }
+            // nested sequences are rejected while parsing
+            Command::Sequence(_) => {}
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_sequences() {
+        let sequences = Sequences::new(&BTreeMap::new()).unwrap();
+        assert!(sequences.get("esp32-bootloader").is_some());
+        assert!(sequences.get("arduino-reset").is_some());
+        assert!(sequences.get("nope").is_none());
+    }
+
+    #[test]
+    fn test_configured_sequences() {
+        let mut configured = BTreeMap::new();
+        configured.insert(
+            "arduino-reset".to_string(),
+            vec!["dtr pulse 10".to_string()],
+        );
+        let sequences = Sequences::new(&configured).unwrap();
+        assert_eq!(sequences.get("arduino-reset").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_configured_sequences_invalid() {
+        let mut configured = BTreeMap::new();
+        configured.insert("a".to_string(), vec!["sequence b".to_string()]);
+        assert!(Sequences::new(&configured).is_err());
+
+        let mut configured = BTreeMap::new();
+        configured.insert("a".to_string(), vec!["dtr maybe".to_string()]);
+        assert!(Sequences::new(&configured).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_sends_requests_in_order() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        let write_proxies = vec![("a".to_string(), WriteProxy::new(sender))];
+        let commands = vec![
+            Command::parse("dtr on").unwrap(),
+            Command::parse("rts pulse 1").unwrap(),
+            Command::parse("sleep 1").unwrap(),
+            Command::parse("break 5").unwrap(),
+            Command::parse("dtr off").unwrap(),
+        ];
+
+        let failures = run(commands, write_proxies).await;
+        assert!(failures.is_empty());
+        assert_eq!(receiver.try_recv().unwrap(), PortRequest::SetDtr(true));
+        assert_eq!(receiver.try_recv().unwrap(), PortRequest::SetRts(true));
+        assert_eq!(receiver.try_recv().unwrap(), PortRequest::SetRts(false));
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            PortRequest::Break(std::time::Duration::from_millis(5))
+        );
+        assert_eq!(receiver.try_recv().unwrap(), PortRequest::SetDtr(false));
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -1,10 +1,11 @@
-use crate::log_monitor;
-
-use super::data::SerialPortSettings;
-use super::log_monitor::{AsyncLogMonitor, Log, MonitorMessage};
+use super::autobaud;
+use super::data::{PortSetting, SerialPortSettings};
+use super::log_monitor::{
+    send_event, AsyncLogMonitor, ModemLines, ModemStatus, MonitorMessage, PortActivity,
+    PortRequest, WriteProxy,
+};
 use super::read_line;
 
-use chrono;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::time::error::Elapsed;
@@ -15,30 +16,24 @@ const DEFAULT_BUFFER_SIZE: usize = 128;
 // TODO: depends on a baud rate
 const BUFFER_COMPLETION_TIMEOUT: u64 = 50;
 const IO_TIMEOUT: u64 = 10;
+const MODEM_STATUS_POLL_INTERVAL: u64 = 100;
+/// Chunks of received data kept for slow subscribers of the port activity.
+const ACTIVITY_CAPACITY: usize = 256;
 
 pub struct SerialLogMonitor {
     port_settings: SerialPortSettings,
     serial_stream: SerialStream,
-    write_receiver: UnboundedReceiver<u8>,
-    write_sender: UnboundedSender<u8>,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
     recv_buffer: Vec<u8>,
-    process_buffer: Vec<u8>,
-}
-
-pub struct SerialLogMonitorWriteProxy {
-    write_sender: UnboundedSender<u8>,
-}
-
-impl SerialLogMonitorWriteProxy {
-    pub fn new(write_sender: UnboundedSender<u8>) -> SerialLogMonitorWriteProxy {
-        SerialLogMonitorWriteProxy {
-            write_sender: write_sender,
-        }
-    }
-
-    pub fn send(&self, data: u8) {
-        self.write_sender.send(data).unwrap();
-    }
+    lines: read_line::LineAssembler,
+    modem_lines: Option<ModemLines>,
+    modem_polling: bool,
+    /// Set while the TX line is held in the break condition
+    break_held: bool,
+    /// When a timed break ends
+    break_end: Option<tokio::time::Instant>,
+    activity_sender: tokio::sync::broadcast::Sender<PortActivity>,
 }
 
 impl SerialLogMonitor {
@@ -53,23 +48,44 @@ impl SerialLogMonitor {
             .set_timeout(std::time::Duration::from_millis(IO_TIMEOUT))
             .map_err(|e| format!("Failed to set serial port timeout: {}", e))?;
 
-        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<u8>();
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
 
         let recv_buffer = vec![0; DEFAULT_BUFFER_SIZE];
-        let process_buffer = vec![];
+        let lines = read_line::LineAssembler::new(port_settings.path.clone());
+        let (activity_sender, _) = tokio::sync::broadcast::channel(ACTIVITY_CAPACITY);
 
         Ok(SerialLogMonitor {
-            port_settings: port_settings,
-            serial_stream: serial_stream,
-            write_receiver: write_receiver,
-            write_sender: write_sender,
-            recv_buffer: recv_buffer,
-            process_buffer: process_buffer,
+            port_settings,
+            serial_stream,
+            write_receiver,
+            write_sender,
+            recv_buffer,
+            lines,
+            modem_lines: None,
+            modem_polling: true,
+            break_held: false,
+            break_end: None,
+            activity_sender,
         })
     }
 
-    pub fn get_write_proxy(&self) -> SerialLogMonitorWriteProxy {
-        SerialLogMonitorWriteProxy::new(self.write_sender.clone())
+    /// Logs the received data under another name than the path of the port.
+    pub fn set_log_name(&mut self, name: String) {
+        self.lines = read_line::LineAssembler::new(name);
+    }
+
+    /// Publishes the received data and the modem line changes of the port.
+    pub fn get_baud_rate(&self) -> u32 {
+        self.port_settings.baud_rate
+    }
+
+    pub fn get_activity_sender(&self) -> tokio::sync::broadcast::Sender<PortActivity> {
+        self.activity_sender.clone()
+    }
+
+    fn publish_activity(&self, activity: PortActivity) {
+        // nobody listening is not an error
+        let _ = self.activity_sender.send(activity);
     }
 
     async fn write_byte(&mut self, data: u8) -> Result<(), String> {
@@ -79,47 +95,215 @@ impl SerialLogMonitor {
         }
     }
 
-    async fn handle_write_request(&mut self, data: Option<u8>) {
-        if let Some(byte) = data {
-            self.write_byte(byte).await.unwrap();
+    fn configure(&mut self, setting: PortSetting) -> tokio_serial::Result<()> {
+        match setting {
+            PortSetting::BaudRate(baud_rate) => {
+                self.serial_stream.set_baud_rate(baud_rate)?;
+                self.port_settings.baud_rate = baud_rate;
+                Ok(())
+            }
+            PortSetting::Parity(parity) => self.serial_stream.set_parity(parity),
+            PortSetting::FlowControl(flow_control) => {
+                self.serial_stream.set_flow_control(flow_control)
+            }
+            PortSetting::DataBits(data_bits) => self.serial_stream.set_data_bits(data_bits),
+            PortSetting::StopBits(stop_bits) => self.serial_stream.set_stop_bits(stop_bits),
+        }
+    }
+
+    /// Reads whatever arrives at the current baud rate during the sample time.
+    /// Fails if the port is closed or cannot be read anymore, e.g. unplugged.
+    async fn read_sample(&mut self) -> Result<Vec<u8>, String> {
+        let deadline =
+            tokio::time::Instant::now() + std::time::Duration::from_millis(autobaud::SAMPLE_TIME);
+        let mut sample = Vec::new();
+
+        while sample.len() < autobaud::SAMPLE_SIZE {
+            match tokio::time::timeout_at(deadline, self.serial_stream.read(&mut self.recv_buffer))
+                .await
+            {
+                Ok(Ok(0)) => return Err("Port closed".to_string()),
+                Ok(Ok(n)) => sample.extend_from_slice(&self.recv_buffer[0..n]),
+                // read timeouts are expected when nothing is being sent
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Ok(Err(e)) => return Err(format!("Failed to read: {}", e)),
+                Err(_) => break,
+            }
+        }
+
+        Ok(sample)
+    }
+
+    async fn detect_baud_rate(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        print!("Detecting baud rate of {}\r\n", self.port_settings.path);
+
+        let mut scores = Vec::new();
+        for baud_rate in autobaud::CANDIDATE_BAUD_RATES {
+            if self.serial_stream.set_baud_rate(*baud_rate).is_err() {
+                continue;
+            }
+            let _ = self.serial_stream.clear(tokio_serial::ClearBuffer::Input);
+            match self.read_sample().await {
+                Ok(sample) => scores.push((*baud_rate, autobaud::score_sample(&sample))),
+                Err(e) => {
+                    let description = format!("Baud rate detection stopped: {}", e);
+                    self.send_event(description, sender_queue);
+                    break;
+                }
+            }
+        }
+
+        let (baud_rate, description) = match autobaud::pick_best(&scores) {
+            Some(detection) => (
+                detection.baud_rate,
+                format!(
+                    "Detected baud rate {} with {:.0}% confidence",
+                    detection.baud_rate,
+                    detection.confidence * 100.0
+                ),
+            ),
+            None => (
+                self.port_settings.baud_rate,
+                format!(
+                    "Failed to detect baud rate, falling back to {}",
+                    self.port_settings.baud_rate
+                ),
+            ),
+        };
+
+        let description = match self.configure(PortSetting::BaudRate(baud_rate)) {
+            Ok(_) => description,
+            Err(e) => format!("Failed to set detected baud rate {}: {}", baud_rate, e),
+        };
+        self.send_event(description, sender_queue);
+        // drop the partial line received at the last tried rate
+        self.lines.clear();
+    }
+
+    /// Sets the break condition, it is cleared by the monitor loop once the time is up.
+    fn send_break(&mut self, duration: std::time::Duration) -> Result<(), String> {
+        self.serial_stream
+            .set_break()
+            .map_err(|e| format!("Failed to set break: {}", e))?;
+        self.break_held = true;
+        self.break_end = Some(tokio::time::Instant::now() + duration);
+        Ok(())
+    }
+
+    fn end_break(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        self.break_held = false;
+        self.break_end = None;
+        if let Err(e) = self.serial_stream.clear_break() {
+            self.send_event(format!("Failed to clear break: {}", e), sender_queue);
         }
     }
 
-    async fn handle_read_timeout(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
-        if self.process_buffer.len() == 0 {
+    fn report_control_line_change(
+        &self,
+        line: &str,
+        level: bool,
+        result: tokio_serial::Result<()>,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        let description = match result {
+            Ok(_) => format!("{} {}", line, if level { "set" } else { "cleared" }),
+            Err(e) => format!("Failed to change {}: {}", line, e),
+        };
+        self.send_event(description, sender_queue);
+    }
+
+    fn read_modem_lines(&mut self) -> tokio_serial::Result<ModemLines> {
+        Ok(ModemLines {
+            cts: self.serial_stream.read_clear_to_send()?,
+            dsr: self.serial_stream.read_data_set_ready()?,
+            dcd: self.serial_stream.read_carrier_detect()?,
+            ri: self.serial_stream.read_ring_indicator()?,
+        })
+    }
+
+    fn poll_modem_lines(&mut self, sender_queue: &UnboundedSender<MonitorMessage>) {
+        let lines = match self.read_modem_lines() {
+            Ok(l) => l,
+            Err(e) => {
+                // e.g. pseudo terminals do not support the modem lines
+                self.modem_polling = false;
+                self.send_event(
+                    format!("Modem status lines are not available: {}", e),
+                    sender_queue,
+                );
+                return;
+            }
+        };
+
+        if self.modem_lines == Some(lines) {
             return;
         }
 
-        let unsolicted_msg = String::from_utf8_lossy(self.process_buffer.as_slice()).to_string();
-        self.process_buffer.clear();
+        self.modem_lines = Some(lines);
+        self.publish_activity(PortActivity::ModemLines(lines));
+        let status = ModemStatus {
+            source_name: self.get_common_name(),
+            lines,
+            timestamp: chrono::Local::now(),
+        };
         sender_queue
-            .send(log_monitor::MonitorMessage::UnsolictedMessage(
-                unsolicted_msg,
-            ))
+            .send(MonitorMessage::ModemStatus(status))
             .unwrap();
     }
 
-    async fn handle_incomming_data(
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    async fn handle_write_request(
         &mut self,
-        n: usize,
+        request: Option<PortRequest>,
         sender_queue: &UnboundedSender<MonitorMessage>,
     ) {
-        if n == 0 {
-            return;
-        }
-
-        // concatenate the new data to the process buffer
-        self.process_buffer
-            .extend_from_slice(&self.recv_buffer[0..n]);
-
-        while let Some(line) = read_line::read_line_from_buffer(&mut self.process_buffer) {
-            let stripped_line = line.trim().to_string();
-            let message = Log {
-                source_name: self.get_common_name(),
-                message: stripped_line,
-                timestamp: chrono::Local::now(),
-            };
-            sender_queue.send(MonitorMessage::Log(message)).unwrap();
+        match request {
+            Some(PortRequest::Write(byte)) => {
+                if let Err(e) = self.write_byte(byte).await {
+                    self.send_event(e, sender_queue);
+                }
+            }
+            Some(PortRequest::SetDtr(level)) => {
+                let result = self.serial_stream.write_data_terminal_ready(level);
+                self.report_control_line_change("DTR", level, result, sender_queue);
+            }
+            Some(PortRequest::SetRts(level)) => {
+                let result = self.serial_stream.write_request_to_send(level);
+                self.report_control_line_change("RTS", level, result, sender_queue);
+            }
+            Some(PortRequest::Break(duration)) => {
+                let description = match self.send_break(duration) {
+                    Ok(_) => format!("TX break for {} ms", duration.as_millis()),
+                    Err(e) => e,
+                };
+                self.send_event(description, sender_queue);
+            }
+            Some(PortRequest::SetBreak(on)) => {
+                // replaces a timed break still in progress
+                self.break_end = None;
+                let result = if on {
+                    self.serial_stream.set_break()
+                } else {
+                    self.serial_stream.clear_break()
+                };
+                self.break_held = on && result.is_ok();
+                self.report_control_line_change("Break", on, result, sender_queue);
+            }
+            Some(PortRequest::Configure(setting)) => {
+                let description = match self.configure(setting) {
+                    Ok(_) => format!("Port settings changed: {}", setting),
+                    Err(e) => format!("Failed to change port settings ({}): {}", setting, e),
+                };
+                self.send_event(description, sender_queue);
+            }
+            Some(request @ PortRequest::SendFrame(_)) => {
+                let description = request.unsupported_description("for serial ports");
+                self.send_event(description, sender_queue);
+            }
+            None => {}
         }
     }
 
@@ -130,17 +314,18 @@ impl SerialLogMonitor {
     ) {
         match read_result {
             Ok(Ok(n)) => {
-                self.handle_incomming_data(n, sender_queue).await;
+                if self.activity_sender.receiver_count() > 0 {
+                    self.publish_activity(PortActivity::Received(self.recv_buffer[0..n].to_vec()));
+                }
+                self.lines.push(&self.recv_buffer[0..n], sender_queue);
             }
             // timweout
             Err(_) => {
-                self.handle_read_timeout(sender_queue).await;
+                self.lines.flush(sender_queue);
             }
 
             // FIXME: handle the error
-            Ok(Err(_)) => {
-                return;
-            }
+            Ok(Err(_)) => {}
         }
     }
 }
@@ -150,24 +335,54 @@ impl AsyncLogMonitor for SerialLogMonitor {
         self.port_settings.path.clone()
     }
 
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
     async fn monitor(
         &mut self,
         cancel_token: CancellationToken,
         sender_queue: UnboundedSender<MonitorMessage>,
     ) {
+        if self.port_settings.autobaud {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                _ = self.detect_baud_rate(&sender_queue) => {}
+            }
+        }
+
         print!(
             "Starting {} port monitor @ {}\r\n",
             self.port_settings.path, self.port_settings.baud_rate
         );
 
+        let mut modem_poll_interval =
+            tokio::time::interval(std::time::Duration::from_millis(MODEM_STATUS_POLL_INTERVAL));
+
         loop {
             tokio::select! {
                 _ = cancel_token.cancelled() => {
+                    if self.break_held {
+                        self.end_break(&sender_queue);
+                    }
                     return;
                 }
 
+                _ = modem_poll_interval.tick(), if self.modem_polling => {
+                    self.poll_modem_lines(&sender_queue);
+                }
+
+                _ = tokio::time::sleep_until(
+                    self.break_end.unwrap_or_else(tokio::time::Instant::now)
+                ), if self.break_end.is_some() => {
+                    self.end_break(&sender_queue);
+                }
+
                 write_data = self.write_receiver.recv() => {
-                    self.handle_write_request(write_data).await;
+                    self.handle_write_request(write_data, &sender_queue).await;
                 }
 
                 read_result = tokio::time::timeout(
@@ -0,0 +1,595 @@
+use super::data::{PortServerSettings, TcpProtocol};
+use super::log_monitor::{send_event, MonitorMessage, PortActivity, PortRequest, WriteProxy};
+use super::rfc2217;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_BUFFER_SIZE: usize = 128;
+const ACCEPT_RETRY_DELAY: u64 = 1000;
+
+/// Address of the client currently allowed to write to the port.
+type WriteLock = Arc<Mutex<Option<SocketAddr>>>;
+
+/// Shares a local port with any number of TCP clients. Every client sees the data
+/// received from the port, the first one sending any input becomes the only writer
+/// until it disconnects.
+pub struct PortServer {
+    settings: PortServerSettings,
+    activity_sender: broadcast::Sender<PortActivity>,
+    /// None for read-only ports
+    write_proxy: Option<WriteProxy>,
+    write_lock: WriteLock,
+    /// Settings changed by the clients, shared so every client is told the same
+    port_state: Arc<Mutex<rfc2217::PortState>>,
+}
+
+impl PortServer {
+    pub fn new(
+        settings: PortServerSettings,
+        baud_rate: u32,
+        activity_sender: broadcast::Sender<PortActivity>,
+        write_proxy: Option<WriteProxy>,
+    ) -> PortServer {
+        PortServer {
+            settings,
+            activity_sender,
+            write_proxy,
+            write_lock: Arc::new(Mutex::new(None)),
+            port_state: Arc::new(Mutex::new(rfc2217::PortState::new(baud_rate))),
+        }
+    }
+
+    pub async fn run(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        let listener = match TcpListener::bind(&self.settings.listen.address).await {
+            Ok(l) => l,
+            Err(e) => {
+                let description = format!(
+                    "Failed to serve on {}: {}",
+                    self.settings.listen.get_name(),
+                    e
+                );
+                send_event(self.settings.port.clone(), description, &sender_queue);
+                return;
+            }
+        };
+
+        self.serve(listener, cancel_token, sender_queue).await;
+    }
+
+    /// Accepts clients until the server is cancelled.
+    pub async fn serve(
+        &mut self,
+        listener: TcpListener,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        let description = format!("Serving on {}", self.settings.listen.get_name());
+        send_event(self.settings.port.clone(), description, &sender_queue);
+
+        let mut failing = false;
+        loop {
+            let (stream, address) = tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                result = listener.accept() => match result {
+                    Ok(connection) => {
+                        failing = false;
+                        connection
+                    }
+                    // e.g. too many open files, reported once until a client is accepted
+                    Err(e) => {
+                        if !failing {
+                            failing = true;
+                            let description = format!("Failed to accept a client: {}", e);
+                            send_event(self.settings.port.clone(), description, &sender_queue);
+                        }
+                        let delay = std::time::Duration::from_millis(ACCEPT_RETRY_DELAY);
+                        tokio::select! {
+                            _ = cancel_token.cancelled() => return,
+                            _ = tokio::time::sleep(delay) => continue,
+                        }
+                    }
+                }
+            };
+
+            let session = match self.settings.listen.protocol {
+                TcpProtocol::Raw => None,
+                TcpProtocol::Rfc2217 => Some(rfc2217::Session::server()),
+            };
+            let mut client = ServedClient {
+                port_name: self.settings.port.clone(),
+                address,
+                stream,
+                session,
+                activity_receiver: self.activity_sender.subscribe(),
+                write_proxy: self.write_proxy.clone(),
+                write_lock: self.write_lock.clone(),
+                port_state: self.port_state.clone(),
+                break_held: false,
+                input_ignored: false,
+                sender_queue: sender_queue.clone(),
+            };
+            let client_cancel_token = cancel_token.clone();
+            tokio::spawn(async move {
+                client.run(client_cancel_token).await;
+            });
+        }
+    }
+}
+
+struct ServedClient {
+    port_name: String,
+    address: SocketAddr,
+    stream: TcpStream,
+    session: Option<rfc2217::Session>,
+    activity_receiver: broadcast::Receiver<PortActivity>,
+    write_proxy: Option<WriteProxy>,
+    write_lock: WriteLock,
+    port_state: Arc<Mutex<rfc2217::PortState>>,
+    /// Set while the client holds the TX line in the break condition
+    break_held: bool,
+    /// The client was told it is not the writer
+    input_ignored: bool,
+    sender_queue: UnboundedSender<MonitorMessage>,
+}
+
+impl ServedClient {
+    fn send_event(&self, description: String) {
+        send_event(self.port_name.clone(), description, &self.sender_queue);
+    }
+
+    async fn run(&mut self, cancel_token: CancellationToken) {
+        self.send_event(format!("Client {} connected", self.address));
+
+        let result = self.handle_connection(&cancel_token).await;
+        let description = match result {
+            Ok(_) => format!("Client {} disconnected", self.address),
+            Err(e) => format!("Client {} disconnected: {}", self.address, e),
+        };
+        self.send_event(description);
+
+        if self.break_held && self.request(PortRequest::SetBreak(false)) {
+            self.port_state.lock().unwrap().break_on = false;
+        }
+        let mut writer = self.write_lock.lock().unwrap();
+        if *writer == Some(self.address) {
+            *writer = None;
+        }
+    }
+
+    async fn handle_connection(
+        &mut self,
+        cancel_token: &CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        if let Some(session) = &mut self.session {
+            let greeting = session.get_greeting();
+            self.stream.write_all(&greeting).await?;
+        }
+
+        let mut recv_buffer = vec![0; DEFAULT_BUFFER_SIZE];
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return Ok(());
+                }
+
+                activity = self.activity_receiver.recv() => match activity {
+                    Ok(activity) => self.forward_activity(activity).await?,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        self.send_event(format!(
+                            "Client {} is too slow, {} chunks dropped",
+                            self.address, count
+                        ));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Ok(());
+                    }
+                },
+
+                read_result = self.stream.read(&mut recv_buffer) => match read_result? {
+                    0 => return Ok(()),
+                    n => self.handle_input(&recv_buffer[0..n]).await?,
+                }
+            }
+        }
+    }
+
+    async fn forward_activity(&mut self, activity: PortActivity) -> Result<(), std::io::Error> {
+        let output = match (activity, &self.session) {
+            (PortActivity::Received(data), None) => data,
+            (PortActivity::Received(data), Some(_)) => rfc2217::escape_data(&data),
+            (PortActivity::ModemLines(_), None) => return Ok(()),
+            (PortActivity::ModemLines(lines), Some(_)) => {
+                let modem_state = rfc2217::get_modem_state(&lines);
+                rfc2217::ComPortCommand::NotifyModemState(modem_state).encode(true)
+            }
+        };
+
+        self.stream.write_all(&output).await
+    }
+
+    /// Checks whether the client may write, making it the writer if nobody else is.
+    fn acquire_write_lock(&mut self) -> bool {
+        let description = {
+            let mut writer = self.write_lock.lock().unwrap();
+            match (*writer, &self.write_proxy) {
+                (_, None) => "the port is read-only".to_string(),
+                (None, Some(_)) => {
+                    *writer = Some(self.address);
+                    drop(writer);
+                    self.input_ignored = false;
+                    self.send_event(format!("Client {} is now writing", self.address));
+                    return true;
+                }
+                (Some(address), Some(_)) if address == self.address => return true,
+                (Some(address), Some(_)) => format!("client {} is writing", address),
+            }
+        };
+
+        // report once until the client becomes the writer
+        if !self.input_ignored {
+            self.input_ignored = true;
+            self.send_event(format!(
+                "Input from client {} ignored, {}",
+                self.address, description
+            ));
+        }
+        false
+    }
+
+    fn request(&mut self, request: PortRequest) -> bool {
+        if !self.acquire_write_lock() {
+            return false;
+        }
+
+        match &self.write_proxy {
+            Some(write_proxy) => write_proxy.request(request).is_ok(),
+            None => false,
+        }
+    }
+
+    async fn handle_input(&mut self, input: &[u8]) -> Result<(), std::io::Error> {
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => {
+                for &byte in input {
+                    self.request(PortRequest::Write(byte));
+                }
+                return Ok(());
+            }
+        };
+
+        let mut received = Vec::new();
+        let mut output = session.receive(input, &mut received);
+
+        for item in received {
+            match item {
+                rfc2217::Received::Data(data) => {
+                    for byte in data {
+                        self.request(PortRequest::Write(byte));
+                    }
+                }
+                rfc2217::Received::Command(command) => {
+                    // every SET command is answered, as the RFC requires
+                    if let Some(reply) = self.handle_command(&command) {
+                        output.extend(reply.encode(true));
+                    }
+                }
+            }
+        }
+
+        if !output.is_empty() {
+            self.stream.write_all(&output).await?;
+        }
+        Ok(())
+    }
+
+    /// Applies a COM port command, returns the reply with the value now in effect.
+    fn handle_command(
+        &mut self,
+        command: &rfc2217::ComPortCommand,
+    ) -> Option<rfc2217::ComPortCommand> {
+        let accepted = match command {
+            // the port is held in the break condition until the client clears it
+            rfc2217::ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_ON) => {
+                self.break_held = self.request(PortRequest::SetBreak(true));
+                self.break_held
+            }
+            rfc2217::ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_OFF) => {
+                self.break_held = false;
+                self.request(PortRequest::SetBreak(false))
+            }
+            // queries and values the port does not support
+            command => match rfc2217::get_port_request(command) {
+                Some(request) => self.request(request),
+                None => false,
+            },
+        };
+
+        let mut port_state = self.port_state.lock().unwrap();
+        if accepted {
+            port_state.apply(command);
+        }
+        port_state.get_reply(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{PortSetting, TcpSettings};
+
+    struct TestServer {
+        address: String,
+        activity_sender: broadcast::Sender<PortActivity>,
+        request_receiver: tokio::sync::mpsc::UnboundedReceiver<PortRequest>,
+        _event_receiver: tokio::sync::mpsc::UnboundedReceiver<MonitorMessage>,
+        cancel_token: CancellationToken,
+    }
+
+    async fn start_server(protocol: TcpProtocol) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (activity_sender, _) = broadcast::channel(16);
+        let (request_sender, request_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (sender, event_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let cancel_token = CancellationToken::new();
+
+        let mut server = PortServer::new(
+            PortServerSettings {
+                port: "/dev/ttyUSB0".to_string(),
+                listen: TcpSettings {
+                    address: address.clone(),
+                    protocol,
+                },
+            },
+            115_200,
+            activity_sender.clone(),
+            Some(WriteProxy::new(request_sender)),
+        );
+        let server_cancel_token = cancel_token.clone();
+        tokio::spawn(async move {
+            server.serve(listener, server_cancel_token, sender).await;
+        });
+
+        TestServer {
+            address,
+            activity_sender,
+            request_receiver,
+            _event_receiver: event_receiver,
+            cancel_token,
+        }
+    }
+
+    async fn next_request(
+        receiver: &mut tokio::sync::mpsc::UnboundedReceiver<PortRequest>,
+    ) -> PortRequest {
+        tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Waits until the server subscribed the given number of clients.
+    async fn wait_for_clients(server: &TestServer, count: usize) {
+        while server.activity_sender.receiver_count() < count {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_viewers_and_writer() {
+        let mut server = start_server(TcpProtocol::Raw).await;
+        let mut writer = TcpStream::connect(&server.address).await.unwrap();
+        let mut viewer = TcpStream::connect(&server.address).await.unwrap();
+        wait_for_clients(&server, 2).await;
+
+        server
+            .activity_sender
+            .send(PortActivity::Received(b"boot\r\n".to_vec()))
+            .unwrap();
+        for client in [&mut writer, &mut viewer] {
+            let mut received = [0u8; 6];
+            client.read_exact(&mut received).await.unwrap();
+            assert_eq!(&received, b"boot\r\n");
+        }
+
+        writer.write_all(b"a").await.unwrap();
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Write(b'a')
+        );
+
+        // only the first client writing gets through
+        viewer.write_all(b"b").await.unwrap();
+        writer.write_all(b"c").await.unwrap();
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Write(b'c')
+        );
+
+        // the lock is released when the writer leaves
+        drop(writer);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        viewer.write_all(b"d").await.unwrap();
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Write(b'd')
+        );
+
+        server.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_rfc2217_client() {
+        let mut server = start_server(TcpProtocol::Rfc2217).await;
+        let mut stream = TcpStream::connect(&server.address).await.unwrap();
+        wait_for_clients(&server, 1).await;
+
+        let mut session = rfc2217::Session::client();
+        let mut input = session.get_greeting();
+        input.extend(session.encode_request(&PortRequest::Configure(PortSetting::BaudRate(9600))));
+        input.extend(rfc2217::escape_data(&[0xff]));
+        input.extend(session.encode_request(&PortRequest::SetDtr(false)));
+        stream.write_all(&input).await.unwrap();
+
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Configure(PortSetting::BaudRate(9600))
+        );
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Write(0xff)
+        );
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::SetDtr(false)
+        );
+
+        server
+            .activity_sender
+            .send(PortActivity::Received(vec![b'x', 0xff]))
+            .unwrap();
+
+        // the changes are confirmed before the data of the port arrives
+        let mut received = Vec::new();
+        while !received
+            .iter()
+            .any(|r| matches!(r, rfc2217::Received::Data(_)))
+        {
+            let mut buffer = [0u8; 64];
+            let n = stream.read(&mut buffer).await.unwrap();
+            assert!(n > 0);
+            session.receive(&buffer[0..n], &mut received);
+        }
+        assert_eq!(
+            received,
+            vec![
+                rfc2217::Received::Command(rfc2217::ComPortCommand::SetBaudRate(9600)),
+                rfc2217::Received::Command(rfc2217::ComPortCommand::SetControl(
+                    rfc2217::CONTROL_DTR_OFF
+                )),
+                rfc2217::Received::Data(vec![b'x', 0xff]),
+            ]
+        );
+
+        server.cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_rfc2217_break() {
+        let mut server = start_server(TcpProtocol::Rfc2217).await;
+        let mut stream = TcpStream::connect(&server.address).await.unwrap();
+        wait_for_clients(&server, 1).await;
+
+        let mut session = rfc2217::Session::client();
+        let mut input = session.get_greeting();
+        input.extend(session.encode_request(&PortRequest::SetBreak(true)));
+        input.extend(rfc2217::escape_data(b"a"));
+        input.extend(session.encode_request(&PortRequest::SetBreak(false)));
+        input.extend(session.encode_request(&PortRequest::SetBreak(true)));
+        stream.write_all(&input).await.unwrap();
+
+        // the break is asserted as soon as the client asks for it
+        for request in [
+            PortRequest::SetBreak(true),
+            PortRequest::Write(b'a'),
+            PortRequest::SetBreak(false),
+            PortRequest::SetBreak(true),
+        ] {
+            assert_eq!(next_request(&mut server.request_receiver).await, request);
+        }
+
+        // a break left on by the client is cleared when it disconnects
+        drop(stream);
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::SetBreak(false)
+        );
+
+        server.cancel_token.cancel();
+    }
+
+    /// Reads the COM port commands sent by the server until the given number arrived.
+    async fn read_commands(
+        stream: &mut TcpStream,
+        session: &mut rfc2217::Session,
+        count: usize,
+    ) -> Vec<rfc2217::ComPortCommand> {
+        let mut received = Vec::new();
+        while received.len() < count {
+            let mut buffer = [0u8; 64];
+            let n = stream.read(&mut buffer).await.unwrap();
+            assert!(n > 0);
+            session.receive(&buffer[0..n], &mut received);
+        }
+        received
+            .into_iter()
+            .map(|r| match r {
+                rfc2217::Received::Command(command) => command,
+                r => panic!("Unexpected data: {:?}", r),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rfc2217_replies() {
+        use rfc2217::ComPortCommand;
+
+        let mut server = start_server(TcpProtocol::Rfc2217).await;
+        let mut writer = TcpStream::connect(&server.address).await.unwrap();
+        let mut viewer = TcpStream::connect(&server.address).await.unwrap();
+        wait_for_clients(&server, 2).await;
+
+        let mut writer_session = rfc2217::Session::client();
+        let mut input = writer_session.get_greeting();
+        for command in [
+            ComPortCommand::SetBaudRate(9600),
+            ComPortCommand::SetBaudRate(0),
+            ComPortCommand::SetParity(4),
+            ComPortCommand::SetControl(rfc2217::CONTROL_REQUEST_DTR),
+        ] {
+            input.extend(command.encode(false));
+        }
+        writer.write_all(&input).await.unwrap();
+        assert_eq!(
+            read_commands(&mut writer, &mut writer_session, 4).await,
+            vec![
+                ComPortCommand::SetBaudRate(9600),
+                ComPortCommand::SetBaudRate(9600),
+                // mark parity is not supported
+                ComPortCommand::SetParity(1),
+                ComPortCommand::SetControl(rfc2217::CONTROL_DTR_ON),
+            ]
+        );
+        assert_eq!(
+            next_request(&mut server.request_receiver).await,
+            PortRequest::Configure(PortSetting::BaudRate(9600))
+        );
+
+        // a client that is not the writer is told the change was not made
+        let mut viewer_session = rfc2217::Session::client();
+        let mut input = viewer_session.get_greeting();
+        input.extend(ComPortCommand::SetBaudRate(57600).encode(false));
+        viewer.write_all(&input).await.unwrap();
+        assert_eq!(
+            read_commands(&mut viewer, &mut viewer_session, 1).await,
+            vec![ComPortCommand::SetBaudRate(9600)]
+        );
+        assert!(server.request_receiver.try_recv().is_err());
+
+        server.cancel_token.cancel();
+    }
+}
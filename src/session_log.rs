@@ -0,0 +1,397 @@
+use super::data::LogFormat;
+use super::log_monitor::{Event, Log, ModemLines, ModemStatus, MonitorMessage};
+
+const LOG_PREFIX: &str = ">> [";
+const EVENT_PREFIX: &str = ">! [";
+const MODEM_STATUS_PREFIX: &str = ">~ [";
+const PARTIAL_PREFIX: &str = ">>> ";
+/// Timestamps as written by the session logger, e.g. 2024-03-01 12:00:00.123 +01:00
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f %:z";
+
+/// Messages read back from a recorded session.
+#[derive(Debug)]
+pub struct SessionLog {
+    pub messages: Vec<MonitorMessage>,
+    /// Lines that could not be parsed
+    pub skipped: usize,
+}
+
+/// Reads a session log written by drfish, as text or JSON lines, or a raw capture
+/// of a port. Raw data has no timestamps, the lines are spaced as if received at
+/// the given baud rate.
+pub fn read(path: &str, format: LogFormat, raw_baud_rate: u32) -> Result<SessionLog, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let format = match format {
+        LogFormat::Auto => detect_format(&data),
+        format => format,
+    };
+
+    let log = match format {
+        LogFormat::Text => parse_text(&String::from_utf8_lossy(&data)),
+        LogFormat::JsonLines => parse_json_lines(&String::from_utf8_lossy(&data)),
+        _ => parse_raw(&data, path, raw_baud_rate, chrono::Local::now()),
+    };
+    Ok(log)
+}
+
+/// Reads a text log of another tool, whose lines start with a timestamp in the
+/// given chrono format, e.g. "[%Y-%m-%d %H:%M:%S%.3f] ". Lines without a timestamp,
+/// like stack traces, are stamped with the time of the line before them.
+pub fn read_with_pattern(
+    path: &str,
+    pattern: &str,
+    source_name: &str,
+) -> Result<SessionLog, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(parse_pattern(
+        &String::from_utf8_lossy(&data),
+        pattern,
+        source_name,
+    ))
+}
+
+fn parse_timestamp_prefix<'a>(
+    line: &'a str,
+    pattern: &str,
+) -> Option<(chrono::DateTime<chrono::Local>, &'a str)> {
+    if let Ok((timestamp, rest)) = chrono::DateTime::parse_and_remainder(line, pattern) {
+        return Some((timestamp.with_timezone(&chrono::Local), rest));
+    }
+
+    // timestamps without an offset are in the local time zone
+    let (timestamp, rest) = chrono::NaiveDateTime::parse_and_remainder(line, pattern).ok()?;
+    let timestamp = timestamp.and_local_timezone(chrono::Local).earliest()?;
+    Some((timestamp, rest))
+}
+
+fn parse_pattern(text: &str, pattern: &str, source_name: &str) -> SessionLog {
+    let mut log = SessionLog {
+        messages: Vec::new(),
+        skipped: 0,
+    };
+    let mut last_timestamp = None;
+
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let (timestamp, message) = match (parse_timestamp_prefix(line, pattern), last_timestamp) {
+            (Some((timestamp, rest)), _) => (timestamp, rest),
+            (None, Some(timestamp)) => (timestamp, line),
+            (None, None) => {
+                log.skipped += 1;
+                continue;
+            }
+        };
+        last_timestamp = Some(timestamp);
+        log.messages.push(MonitorMessage::Log(Log {
+            source_name: source_name.to_string(),
+            message: message.trim().to_string(),
+            timestamp,
+        }));
+    }
+
+    log
+}
+
+fn detect_format(data: &[u8]) -> LogFormat {
+    let text = String::from_utf8_lossy(&data[0..data.len().min(1024)]);
+    let first_line = text.lines().map(|l| l.trim()).find(|l| !l.is_empty());
+
+    match first_line {
+        Some(line) if line.starts_with('{') => LogFormat::JsonLines,
+        Some(line)
+            if [
+                LOG_PREFIX,
+                EVENT_PREFIX,
+                MODEM_STATUS_PREFIX,
+                PARTIAL_PREFIX,
+            ]
+            .iter()
+            .any(|p| line.starts_with(p)) =>
+        {
+            LogFormat::Text
+        }
+        _ => LogFormat::Raw,
+    }
+}
+
+fn parse_json_lines(text: &str) -> SessionLog {
+    let mut log = SessionLog {
+        messages: Vec::new(),
+        skipped: 0,
+    };
+
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match MonitorMessage::from_json(line) {
+            Ok(msg) => log.messages.push(msg),
+            Err(_) => log.skipped += 1,
+        }
+    }
+
+    log
+}
+
+fn parse_modem_lines(text: &str) -> Option<ModemLines> {
+    let mut lines = ModemLines::default();
+
+    for field in text.split_whitespace() {
+        let level = match field.split_once('=')? {
+            (name, "1") => (name, true),
+            (name, "0") => (name, false),
+            _ => return None,
+        };
+        match level {
+            ("CTS", level) => lines.cts = level,
+            ("DSR", level) => lines.dsr = level,
+            ("DCD", level) => lines.dcd = level,
+            ("RI", level) => lines.ri = level,
+            _ => return None,
+        }
+    }
+
+    Some(lines)
+}
+
+/// Parses a single record, e.g. ">> [timestamp] | source: message".
+fn parse_record(record: &str) -> Option<MonitorMessage> {
+    let (kind, rest) = record.split_at(LOG_PREFIX.len());
+    let (timestamp, rest) = rest.split_once("] | ")?;
+    let timestamp = chrono::DateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()?
+        .with_timezone(&chrono::Local);
+    let (source_name, text) = rest.split_once(": ")?;
+    let source_name = source_name.to_string();
+
+    let msg = match kind {
+        LOG_PREFIX => MonitorMessage::Log(Log {
+            source_name,
+            message: text.to_string(),
+            timestamp,
+        }),
+        EVENT_PREFIX => MonitorMessage::Event(Event {
+            source_name,
+            description: text.to_string(),
+            timestamp,
+        }),
+        _ => MonitorMessage::ModemStatus(ModemStatus {
+            source_name,
+            lines: parse_modem_lines(text)?,
+            timestamp,
+        }),
+    };
+    Some(msg)
+}
+
+/// Finds the start of the next record after a partial line, which is written
+/// without a line ending.
+fn find_record_start(text: &str) -> Option<usize> {
+    [
+        LOG_PREFIX,
+        EVENT_PREFIX,
+        MODEM_STATUS_PREFIX,
+        PARTIAL_PREFIX,
+    ]
+    .iter()
+    .filter_map(|p| text.find(p))
+    .min()
+}
+
+fn parse_text(text: &str) -> SessionLog {
+    let mut log = SessionLog {
+        messages: Vec::new(),
+        skipped: 0,
+    };
+
+    for line in text.split('\n') {
+        let mut rest = line.strip_suffix('\r').unwrap_or(line);
+
+        while let Some(partial) = rest.strip_prefix(PARTIAL_PREFIX) {
+            let end = find_record_start(partial).unwrap_or(partial.len());
+            log.messages.push(MonitorMessage::UnsolictedMessage(
+                partial[0..end].to_string(),
+            ));
+            rest = &partial[end..];
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+        let record = [LOG_PREFIX, EVENT_PREFIX, MODEM_STATUS_PREFIX]
+            .iter()
+            .any(|p| rest.starts_with(p))
+            .then(|| parse_record(rest))
+            .flatten();
+        match record {
+            Some(msg) => log.messages.push(msg),
+            None => log.skipped += 1,
+        }
+    }
+
+    log
+}
+
+fn parse_raw(
+    data: &[u8],
+    source_name: &str,
+    baud_rate: u32,
+    start: chrono::DateTime<chrono::Local>,
+) -> SessionLog {
+    // a start and a stop bit for every byte
+    let bytes_per_second = (baud_rate as f64 / 10.0).max(1.0);
+    let mut messages = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let end = match data[offset..].iter().position(|b| *b == b'\n') {
+            Some(i) => offset + i + 1,
+            None => data.len(),
+        };
+        let text = String::from_utf8_lossy(&data[offset..end]);
+        let elapsed = std::time::Duration::from_secs_f64(offset as f64 / bytes_per_second);
+
+        messages.push(if text.ends_with('\n') {
+            MonitorMessage::Log(Log {
+                source_name: source_name.to_string(),
+                message: text.trim().to_string(),
+                timestamp: start + elapsed,
+            })
+        } else {
+            MonitorMessage::UnsolictedMessage(text.to_string())
+        });
+        offset = end;
+    }
+
+    SessionLog {
+        messages,
+        skipped: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_timestamp(text: &str) -> chrono::DateTime<chrono::Local> {
+        chrono::DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&chrono::Local)
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let first = get_timestamp("2024-03-01T12:00:00.250+01:00");
+        let second = get_timestamp("2024-03-01T12:00:01+01:00");
+        // the way the session logger writes the messages
+        let text = format!(
+            ">> [{}] | /dev/ttyUSB0: boot: ok\r\n>>> login>! [{}] | cmd:echo: DTR set\r\n\
+             >~ [{}] | /dev/ttyUSB0: CTS=1 DSR=0 DCD=1 RI=0\r\ngarbage\r\n",
+            first, second, second
+        );
+
+        let log = parse_text(&text);
+        assert_eq!(log.skipped, 1);
+        assert_eq!(
+            log.messages,
+            vec![
+                MonitorMessage::Log(Log {
+                    source_name: "/dev/ttyUSB0".to_string(),
+                    message: "boot: ok".to_string(),
+                    timestamp: first,
+                }),
+                MonitorMessage::UnsolictedMessage("login".to_string()),
+                MonitorMessage::Event(Event {
+                    source_name: "cmd:echo".to_string(),
+                    description: "DTR set".to_string(),
+                    timestamp: second,
+                }),
+                MonitorMessage::ModemStatus(ModemStatus {
+                    source_name: "/dev/ttyUSB0".to_string(),
+                    lines: ModemLines {
+                        cts: true,
+                        dsr: false,
+                        dcd: true,
+                        ri: false,
+                    },
+                    timestamp: second,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_lines() {
+        let msg = MonitorMessage::Event(Event {
+            source_name: "udp://0.0.0.0:5000".to_string(),
+            description: "Failed to listen".to_string(),
+            timestamp: chrono::Local::now(),
+        });
+        let text = format!("{}\n{{\"type\": \"unknown\"}}\n", msg.to_json_line());
+
+        let log = parse_json_lines(&text);
+        assert_eq!(log.messages, vec![msg]);
+        assert_eq!(log.skipped, 1);
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        let start = chrono::Local::now();
+        let log = parse_raw(b"0123456789\nab", "capture.bin", 1000, start);
+
+        assert_eq!(log.messages.len(), 2);
+        assert_eq!(log.messages[0].get_timestamp(), Some(start));
+        assert_eq!(
+            log.messages[1],
+            MonitorMessage::UnsolictedMessage("ab".to_string())
+        );
+        let log = parse_raw(b"0123456789\nab\n", "capture.bin", 1000, start);
+        // 11 bytes at 100 bytes per second
+        assert_eq!(
+            log.messages[1].get_timestamp(),
+            Some(start + std::time::Duration::from_millis(110))
+        );
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        let text = "boot\n[2024-03-01 12:00:00.500] panic at main.c\n  #0 0x0800 in main\n\n\
+                    [2024-03-01 12:00:01.000] restarting\n";
+
+        let log = parse_pattern(text, "[%Y-%m-%d %H:%M:%S%.3f]", "host-a");
+        assert_eq!(log.skipped, 1);
+        assert_eq!(log.messages.len(), 3);
+        let first = log.messages[0].get_timestamp().unwrap();
+        assert_eq!(log.messages[1].get_timestamp(), Some(first));
+        assert_eq!(
+            log.messages[1],
+            MonitorMessage::Log(Log {
+                source_name: "host-a".to_string(),
+                message: "#0 0x0800 in main".to_string(),
+                timestamp: first,
+            })
+        );
+        assert_eq!(
+            log.messages[2].get_timestamp(),
+            Some(first + chrono::TimeDelta::milliseconds(500))
+        );
+
+        let log = parse_pattern("2024-03-01T12:00:00+02:00 up\n", "%+", "host-b");
+        assert_eq!(
+            log.messages[0].get_timestamp(),
+            Some(get_timestamp("2024-03-01T10:00:00+00:00"))
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            detect_format(b"\n{\"type\": \"log\"}\n"),
+            LogFormat::JsonLines
+        );
+        assert_eq!(
+            detect_format(b">> [2024-03-01] | a: b\r\n"),
+            LogFormat::Text
+        );
+        assert_eq!(detect_format(b">>> partial"), LogFormat::Text);
+        assert_eq!(detect_format(b"ets Jun  8 2016\r\n"), LogFormat::Raw);
+        assert_eq!(detect_format(b""), LogFormat::Raw);
+    }
+}
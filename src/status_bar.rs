@@ -0,0 +1,99 @@
+use std::io::Write;
+
+/// Single line pinned to the bottom of the terminal.
+///
+/// The rest of the screen is turned into a scroll region, so the log output
+/// scrolls above the status bar without overwriting it.
+pub struct StatusBar {
+    fields: indexmap::IndexMap<String, String>,
+    terminal_height: Option<u16>,
+}
+
+impl StatusBar {
+    pub fn new() -> Self {
+        StatusBar {
+            fields: indexmap::IndexMap::new(),
+            terminal_height: None,
+        }
+    }
+
+    pub fn set_field(&mut self, name: &str, value: String) {
+        self.fields.insert(name.to_string(), value);
+    }
+
+    pub fn remove_field(&mut self, name: &str) {
+        self.fields.shift_remove(name);
+    }
+
+    pub fn draw(&mut self, stdout: &mut std::io::Stdout) {
+        let (width, height) = match termion::terminal_size() {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        if height < 2 {
+            return;
+        }
+
+        if self.terminal_height != Some(height) {
+            // setting the scroll region moves the cursor home, put it back at the bottom
+            print!(
+                "\r\n\x1b[1;{}r{}",
+                height - 1,
+                termion::cursor::Goto(1, height - 1)
+            );
+            self.terminal_height = Some(height);
+        }
+
+        let text: String = self.render().chars().take(width as usize).collect();
+        print!(
+            "{}{}{}{}{}{}",
+            termion::cursor::Save,
+            termion::cursor::Goto(1, height),
+            termion::clear::CurrentLine,
+            termion::style::Invert,
+            text,
+            termion::style::Reset,
+        );
+        print!("{}", termion::cursor::Restore);
+        stdout.flush().unwrap();
+    }
+
+    /// Gives the whole screen back to the regular output.
+    pub fn clear(&mut self, stdout: &mut std::io::Stdout) {
+        let height = match self.terminal_height.take() {
+            Some(h) => h,
+            None => return,
+        };
+
+        print!(
+            "{}{}\x1b[r{}",
+            termion::cursor::Goto(1, height),
+            termion::clear::CurrentLine,
+            termion::cursor::Goto(1, height - 1),
+        );
+        print!("\r\n");
+        stdout.flush().unwrap();
+    }
+
+    fn render(&self) -> String {
+        self.fields
+            .iter()
+            .map(|(name, value)| format!(" {}: {} ", name, value))
+            .collect::<Vec<String>>()
+            .join("|")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_fields_in_insertion_order() {
+        let mut status_bar = StatusBar::new();
+        status_bar.set_field("input", "/dev/ttyUSB0".to_string());
+        status_bar.set_field("log", "log.txt".to_string());
+        status_bar.set_field("input", "all ports".to_string());
+        assert_eq!(status_bar.render(), " input: all ports | log: log.txt ");
+    }
+}
@@ -0,0 +1,258 @@
+use super::log_monitor::{send_event, AsyncLogMonitor, MonitorMessage, PortRequest, WriteProxy};
+use super::read_line;
+
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_BUFFER_SIZE: usize = 128;
+const BUFFER_COMPLETION_TIMEOUT: u64 = 50;
+pub const RECONNECT_DELAY: u64 = 1000;
+
+/// Connection of a stream monitor, e.g. to a TCP server or a Unix socket, with
+/// the protocol spoken over it.
+pub trait StreamConnection: Send {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    fn get_name(&self) -> String;
+    /// Where the connection goes, shown when connected
+    fn get_address(&self) -> String;
+    fn connect(&self) -> impl Future<Output = std::io::Result<Self::Stream>> + Send;
+
+    /// Prepares a new connection, e.g. negotiates the protocol options.
+    fn start_session(
+        &mut self,
+        _stream: &mut Self::Stream,
+    ) -> impl Future<Output = std::io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    fn handle_write_request(
+        &mut self,
+        stream: &mut Self::Stream,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    /// Passes the received data to the line assembler.
+    fn handle_received(
+        &mut self,
+        stream: &mut Self::Stream,
+        data: &[u8],
+        lines: &mut read_line::LineAssembler,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> impl Future<Output = std::io::Result<()>> + Send;
+}
+
+/// Reads logs from a stream, reconnecting whenever the connection is lost.
+pub struct StreamLogMonitor<C: StreamConnection> {
+    connection: C,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    recv_buffer: Vec<u8>,
+    lines: read_line::LineAssembler,
+    input_dropped_reported: bool,
+}
+
+impl<C: StreamConnection> StreamLogMonitor<C> {
+    pub fn from_connection(connection: C) -> StreamLogMonitor<C> {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        let lines = read_line::LineAssembler::new(connection.get_name());
+
+        StreamLogMonitor {
+            connection,
+            write_receiver,
+            write_sender,
+            recv_buffer: vec![0; DEFAULT_BUFFER_SIZE],
+            lines,
+            input_dropped_reported: false,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    /// Waits before the next connection attempt, dropping the input meanwhile.
+    /// Returns false if the monitor was cancelled.
+    async fn wait_for_retry(
+        &mut self,
+        cancel_token: &CancellationToken,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> bool {
+        // input doesn't hasten the next attempt
+        let retry_at =
+            tokio::time::Instant::now() + std::time::Duration::from_millis(RECONNECT_DELAY);
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return false;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if request.is_some() && !self.input_dropped_reported {
+                        self.input_dropped_reported = true;
+                        let description = "Not connected, input dropped".to_string();
+                        self.send_event(description, sender_queue);
+                    }
+                }
+
+                _ = tokio::time::sleep_until(retry_at) => return true,
+            }
+        }
+    }
+
+    /// Connects, retrying until it succeeds or the monitor is cancelled.
+    async fn connect(
+        &mut self,
+        cancel_token: &CancellationToken,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Option<C::Stream> {
+        let mut failure_reported = false;
+
+        loop {
+            let connect_result = tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return None;
+                }
+
+                result = self.connection.connect() => result
+            };
+
+            match connect_result {
+                Ok(stream) => {
+                    let description = format!("Connected to {}", self.connection.get_address());
+                    self.send_event(description, sender_queue);
+                    self.input_dropped_reported = false;
+                    return Some(stream);
+                }
+                Err(e) if !failure_reported => {
+                    failure_reported = true;
+                    self.send_event(format!("Failed to connect, retrying: {}", e), sender_queue);
+                }
+                Err(_) => {}
+            }
+
+            if !self.wait_for_retry(cancel_token, sender_queue).await {
+                return None;
+            }
+        }
+    }
+
+    /// Handles a single connection until it is closed or the monitor is cancelled.
+    /// Returns false if the monitor was cancelled.
+    async fn handle_connection(
+        &mut self,
+        mut stream: C::Stream,
+        cancel_token: &CancellationToken,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> bool {
+        if let Err(e) = self.connection.start_session(&mut stream).await {
+            let description = format!("Connection lost, reconnecting: {}", e);
+            self.send_event(description, sender_queue);
+            return true;
+        }
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return false;
+                }
+
+                request = self.write_receiver.recv() => {
+                    let request = match request {
+                        Some(r) => r,
+                        None => continue,
+                    };
+                    let result = self
+                        .connection
+                        .handle_write_request(&mut stream, request, sender_queue)
+                        .await;
+                    if let Err(e) = result {
+                        self.send_event(format!("Failed to write: {}", e), sender_queue);
+                    }
+                }
+
+                read_result = tokio::time::timeout(
+                    std::time::Duration::from_millis(BUFFER_COMPLETION_TIMEOUT),
+                    stream.read(&mut self.recv_buffer),
+                ) => {
+                    match read_result {
+                        Ok(Ok(0)) => {
+                            self.lines.flush(sender_queue);
+                            let description = "Connection closed, reconnecting".to_string();
+                            self.send_event(description, sender_queue);
+                            return true;
+                        }
+                        Ok(Ok(n)) => {
+                            let result = self
+                                .connection
+                                .handle_received(
+                                    &mut stream,
+                                    &self.recv_buffer[0..n],
+                                    &mut self.lines,
+                                    sender_queue,
+                                )
+                                .await;
+                            if let Err(e) = result {
+                                self.lines.flush(sender_queue);
+                                let description =
+                                    format!("Connection lost, reconnecting: {}", e);
+                                self.send_event(description, sender_queue);
+                                return true;
+                            }
+                        }
+                        Ok(Err(e)) => {
+                            self.lines.flush(sender_queue);
+                            let description = format!("Connection lost, reconnecting: {}", e);
+                            self.send_event(description, sender_queue);
+                            return true;
+                        }
+                        // timeout
+                        Err(_) => {
+                            self.lines.flush(sender_queue);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<C: StreamConnection> AsyncLogMonitor for StreamLogMonitor<C> {
+    fn get_common_name(&self) -> String {
+        self.connection.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        loop {
+            let stream = match self.connect(&cancel_token, &sender_queue).await {
+                Some(s) => s,
+                None => return,
+            };
+
+            if !self
+                .handle_connection(stream, &cancel_token, &sender_queue)
+                .await
+            {
+                return;
+            }
+
+            // a server may accept and close the connection right away, e.g. when busy
+            if !self.wait_for_retry(&cancel_token, &sender_queue).await {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use super::data::{PortSetting, TcpProtocol, TcpSettings};
+use super::log_monitor::{send_event, ModemLines, ModemStatus, MonitorMessage, PortRequest};
+use super::read_line;
+use super::rfc2217;
+use super::stream_monitor::{StreamConnection, StreamLogMonitor};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Reads logs from a TCP server, e.g. a serial console shared with ser2net.
+/// With RFC 2217 the remote port settings and control lines are handled as well.
+pub type TcpLogMonitor = StreamLogMonitor<TcpConnection>;
+
+impl TcpLogMonitor {
+    pub fn new(settings: TcpSettings) -> TcpLogMonitor {
+        StreamLogMonitor::from_connection(TcpConnection {
+            settings,
+            session: None,
+            port_settings: Vec::new(),
+            modem_lines: None,
+        })
+    }
+}
+
+pub struct TcpConnection {
+    settings: TcpSettings,
+    /// Telnet state of the current RFC 2217 connection
+    session: Option<rfc2217::Session>,
+    /// Settings changed during the session, restored after reconnecting
+    port_settings: Vec<PortSetting>,
+    modem_lines: Option<ModemLines>,
+}
+
+impl TcpConnection {
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_name(), description, sender_queue);
+    }
+
+    async fn handle_raw_write_request(
+        &mut self,
+        stream: &mut TcpStream,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Result<(), std::io::Error> {
+        if let PortRequest::Write(byte) = request {
+            return stream.write_all(&[byte]).await;
+        }
+
+        self.send_event(
+            request.unsupported_description("over plain TCP"),
+            sender_queue,
+        );
+        Ok(())
+    }
+
+    fn remember_port_setting(&mut self, setting: PortSetting) {
+        self.port_settings
+            .retain(|s| std::mem::discriminant(s) != std::mem::discriminant(&setting));
+        self.port_settings.push(setting);
+    }
+
+    fn report_modem_lines(
+        &mut self,
+        lines: ModemLines,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        if self.modem_lines == Some(lines) {
+            return;
+        }
+
+        self.modem_lines = Some(lines);
+        let status = ModemStatus {
+            source_name: self.get_name(),
+            lines,
+            timestamp: chrono::Local::now(),
+        };
+        sender_queue
+            .send(MonitorMessage::ModemStatus(status))
+            .unwrap();
+    }
+}
+
+impl StreamConnection for TcpConnection {
+    type Stream = TcpStream;
+
+    fn get_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_address(&self) -> String {
+        self.settings.address.clone()
+    }
+
+    async fn connect(&self) -> std::io::Result<TcpStream> {
+        TcpStream::connect(&self.settings.address).await
+    }
+
+    /// Negotiates the COM port option and restores the settings changed earlier.
+    async fn start_session(&mut self, stream: &mut TcpStream) -> Result<(), std::io::Error> {
+        if self.settings.protocol != TcpProtocol::Rfc2217 {
+            self.session = None;
+            return Ok(());
+        }
+
+        let mut session = rfc2217::Session::client();
+        let mut greeting = session.get_greeting();
+        for setting in &self.port_settings {
+            greeting.extend(session.encode_request(&PortRequest::Configure(*setting)));
+        }
+        self.session = Some(session);
+        self.modem_lines = None;
+
+        stream.write_all(&greeting).await
+    }
+
+    async fn handle_write_request(
+        &mut self,
+        stream: &mut TcpStream,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Result<(), std::io::Error> {
+        let session = match &self.session {
+            Some(s) => s,
+            None => {
+                return self
+                    .handle_raw_write_request(stream, request, sender_queue)
+                    .await
+            }
+        };
+
+        match request {
+            PortRequest::Break(duration) => {
+                let break_on = rfc2217::ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_ON);
+                let break_off = rfc2217::ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_OFF);
+                stream.write_all(&break_on.encode(false)).await?;
+                tokio::time::sleep(duration).await;
+                stream.write_all(&break_off.encode(false)).await?;
+                let description = format!("TX break for {} ms", duration.as_millis());
+                self.send_event(description, sender_queue);
+                Ok(())
+            }
+            request @ PortRequest::SendFrame(_) => {
+                let description = request.unsupported_description("over RFC 2217");
+                self.send_event(description, sender_queue);
+                Ok(())
+            }
+            request => {
+                stream.write_all(&session.encode_request(&request)).await?;
+                if let PortRequest::Configure(setting) = request {
+                    self.remember_port_setting(setting);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles the telnet commands in between the data when talking RFC 2217.
+    async fn handle_received(
+        &mut self,
+        stream: &mut TcpStream,
+        data: &[u8],
+        lines: &mut read_line::LineAssembler,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Result<(), std::io::Error> {
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => {
+                lines.push(data, sender_queue);
+                return Ok(());
+            }
+        };
+
+        let mut received = Vec::new();
+        let replies = session.receive(data, &mut received);
+
+        for item in received {
+            match item {
+                rfc2217::Received::Data(data) => {
+                    lines.push(&data, sender_queue);
+                }
+                rfc2217::Received::Command(rfc2217::ComPortCommand::NotifyModemState(state)) => {
+                    self.report_modem_lines(rfc2217::get_modem_lines(state), sender_queue);
+                }
+                rfc2217::Received::Command(command) => {
+                    if let Some(description) = command.describe() {
+                        self.send_event(description, sender_queue);
+                    }
+                }
+            }
+        }
+
+        if !replies.is_empty() {
+            stream.write_all(&replies).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::{AsyncLogMonitor, Log};
+    use crate::stream_monitor::RECONNECT_DELAY;
+    use rfc2217::{ComPortCommand, TelnetItem};
+    use tokio::io::AsyncReadExt;
+    use tokio_util::sync::CancellationToken;
+
+    async fn next_log(receiver: &mut tokio::sync::mpsc::UnboundedReceiver<MonitorMessage>) -> Log {
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let MonitorMessage::Log(log) = msg {
+                return log;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_write_and_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut monitor = TcpLogMonitor::new(TcpSettings {
+            address: address.clone(),
+            protocol: TcpProtocol::Raw,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        server.write_all(b"hello\r\n").await.unwrap();
+        let log = next_log(&mut receiver).await;
+        assert_eq!(log.source_name, format!("tcp://{}", address));
+        assert_eq!(log.message, "hello");
+
+        write_proxy.request(PortRequest::Write(b'x')).unwrap();
+        let mut received = [0u8; 1];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received[0], b'x');
+
+        drop(server);
+        let (mut server, _) = listener.accept().await.unwrap();
+        server.write_all(b"again\n").await.unwrap();
+        assert_eq!(next_log(&mut receiver).await.message, "again");
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+
+    async fn next_event(
+        receiver: &mut tokio::sync::mpsc::UnboundedReceiver<MonitorMessage>,
+    ) -> String {
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let MonitorMessage::Event(event) = msg {
+                return event.description;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_input_while_disconnected() {
+        // nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut monitor = TcpLogMonitor::new(TcpSettings {
+            address,
+            protocol: TcpProtocol::Raw,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        assert!(next_event(&mut receiver)
+            .await
+            .starts_with("Failed to connect, retrying"));
+        for byte in b"reboot\r" {
+            write_proxy.request(PortRequest::Write(*byte)).unwrap();
+        }
+        assert_eq!(
+            next_event(&mut receiver).await,
+            "Not connected, input dropped"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_delay_after_close() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut monitor = TcpLogMonitor::new(TcpSettings {
+            address,
+            protocol: TcpProtocol::Raw,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        // the server closes every connection as soon as it is accepted
+        drop(listener.accept().await.unwrap());
+        assert!(next_event(&mut receiver).await.starts_with("Connected to"));
+        assert_eq!(
+            next_event(&mut receiver).await,
+            "Connection closed, reconnecting"
+        );
+        write_proxy.request(PortRequest::Write(b'x')).unwrap();
+        assert_eq!(
+            next_event(&mut receiver).await,
+            "Not connected, input dropped"
+        );
+
+        let wait = std::time::Duration::from_millis(RECONNECT_DELAY / 2);
+        assert!(tokio::time::timeout(wait, listener.accept()).await.is_err());
+        drop(listener.accept().await.unwrap());
+        assert!(next_event(&mut receiver).await.starts_with("Connected to"));
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+
+    /// Minimal RFC 2217 server side, enough to check what the client sends.
+    struct TestServer {
+        stream: TcpStream,
+        decoder: rfc2217::TelnetDecoder,
+        items: std::collections::VecDeque<TelnetItem>,
+    }
+
+    impl TestServer {
+        async fn next_item(&mut self) -> TelnetItem {
+            while self.items.is_empty() {
+                let mut buffer = [0u8; 64];
+                let n = self.stream.read(&mut buffer).await.unwrap();
+                assert!(n > 0);
+                let mut items = Vec::new();
+                self.decoder.decode(&buffer[0..n], &mut items);
+                self.items.extend(items);
+            }
+            self.items.pop_front().unwrap()
+        }
+
+        async fn next_command(&mut self) -> ComPortCommand {
+            loop {
+                if let TelnetItem::Subnegotiation(s) = self.next_item().await {
+                    let (command, from_server) = ComPortCommand::parse(&s).unwrap();
+                    assert!(!from_server);
+                    return command;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rfc2217_control() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut monitor = TcpLogMonitor::new(TcpSettings {
+            address: address.clone(),
+            protocol: TcpProtocol::Rfc2217,
+        });
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = TestServer {
+            stream,
+            decoder: rfc2217::TelnetDecoder::new(),
+            items: std::collections::VecDeque::new(),
+        };
+        assert_eq!(
+            server.next_item().await,
+            TelnetItem::Negotiation(rfc2217::WILL, rfc2217::COM_PORT_OPTION)
+        );
+        server
+            .stream
+            .write_all(&rfc2217::encode_negotiation(
+                rfc2217::DO,
+                rfc2217::COM_PORT_OPTION,
+            ))
+            .await
+            .unwrap();
+
+        let mut output = b"boot \xff\xff ok\r\n".to_vec();
+        output.extend(ComPortCommand::NotifyModemState(0x30).encode(true));
+        server.stream.write_all(&output).await.unwrap();
+        let log = next_log(&mut receiver).await;
+        assert_eq!(log.source_name, format!("rfc2217://{}", address));
+        assert_eq!(log.message, "boot \u{fffd} ok");
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match msg {
+            MonitorMessage::ModemStatus(status) => {
+                assert!(status.lines.cts && status.lines.dsr && !status.lines.dcd);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        write_proxy
+            .request(PortRequest::Configure(PortSetting::BaudRate(9600)))
+            .unwrap();
+        assert_eq!(
+            server.next_command().await,
+            ComPortCommand::SetBaudRate(9600)
+        );
+        server
+            .stream
+            .write_all(&ComPortCommand::SetBaudRate(9600).encode(true))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_event(&mut receiver).await,
+            "Port settings changed: baud 9600"
+        );
+
+        write_proxy.request(PortRequest::SetDtr(true)).unwrap();
+        write_proxy
+            .request(PortRequest::Break(std::time::Duration::from_millis(1)))
+            .unwrap();
+        assert_eq!(
+            server.next_command().await,
+            ComPortCommand::SetControl(rfc2217::CONTROL_DTR_ON)
+        );
+        assert_eq!(
+            server.next_command().await,
+            ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_ON)
+        );
+        assert_eq!(
+            server.next_command().await,
+            ComPortCommand::SetControl(rfc2217::CONTROL_BREAK_OFF)
+        );
+
+        // the baud rate is restored after reconnecting
+        drop(server);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = TestServer {
+            stream,
+            decoder: rfc2217::TelnetDecoder::new(),
+            items: std::collections::VecDeque::new(),
+        };
+        assert_eq!(
+            server.next_command().await,
+            ComPortCommand::SetBaudRate(9600)
+        );
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+}
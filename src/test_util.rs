@@ -0,0 +1,19 @@
+use super::log_monitor::MonitorMessage;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Returns a path in the temporary directory that is unique to the test run,
+/// with any file left there by an earlier run removed.
+pub fn get_temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("drfish-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().to_string()
+}
+
+/// Waits for the next message, failing the test if none arrives in time.
+pub async fn next_message(receiver: &mut UnboundedReceiver<MonitorMessage>) -> MonitorMessage {
+    tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
@@ -0,0 +1,232 @@
+use super::command::Command;
+use super::config::TriggerConfig;
+use super::log_monitor::Log;
+
+use std::time::{Duration, Instant};
+
+/// Something done when a trigger fires, in the order listed here.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TriggerAction {
+    /// Ring the terminal bell and mark the log
+    Notify,
+    /// Write the text to the target
+    Send(String),
+    /// Run the drfish commands on the target
+    Commands(Vec<Command>),
+    /// Run the shell command
+    Run(String),
+    /// End the session
+    Stop,
+    /// End the session and exit with the code
+    Exit(i32),
+}
+
+/// Trigger fired by a line, with the actions to run for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Firing {
+    pub name: String,
+    /// Writer receiving the text and commands, None for the source of the line
+    pub target: Option<String>,
+    pub actions: Vec<TriggerAction>,
+}
+
+struct Trigger {
+    name: String,
+    pattern: regex::Regex,
+    sources: Vec<String>,
+    cooldown: Duration,
+    target: Option<String>,
+    actions: Vec<TriggerAction>,
+    last_fired: Option<Instant>,
+}
+
+impl Trigger {
+    fn new(config: &TriggerConfig) -> Result<Trigger, String> {
+        if config.pattern.is_empty() {
+            return Err("Trigger without a pattern".to_string());
+        }
+        let name = config.name.clone().unwrap_or(config.pattern.clone());
+        let pattern = regex::Regex::new(&config.pattern)
+            .map_err(|e| format!("Invalid pattern of trigger {}: {}", name, e))?;
+
+        let mut actions = Vec::new();
+        if config.notify {
+            actions.push(TriggerAction::Notify);
+        }
+        if let Some(text) = &config.send {
+            actions.push(TriggerAction::Send(text.clone()));
+        }
+        if !config.commands.is_empty() {
+            let commands = config
+                .commands
+                .iter()
+                .map(|c| Command::parse(c))
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(|e| format!("Invalid command of trigger {}: {}", name, e))?;
+            actions.push(TriggerAction::Commands(commands));
+        }
+        if let Some(command) = &config.run {
+            actions.push(TriggerAction::Run(command.clone()));
+        }
+        match config.exit_code {
+            Some(code) => actions.push(TriggerAction::Exit(code)),
+            None if config.stop => actions.push(TriggerAction::Stop),
+            None => {}
+        }
+        if actions.is_empty() {
+            return Err(format!("Trigger {} has no actions", name));
+        }
+
+        Ok(Trigger {
+            name,
+            pattern,
+            sources: config.sources.clone(),
+            cooldown: Duration::from_millis(config.cooldown),
+            target: config.target.clone(),
+            actions,
+            last_fired: None,
+        })
+    }
+
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        match self.last_fired {
+            Some(last_fired) => now.duration_since(last_fired) < self.cooldown,
+            None => false,
+        }
+    }
+}
+
+/// Configured rules run against every received line.
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+}
+
+impl Triggers {
+    pub fn new(configs: &[TriggerConfig]) -> Result<Triggers, String> {
+        let triggers = configs
+            .iter()
+            .map(Trigger::new)
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Triggers { triggers })
+    }
+
+    /// Returns the triggers fired by the line. A trigger fired less than its
+    /// cooldown ago is skipped.
+    pub fn check(&mut self, log: &Log, now: Instant) -> Vec<Firing> {
+        let mut firings = Vec::new();
+
+        for trigger in &mut self.triggers {
+            if !trigger.sources.is_empty() && !trigger.sources.contains(&log.source_name) {
+                continue;
+            }
+            if trigger.is_cooling_down(now) || !trigger.pattern.is_match(&log.message) {
+                continue;
+            }
+
+            trigger.last_fired = Some(now);
+            firings.push(Firing {
+                name: trigger.name.clone(),
+                target: trigger.target.clone(),
+                actions: trigger.actions.clone(),
+            });
+        }
+
+        firings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_log(source_name: &str, message: &str) -> Log {
+        Log {
+            source_name: source_name.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Local::now(),
+        }
+    }
+
+    #[test]
+    fn test_actions() {
+        let config = TriggerConfig {
+            pattern: "Guru Meditation".to_string(),
+            notify: true,
+            send: Some("\r".to_string()),
+            commands: vec!["dtr pulse".to_string()],
+            run: Some("./collect.sh".to_string()),
+            stop: true,
+            exit_code: Some(3),
+            ..Default::default()
+        };
+        let mut triggers = Triggers::new(&[config]).unwrap();
+
+        let firings = triggers.check(&get_log("a", "Guru Meditation Error"), Instant::now());
+        assert_eq!(
+            firings,
+            [Firing {
+                name: "Guru Meditation".to_string(),
+                target: None,
+                actions: vec![
+                    TriggerAction::Notify,
+                    TriggerAction::Send("\r".to_string()),
+                    TriggerAction::Commands(vec![Command::parse("dtr pulse").unwrap()]),
+                    TriggerAction::Run("./collect.sh".to_string()),
+                    TriggerAction::Exit(3),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sources_and_cooldown() {
+        let config = TriggerConfig {
+            name: Some("panic".to_string()),
+            pattern: r"(?i)panic|abort\(\)".to_string(),
+            sources: vec!["a".to_string()],
+            cooldown: 1000,
+            stop: true,
+            ..Default::default()
+        };
+        let mut triggers = Triggers::new(&[config]).unwrap();
+        let start = Instant::now();
+
+        assert!(triggers.check(&get_log("b", "PANIC"), start).is_empty());
+        assert!(triggers.check(&get_log("a", "ready"), start).is_empty());
+        assert_eq!(triggers.check(&get_log("a", "PANIC"), start).len(), 1);
+        let later = start + Duration::from_millis(999);
+        assert!(triggers.check(&get_log("a", "abort()"), later).is_empty());
+        let later = start + Duration::from_millis(1000);
+        assert_eq!(
+            triggers.check(&get_log("a", "abort()"), later)[0].actions,
+            [TriggerAction::Stop]
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = [
+            TriggerConfig {
+                notify: true,
+                ..Default::default()
+            },
+            TriggerConfig {
+                pattern: "(".to_string(),
+                notify: true,
+                ..Default::default()
+            },
+            TriggerConfig {
+                pattern: "boot".to_string(),
+                ..Default::default()
+            },
+            TriggerConfig {
+                pattern: "boot".to_string(),
+                commands: vec!["reboot".to_string()],
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(Triggers::new(&[config]).is_err());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use super::data::UdpSettings;
+use super::log_monitor::{
+    send_event, AsyncLogMonitor, Log, MonitorMessage, PortRequest, WriteProxy,
+};
+
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+
+/// Receives logs sent as UDP datagrams, e.g. by devices without a serial console.
+/// Every line of a datagram is logged under the address of its sender. Input is
+/// sent back, a line per datagram, to whoever sent the last datagram.
+pub struct UdpLogMonitor {
+    settings: UdpSettings,
+    write_receiver: UnboundedReceiver<PortRequest>,
+    write_sender: UnboundedSender<PortRequest>,
+    recv_buffer: Vec<u8>,
+    reply_buffer: Vec<u8>,
+    last_sender: Option<SocketAddr>,
+}
+
+impl UdpLogMonitor {
+    pub fn new(settings: UdpSettings) -> UdpLogMonitor {
+        let (write_sender, write_receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+
+        UdpLogMonitor {
+            settings,
+            write_receiver,
+            write_sender,
+            recv_buffer: vec![0; MAX_DATAGRAM_SIZE],
+            reply_buffer: Vec::new(),
+            last_sender: None,
+        }
+    }
+
+    fn send_event(&self, description: String, sender_queue: &UnboundedSender<MonitorMessage>) {
+        send_event(self.get_common_name(), description, sender_queue);
+    }
+
+    fn handle_datagram(
+        &mut self,
+        size: usize,
+        sender: SocketAddr,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        self.last_sender = Some(sender);
+
+        let timestamp = chrono::Local::now();
+        let text = String::from_utf8_lossy(&self.recv_buffer[0..size]);
+        for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let log = Log {
+                source_name: sender.to_string(),
+                message: line.to_string(),
+                timestamp,
+            };
+            sender_queue.send(MonitorMessage::Log(log)).unwrap();
+        }
+    }
+
+    async fn handle_write_request(
+        &mut self,
+        socket: &UdpSocket,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) {
+        let byte = match request {
+            PortRequest::Write(byte) => byte,
+            request => {
+                self.send_event(request.unsupported_description("over UDP"), sender_queue);
+                return;
+            }
+        };
+
+        self.reply_buffer.push(byte);
+        if byte != b'\r' && byte != b'\n' {
+            return;
+        }
+
+        let reply = std::mem::take(&mut self.reply_buffer);
+        let description = match self.last_sender {
+            Some(address) => match socket.send_to(&reply, address).await {
+                Ok(_) => return,
+                Err(e) => format!("Failed to reply to {}: {}", address, e),
+            },
+            None => "Nothing received yet, input dropped".to_string(),
+        };
+        self.send_event(description, sender_queue);
+    }
+}
+
+impl AsyncLogMonitor for UdpLogMonitor {
+    fn get_common_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_write_proxy(&self) -> WriteProxy {
+        WriteProxy::new(self.write_sender.clone())
+    }
+
+    async fn monitor(
+        &mut self,
+        cancel_token: CancellationToken,
+        sender_queue: UnboundedSender<MonitorMessage>,
+    ) {
+        print!("Starting {} monitor\r\n", self.get_common_name());
+
+        let socket = match UdpSocket::bind(&self.settings.address).await {
+            Ok(s) => s,
+            Err(e) => {
+                self.send_event(format!("Failed to listen: {}", e), &sender_queue);
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+
+                request = self.write_receiver.recv() => {
+                    if let Some(request) = request {
+                        self.handle_write_request(&socket, request, &sender_queue).await;
+                    }
+                }
+
+                result = socket.recv_from(&mut self.recv_buffer) => match result {
+                    Ok((n, sender)) => self.handle_datagram(n, sender, &sender_queue),
+                    // e.g. ICMP port unreachable after replying to a sender that went away
+                    Err(e) => self.send_event(format!("Failed to receive: {}", e), &sender_queue),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::next_message;
+
+    #[tokio::test]
+    async fn test_receive_and_reply() {
+        // find a free port for the monitor
+        let address = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let mut monitor = UdpLogMonitor::new(UdpSettings {
+            address: address.clone(),
+        });
+        assert_eq!(monitor.get_common_name(), format!("udp://{}", address));
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        // input sent before anything was received has nowhere to go
+        write_proxy.request(PortRequest::Write(b'\r')).unwrap();
+        match next_message(&mut receiver).await {
+            MonitorMessage::Event(event) => {
+                assert_eq!(event.description, "Nothing received yet, input dropped")
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let device_address = device.local_addr().unwrap().to_string();
+        device
+            .send_to(b"temp=21\r\n\r\nhum=40", &address)
+            .await
+            .unwrap();
+        for expected in ["temp=21", "hum=40"] {
+            match next_message(&mut receiver).await {
+                MonitorMessage::Log(log) => {
+                    assert_eq!(log.source_name, device_address);
+                    assert_eq!(log.message, expected);
+                }
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+        }
+
+        for byte in b"ping\r" {
+            write_proxy.request(PortRequest::Write(*byte)).unwrap();
+        }
+        let mut reply = [0u8; 16];
+        let (n, _) = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            device.recv_from(&mut reply),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(&reply[0..n], b"ping\r");
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+    }
+}
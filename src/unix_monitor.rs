@@ -0,0 +1,114 @@
+use super::data::UnixSettings;
+use super::log_monitor::{send_event, MonitorMessage, PortRequest};
+use super::read_line;
+use super::stream_monitor::{StreamConnection, StreamLogMonitor};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Reads logs from a Unix domain stream socket, e.g. the UART of a simulator.
+/// Input is written to the socket as it is typed. The connection is retried,
+/// e.g. while the simulator is not started yet.
+pub type UnixLogMonitor = StreamLogMonitor<UnixConnection>;
+
+impl UnixLogMonitor {
+    pub fn new(settings: UnixSettings) -> UnixLogMonitor {
+        StreamLogMonitor::from_connection(UnixConnection { settings })
+    }
+}
+
+pub struct UnixConnection {
+    settings: UnixSettings,
+}
+
+impl StreamConnection for UnixConnection {
+    type Stream = UnixStream;
+
+    fn get_name(&self) -> String {
+        self.settings.get_name()
+    }
+
+    fn get_address(&self) -> String {
+        self.settings.path.clone()
+    }
+
+    async fn connect(&self) -> std::io::Result<UnixStream> {
+        UnixStream::connect(&self.settings.path).await
+    }
+
+    async fn handle_write_request(
+        &mut self,
+        stream: &mut UnixStream,
+        request: PortRequest,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Result<(), std::io::Error> {
+        if let PortRequest::Write(byte) = request {
+            return stream.write_all(&[byte]).await;
+        }
+
+        let description = request.unsupported_description("over Unix sockets");
+        send_event(self.get_name(), description, sender_queue);
+        Ok(())
+    }
+
+    async fn handle_received(
+        &mut self,
+        _stream: &mut UnixStream,
+        data: &[u8],
+        lines: &mut read_line::LineAssembler,
+        sender_queue: &UnboundedSender<MonitorMessage>,
+    ) -> Result<(), std::io::Error> {
+        lines.push(data, sender_queue);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_monitor::AsyncLogMonitor;
+    use crate::test_util::{get_temp_path, next_message};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixListener;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_read_and_write() {
+        let path = get_temp_path("uart.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut monitor = UnixLogMonitor::new(UnixSettings { path: path.clone() });
+        assert_eq!(monitor.get_common_name(), format!("unix:{}", path));
+        let write_proxy = monitor.get_write_proxy();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<MonitorMessage>();
+        let cancel_token = CancellationToken::new();
+        let monitor_cancel_token = cancel_token.clone();
+        let handle = tokio::spawn(async move {
+            monitor.monitor(monitor_cancel_token, sender).await;
+        });
+
+        let (mut simulator, _) = listener.accept().await.unwrap();
+        match next_message(&mut receiver).await {
+            MonitorMessage::Event(event) => assert!(event.description.starts_with("Connected")),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        simulator.write_all(b"boot ok\r\n").await.unwrap();
+        match next_message(&mut receiver).await {
+            MonitorMessage::Log(log) => assert_eq!(log.message, "boot ok"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        for byte in b"reset\r" {
+            write_proxy.request(PortRequest::Write(*byte)).unwrap();
+        }
+        let mut input = [0u8; 6];
+        simulator.read_exact(&mut input).await.unwrap();
+        assert_eq!(&input, b"reset\r");
+
+        cancel_token.cancel();
+        handle.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,84 +1,395 @@
-use super::serial_monitor::SerialLogMonitorWriteProxy;
-use indexmap;
+use super::log_monitor::{PortRequest, WriteProxy};
+
+/// Set of ports the user input is currently delivered to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteTarget {
+    Single(usize),
+    All,
+    Group(String),
+}
+
+/// Input that could not be handed over to one of the target ports.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryFailure {
+    pub port_name: String,
+    pub error: String,
+}
 
 pub struct Writer {
-    write_proxies: indexmap::IndexMap<String, SerialLogMonitorWriteProxy>,
-    current_writer_index: Option<u32>,
+    write_proxies: indexmap::IndexMap<String, WriteProxy>,
+    groups: indexmap::IndexMap<String, Vec<String>>,
+    aliases: indexmap::IndexMap<String, String>,
+    target: Option<WriteTarget>,
 }
 
 impl Writer {
     pub fn new() -> Self {
         Writer {
             write_proxies: indexmap::IndexMap::new(),
-            current_writer_index: None,
+            groups: indexmap::IndexMap::new(),
+            aliases: indexmap::IndexMap::new(),
+            target: None,
         }
     }
 
-    pub fn add_write_proxy(
-        &mut self,
-        common_name: String,
-        write_proxy: SerialLogMonitorWriteProxy,
-    ) {
-        if self.current_writer_index.is_none() {
-            self.current_writer_index = Some(0);
+    pub fn add_write_proxy(&mut self, common_name: String, write_proxy: WriteProxy) {
+        if self.target.is_none() {
+            self.target = Some(WriteTarget::Single(0));
         }
         self.write_proxies.insert(common_name, write_proxy);
     }
 
+    pub fn add_group(&mut self, name: String, members: Vec<String>) -> Result<(), String> {
+        if members.is_empty() {
+            return Err(format!("Group {} has no ports", name));
+        }
+
+        for member in &members {
+            if !self.write_proxies.contains_key(member) {
+                return Err(format!("Unknown port {} in group {}", member, name));
+            }
+        }
+
+        self.groups.insert(name, members);
+        Ok(())
+    }
+
+    pub fn add_alias(&mut self, alias: String, port_name: String) -> Result<(), String> {
+        if !self.write_proxies.contains_key(&port_name) {
+            return Err(format!("Unknown port {} for alias {}", port_name, alias));
+        }
+
+        self.aliases.insert(alias, port_name);
+        Ok(())
+    }
+
+    /// Selects a single writer by its position, starting from 0.
+    pub fn select_writer_by_index(&mut self, index: usize) -> Result<String, String> {
+        if index >= self.write_proxies.len() {
+            return Err(format!("No writer with index {}", index + 1));
+        }
+
+        self.target = Some(WriteTarget::Single(index));
+        Ok(self.get_current_writer_name().unwrap())
+    }
+
+    /// Selects a writer by its port name or alias, a group by its name,
+    /// or all writers with "all".
+    pub fn select_writer_by_name(&mut self, name: &str) -> Result<String, String> {
+        self.target = Some(self.find_target(name)?);
+        Ok(self.get_current_writer_name().unwrap())
+    }
+
+    /// Write proxies of the writer with the given name, without selecting it.
+    pub fn get_write_proxies_by_name(
+        &self,
+        name: &str,
+    ) -> Result<Vec<(String, WriteProxy)>, String> {
+        let target = Some(self.find_target(name)?);
+        Ok(self
+            .get_proxies(&target)
+            .into_iter()
+            .map(|(name, write_proxy)| (name.clone(), write_proxy.clone()))
+            .collect())
+    }
+
+    fn find_target(&self, name: &str) -> Result<WriteTarget, String> {
+        let port_name = match self.aliases.get(name) {
+            Some(port_name) => port_name.as_str(),
+            None => name,
+        };
+
+        if let Some(index) = self.write_proxies.get_index_of(port_name) {
+            Ok(WriteTarget::Single(index))
+        } else if self.groups.contains_key(name) {
+            Ok(WriteTarget::Group(name.to_string()))
+        } else if name == "all" && !self.write_proxies.is_empty() {
+            Ok(WriteTarget::All)
+        } else {
+            Err(format!("No writer named {}", name))
+        }
+    }
+
+    /// Lists the writers with their indices (starting from 1) and aliases.
+    pub fn get_writer_list(&self) -> Vec<String> {
+        self.write_proxies
+            .keys()
+            .enumerate()
+            .map(|(index, name)| {
+                let aliases: Vec<&str> = self
+                    .aliases
+                    .iter()
+                    .filter(|(_, port_name)| *port_name == name)
+                    .map(|(alias, _)| alias.as_str())
+                    .collect();
+                if aliases.is_empty() {
+                    format!("{}: {}", index + 1, name)
+                } else {
+                    format!("{}: {} ({})", index + 1, name, aliases.join(", "))
+                }
+            })
+            .collect()
+    }
+
     pub fn get_current_writer_name(&self) -> Option<String> {
-        match self.current_writer_index {
-            Some(index) => {
-                let (name, _) = self.write_proxies.get_index(index as usize).unwrap();
+        match &self.target {
+            Some(WriteTarget::Single(index)) => {
+                let (name, _) = self.write_proxies.get_index(*index).unwrap();
                 Some(name.clone())
             }
+            Some(WriteTarget::All) => Some("all ports".to_string()),
+            Some(WriteTarget::Group(name)) => Some(format!("group {}", name)),
             None => None,
         }
     }
 
+    /// Names of the ports the input is currently delivered to.
+    pub fn get_target_port_names(&self) -> Vec<String> {
+        self.get_target_proxies()
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Cycles through single ports, then all ports, then the configured groups.
     pub fn switch_to_next_writer(&mut self) -> Option<String> {
-        if self.write_proxies.len() <= 1 {
+        let targets = self.get_available_targets();
+        if targets.len() <= 1 {
             return None;
         }
 
-        self.current_writer_index = match self.current_writer_index {
-            Some(index) => {
-                if index + 1 < self.write_proxies.len() as u32 {
-                    Some(index + 1)
-                } else {
-                    Some(0)
-                }
-            }
-            None => None,
+        let current_position = self
+            .target
+            .as_ref()
+            .and_then(|target| targets.iter().position(|t| t == target));
+        self.target = match current_position {
+            Some(position) => Some(targets[(position + 1) % targets.len()].clone()),
+            None => Some(targets[0].clone()),
         };
 
-        return self.get_current_writer_name();
+        self.get_current_writer_name()
     }
 
-    pub fn write_key(&self, key: termion::event::Key) {
-        let current_writer = match self.get_current_writer() {
-            Some(writer) => writer,
-            None => return,
+    pub fn write_key(&self, key: termion::event::Key) -> Vec<DeliveryFailure> {
+        let data = match key {
+            termion::event::Key::Char(c) => c as u8,
+            termion::event::Key::Ctrl(c) => (c.to_uppercase().next().unwrap() as u8) - 64,
+            _ => return Vec::new(),
         };
 
-        match key {
-            termion::event::Key::Char(c) => {
-                current_writer.send(c as u8);
-            }
-            termion::event::Key::Ctrl(c) => {
-                let ascii = (c.to_uppercase().next().unwrap() as u8) - 64;
-                current_writer.send(ascii);
+        self.request(PortRequest::Write(data))
+    }
+
+    /// Sends the request to every port of the current target.
+    pub fn request(&self, request: PortRequest) -> Vec<DeliveryFailure> {
+        let mut failures = Vec::new();
+        for (name, write_proxy) in self.get_target_proxies() {
+            if let Err(e) = write_proxy.request(request.clone()) {
+                failures.push(DeliveryFailure {
+                    port_name: name.clone(),
+                    error: e,
+                });
             }
-            _ => {}
         }
+
+        failures
+    }
+
+    /// Write proxies of the current target, for requests sent from another task.
+    pub fn get_target_write_proxies(&self) -> Vec<(String, WriteProxy)> {
+        self.get_target_proxies()
+            .into_iter()
+            .map(|(name, write_proxy)| (name.clone(), write_proxy.clone()))
+            .collect()
     }
 
-    fn get_current_writer(&self) -> Option<&SerialLogMonitorWriteProxy> {
-        match self.current_writer_index {
-            Some(index) => {
-                let (_, write_proxy) = self.write_proxies.get_index(index as usize).unwrap();
-                Some(write_proxy)
+    fn get_available_targets(&self) -> Vec<WriteTarget> {
+        let mut targets: Vec<WriteTarget> = (0..self.write_proxies.len())
+            .map(WriteTarget::Single)
+            .collect();
+
+        if self.write_proxies.len() > 1 {
+            targets.push(WriteTarget::All);
+        }
+
+        for name in self.groups.keys() {
+            targets.push(WriteTarget::Group(name.clone()));
+        }
+
+        targets
+    }
+
+    fn get_target_proxies(&self) -> Vec<(&String, &WriteProxy)> {
+        self.get_proxies(&self.target)
+    }
+
+    fn get_proxies(&self, target: &Option<WriteTarget>) -> Vec<(&String, &WriteProxy)> {
+        match target {
+            Some(WriteTarget::Single(index)) => {
+                vec![self.write_proxies.get_index(*index).unwrap()]
             }
-            None => None,
+            Some(WriteTarget::All) => self.write_proxies.iter().collect(),
+            Some(WriteTarget::Group(name)) => self.groups[name]
+                .iter()
+                .filter_map(|member| self.write_proxies.get_key_value(member))
+                .collect(),
+            None => Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_port(
+        writer: &mut Writer,
+        name: &str,
+    ) -> tokio::sync::mpsc::UnboundedReceiver<PortRequest> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel::<PortRequest>();
+        writer.add_write_proxy(name.to_string(), WriteProxy::new(sender));
+        receiver
+    }
+
+    #[test]
+    fn test_switch_cycles_through_ports_all_and_groups() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        let _b = add_port(&mut writer, "b");
+        writer
+            .add_group("rack".to_string(), vec!["a".to_string()])
+            .unwrap();
+
+        assert_eq!(writer.get_current_writer_name().unwrap(), "a");
+        assert_eq!(writer.switch_to_next_writer().unwrap(), "b");
+        assert_eq!(writer.switch_to_next_writer().unwrap(), "all ports");
+        assert_eq!(writer.switch_to_next_writer().unwrap(), "group rack");
+        assert_eq!(writer.switch_to_next_writer().unwrap(), "a");
+    }
+
+    #[test]
+    fn test_switch_single_port() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        assert!(writer.switch_to_next_writer().is_none());
+    }
+
+    #[test]
+    fn test_broadcast_delivers_to_all_ports() {
+        let mut writer = Writer::new();
+        let mut a = add_port(&mut writer, "a");
+        let mut b = add_port(&mut writer, "b");
+        writer.switch_to_next_writer();
+        writer.switch_to_next_writer();
+
+        let failures = writer.write_key(termion::event::Key::Char('x'));
+        assert!(failures.is_empty());
+        assert_eq!(a.try_recv().unwrap(), PortRequest::Write(b'x'));
+        assert_eq!(b.try_recv().unwrap(), PortRequest::Write(b'x'));
+    }
+
+    #[test]
+    fn test_group_delivers_to_members_only() {
+        let mut writer = Writer::new();
+        let mut a = add_port(&mut writer, "a");
+        let mut b = add_port(&mut writer, "b");
+        let mut c = add_port(&mut writer, "c");
+        writer
+            .add_group("rack".to_string(), vec!["a".to_string(), "c".to_string()])
+            .unwrap();
+        while writer.get_current_writer_name().unwrap() != "group rack" {
+            writer.switch_to_next_writer();
+        }
+
+        assert_eq!(writer.get_target_port_names(), vec!["a", "c"]);
+        writer.write_key(termion::event::Key::Ctrl('c'));
+        assert_eq!(a.try_recv().unwrap(), PortRequest::Write(3));
+        assert!(b.try_recv().is_err());
+        assert_eq!(c.try_recv().unwrap(), PortRequest::Write(3));
+    }
+
+    #[test]
+    fn test_delivery_failure_is_reported_per_port() {
+        let mut writer = Writer::new();
+        let a = add_port(&mut writer, "a");
+        let _b = add_port(&mut writer, "b");
+        writer.switch_to_next_writer();
+        writer.switch_to_next_writer();
+        drop(a);
+
+        let failures = writer.write_key(termion::event::Key::Char('x'));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].port_name, "a");
+    }
+
+    #[test]
+    fn test_select_writer_by_index() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        let mut b = add_port(&mut writer, "b");
+
+        assert_eq!(writer.select_writer_by_index(1).unwrap(), "b");
+        writer.write_key(termion::event::Key::Char('x'));
+        assert_eq!(b.try_recv().unwrap(), PortRequest::Write(b'x'));
+        assert!(writer.select_writer_by_index(2).is_err());
+        assert_eq!(writer.get_current_writer_name().unwrap(), "b");
+    }
+
+    #[test]
+    fn test_select_writer_by_name() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        let _b = add_port(&mut writer, "b");
+        writer
+            .add_alias("esp".to_string(), "b".to_string())
+            .unwrap();
+        writer
+            .add_group("rack".to_string(), vec!["a".to_string()])
+            .unwrap();
+
+        assert_eq!(writer.select_writer_by_name("esp").unwrap(), "b");
+        assert_eq!(writer.select_writer_by_name("a").unwrap(), "a");
+        assert_eq!(writer.select_writer_by_name("rack").unwrap(), "group rack");
+        assert_eq!(writer.select_writer_by_name("all").unwrap(), "all ports");
+        assert!(writer.select_writer_by_name("nope").is_err());
+        assert_eq!(writer.get_writer_list(), vec!["1: a", "2: b (esp)"]);
+    }
+
+    #[test]
+    fn test_get_write_proxies_by_name() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        let _b = add_port(&mut writer, "b");
+        writer
+            .add_alias("esp".to_string(), "b".to_string())
+            .unwrap();
+
+        let names = |name| -> Vec<String> {
+            let proxies = writer.get_write_proxies_by_name(name).unwrap();
+            proxies.into_iter().map(|(name, _)| name).collect()
+        };
+        assert_eq!(names("esp"), ["b"]);
+        assert_eq!(names("all"), ["a", "b"]);
+        assert!(writer.get_write_proxies_by_name("nope").is_err());
+        // the current target is kept
+        assert_eq!(writer.get_current_writer_name().unwrap(), "a");
+    }
+
+    #[test]
+    fn test_add_alias_unknown_port() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        assert!(writer
+            .add_alias("esp".to_string(), "b".to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_group_unknown_port() {
+        let mut writer = Writer::new();
+        let _a = add_port(&mut writer, "a");
+        assert!(writer
+            .add_group("rack".to_string(), vec!["b".to_string()])
+            .is_err());
+    }
+}